@@ -0,0 +1,46 @@
+// Measures what the MessagePack variants are for: data that arrives as bytes and leaves as bytes,
+// e.g. from a socket or a cache. Without them, the bytes have to become JS objects before
+// setGlobal and be serialized again after getGlobal, timed here with JSON as the wire format. The
+// packed variants take and return the bytes directly.
+//
+// lua-js doesn't ship a MessagePack encoder or decoder. If the data starts out as JS objects, the
+// cost of packing it with a JS library comes on top of the packed numbers, and isn't measured here.
+//
+//     npm run bench
+const { createLuaState } = require("../lib");
+
+const ITERATIONS = 50;
+const ELEMENTS = 10000;
+
+function time(label, fn) {
+    fn();
+    const start = process.hrtime.bigint();
+    for (let i = 0; i < ITERATIONS; i++) {
+        fn();
+    }
+    const elapsed = Number(process.hrtime.bigint() - start) / 1e6 / ITERATIONS;
+    console.log(`  ${label.padEnd(32)} ${elapsed.toFixed(2)} ms`);
+    return elapsed;
+}
+
+const payload = Array.from({ length: ELEMENTS }, (_, i) => ({
+    id: i,
+    label: `item ${i}`,
+    price: i * 1.25,
+    tags: ["a", "b"],
+}));
+const json = Buffer.from(JSON.stringify(payload));
+
+for (const version of ["lua54", "lua53", "lua52", "lua51", "luajit"]) {
+    const state = createLuaState({ version });
+    state.setGlobal("payload", payload);
+    const packed = state.getGlobalPacked("payload");
+
+    console.log(`${version} (${ELEMENTS} elements, ${json.length} bytes as JSON, ${packed.length} packed)`);
+    const set = time("JSON.parse + setGlobal", () => state.setGlobal("payload", JSON.parse(json.toString())));
+    const setPacked = time("setGlobalPacked", () => state.setGlobalPacked("payload", packed));
+    const get = time("getGlobal + JSON.stringify", () => Buffer.from(JSON.stringify(state.getGlobal("payload"))));
+    const getPacked = time("getGlobalPacked", () => state.getGlobalPacked("payload"));
+    console.log(`  set ${(set / setPacked).toFixed(1)}x faster, get ${(get / getPacked).toFixed(1)}x faster\n`);
+    state.close();
+}
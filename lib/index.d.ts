@@ -70,26 +70,448 @@ interface LuaJit {
 
 type Values<K> = (K[keyof K])[]
 
-type LuaStateOptions =
+/**
+ * Controls which properties of JS objects are converted to Lua tables. By default only own,
+ * enumerable, string-keyed properties are converted.
+ *
+ * - `inherited` includes enumerable properties from the prototype chain, like `for...in`.
+ * - `getters` includes accessor properties from the prototype chain, e.g. class getters.
+ * - `nonEnumerable` includes own non-enumerable properties.
+ * - `symbols` decides what happens to symbol keys and values. `"skip"` (the default) drops
+ *   them: properties with a symbol key or value are left out, and a symbol on its own becomes
+ *   `nil`. `"lightuserdata"` passes symbols to Lua as light userdata, which convert back to the
+ *   same symbol.
+ *
+ * Functions found through these options (e.g. class methods) are skipped. `Object.prototype`
+ * and `Array.prototype` are never walked.
+ */
+export interface ConversionOptions {
+    inherited?: boolean;
+    getters?: boolean;
+    nonEnumerable?: boolean;
+    symbols?: "skip" | "lightuserdata";
+}
+
+/**
+ * Restricts the loaded libraries below the granularity of library flags. Applied after the
+ * libraries are opened, and again on `reset()`.
+ *
+ * - `profile` starts from a named profile:
+ *   - `"none"` changes nothing.
+ *   - `"standard"` keeps only `os.clock`, `os.date`, `os.difftime` and `os.time`, and removes
+ *     `io`, `dofile` and `loadfile`.
+ *   - `"strict"` additionally removes `string.rep`, `collectgarbage`, `package`, `require`,
+ *     `debug`, and `load`, `loadstring`, `rawset`, `rawget`, `rawequal`, `setfenv`, `getfenv`.
+ * - `allow` keeps only the listed functions of a library, e.g. `{ os: ["time", "clock"] }`.
+ *   It replaces the profile's list for that library.
+ * - `deny` removes the listed functions from a library, e.g. `{ string: ["rep"] }`.
+ * - `remove` removes globals entirely, e.g. `["load", "dofile"]`.
+ *
+ * A profile name on its own is shorthand for `{ profile }`.
+ */
+export type SandboxOptions = SandboxProfile | {
+    profile?: SandboxProfile;
+    allow?: { [library: string]: string[] };
+    deny?: { [library: string]: string[] };
+    remove?: string[];
+}
+
+type SandboxProfile = "none" | "standard" | "strict";
+
+/**
+ * A virtual filesystem that `doFileSync`, `dofile`, `loadfile`, `io.open`, `io.lines`, `require`,
+ * `os.remove` and `os.rename` go through instead of the real disk. Exactly one backend is used:
+ *
+ * - `files` keeps files in memory, keyed by path. Writes from Lua update the map held by the
+ *   state, not the object passed in.
+ * - `root` uses a directory on disk. Paths are resolved relative to it and can't escape it,
+ *   neither with `..` nor through symlinks.
+ * - `read` (and optionally `write`) are called with normalized paths. `read` returns the file
+ *   contents, or `undefined` if it doesn't exist. Without `write` the filesystem is read-only.
+ *   Removing and renaming files isn't supported.
+ *
+ * With `readOnly`, opening files for writing fails. `require` searches `?.lua` and `?/init.lua`,
+ * C modules can't be loaded, and `io.popen`/`io.tmpfile` are removed. Files opened for writing
+ * are saved on `flush()`, `close()`, or when they're garbage collected, and can't grow beyond
+ * 256 MiB. Other `os` functions, like `os.execute`, aren't affected; use a sandbox to remove them.
+ */
+export interface FileSystemOptions {
+    files?: { [path: string]: string };
+    root?: string;
+    read?: (path: string) => string | undefined;
+    write?: (path: string, contents: string) => void;
+    readOnly?: boolean;
+}
+
+type LuaStateOptions = (
     | { version: "lua51"; libraries?: Values<Lua51Libs>; }
     | { version: "lua52"; libraries?: Values<Lua52Libs>; }
     | { version: "lua53"; libraries?: Values<Lua53Libs>; }
     | { version: "lua54"; libraries?: Values<Lua54Libs>; }
     | { version: "luajit"; libraries?: Values<LuaJitLibs>; }
+) & {
+    conversion?: ConversionOptions;
+    sandbox?: SandboxOptions;
+    filesystem?: FileSystemOptions;
+    unsafe?: boolean;
+    timers?: boolean;
+    json?: boolean;
+}
 
 /**
  * If `libraries` field is excluded, it defaults to `ALL_SAFE`
+ *
+ * The `debug` and `ffi` libraries can break memory safety, and are rejected unless `unsafe: true`
+ * is passed. Note that `ALL` includes both. Without `unsafe: true`, the state is also unable to
+ * load C modules.
+ *
+ * With `timers: true`, Lua gets a `timer` library driven by Node's timers:
+ * - `timer.after(ms, fn)` calls `fn` once after `ms` milliseconds, and returns the timer's id.
+ * - `timer.every(ms, fn)` calls `fn` every `ms` milliseconds until cancelled, and returns its id.
+ * - `timer.cancel(id)` cancels a timer, returning whether it was still pending.
+ * Callbacks run from the event loop, so never while the state is executing. Errors they raise are
+ * emitted as an `"error"` event, or thrown from the Node timer if there are no listeners.
+ * `reset()` and `close()` cancel every pending timer.
+ *
+ * With `json: true`, Lua gets a `json` library:
+ * - `json.encode(value, { pretty, sort_keys })` returns JSON text. Tables are shaped as they'd be
+ *   for JS: only positive integer keys make an array, with holes as `null`, and anything else an
+ *   object. An empty table is `{}`, and a table with both kinds of keys is an error.
+ * - `json.decode(text)` returns the decoded value, with `null` as `json.null`.
+ * - `json.null` stands for `null` where `nil` would be lost. It converts to JS `null`.
+ *
+ * Numbers cross the boundary as follows:
+ * - Integral numbers within the safe integer range become Lua integers. Everything else,
+ *   including `-0`, `NaN` and `Infinity`, becomes a Lua float. BigInts become Lua integers.
+ * - Lua integers outside the safe integer range become BigInts.
+ * - `math.huge` is `Infinity`, and `-0` keeps its sign. Lua 5.1, 5.2 and LuaJIT have no integer
+ *   subtype, so there `-0` reads back as `0`.
+ * - `NaN` can't be used as a table key.
+ *
+ * Errors cross the boundary as follows:
+ * - Lua errors raised with a non-string value, e.g. `error({ code = 404 })`, are thrown as a JS
+ *   `Error` with the converted value attached as `value`.
+ * - JS `Error` objects become Lua tables with `name`, `message` and `stack` fields, and a
+ *   `__tostring` metamethod. They convert back into JS `Error`s.
+ * - Exceptions thrown by registered class constructors and methods are raised in Lua with the
+ *   converted exception as the error value, so `pcall` handlers can inspect them.
  */
 export function createLuaState(options: LuaStateOptions): LuaState;
 
+/**
+ * Options for `LuaStatePool`, along with the options its states are created with.
+ *
+ * - `max` caps the number of states, idle or in use. Defaults to 10.
+ * - `init` is a script or function run on each new state, and again after each reset.
+ * - `maxUses` and `maxMemory` (in bytes) are limits checked on release. States over them are
+ *   closed rather than reused.
+ * - `acquireTimeout` rejects `acquire()` after that many milliseconds of waiting.
+ * - `resetOnRelease` resets and re-initializes states before reusing them. Defaults to `true`.
+ */
+export type LuaStatePoolOptions = LuaStateOptions & {
+    max?: number;
+    init?: string | ((state: LuaState) => void);
+    maxUses?: number;
+    maxMemory?: number;
+    acquireTimeout?: number;
+    resetOnRelease?: boolean;
+};
+
+/**
+ * `size` counts every state the pool holds, idle or in use. Wait times are in milliseconds.
+ */
+export interface LuaStatePoolMetrics {
+    size: number;
+    idle: number;
+    inUse: number;
+    waiting: number;
+    created: number;
+    discarded: number;
+    acquisitions: number;
+    averageWaitTime: number;
+    maxWaitTime: number;
+}
+
+/**
+ * Hands out initialized states, creating them on demand up to `max`. Acquisitions beyond that
+ * wait for a state to be released.
+ *
+ * ```
+ * const pool = new LuaStatePool({ version: "lua54", max: 4, init: "helpers = require('helpers')" });
+ * const result = await pool.withState((state) => state.callChunk("function(input) return helpers.run(input) end", [input]));
+ * ```
+ */
+export class LuaStatePool {
+    constructor(options: LuaStatePoolOptions);
+
+    readonly metrics: LuaStatePoolMetrics;
+
+    acquire(): Promise<LuaState>;
+
+    /**
+     * Returns a state to the pool. With `discard`, or when it's over a limit, the state is closed
+     * instead of being reused. States that were already closed are dropped from the pool.
+     */
+    release(state: LuaState, options?: { discard?: boolean }): void;
+
+    /**
+     * Runs `fn` with an acquired state and releases it afterwards. If `fn` throws, the state is
+     * discarded.
+     */
+    withState<T>(fn: (state: LuaState) => T | Promise<T>): Promise<T>;
+
+    /**
+     * Closes idle states and rejects waiting acquisitions. States still in use are closed when
+     * they're released.
+     */
+    drain(): void;
+}
+
 export const Lua51: Lua51;
 export const Lua52: Lua52;
 export const Lua53: Lua53;
 export const Lua54: Lua54;
 export const LuaJIT: LuaJit;
 
+/**
+ * Describes a JS class exposed to Lua via `registerClass`. Lua constructs instances with
+ * `Name.new(...)`, which calls `constructor` with `new`.
+ *
+ * - `methods` are called as `instance:method(...)`, with `this` bound to the instance.
+ * - `getters`/`setters` back field access (`instance.x`, `instance.x = 1`).
+ * - `metamethods` are keyed by their Lua name (`__add`, `__eq`, `__tostring`, ...) and receive
+ *   the operands as arguments, like they would in Lua.
+ */
+export interface LuaClassDefinition<T extends object> {
+    constructor: new (...args: any[]) => T;
+    methods?: { [name: string]: (this: T, ...args: any[]) => any };
+    getters?: { [name: string]: (this: T) => any };
+    setters?: { [name: string]: (this: T, value: any) => void };
+    metamethods?: { [name: string]: (...args: any[]) => any };
+}
+
+/**
+ * Serializes matching JS values before they're converted to Lua. Values are matched either by
+ * `class` (anywhere on the prototype chain) or by a `test` predicate. If `metatable` is given,
+ * the resulting table is tagged with a metatable whose `__name` is `metatable`.
+ */
+export interface ToLuaHook<T = any> {
+    class?: new (...args: any[]) => T;
+    test?: (value: any) => boolean;
+    serialize: (value: T) => any;
+    metatable?: string;
+}
+
+/**
+ * Deserializes Lua tables whose metatable `__name` is `metatable`. The table is converted with
+ * the built-in rules first, then passed to `deserialize`.
+ */
+export interface FromLuaHook<T = any> {
+    metatable: string;
+    deserialize: (table: any) => T;
+}
+
+/**
+ * Thrown for Lua errors raised with a non-string value.
+ */
+export interface LuaValueError extends Error {
+    value: any;
+}
+
+/**
+ * Which events `setHook` reports. `count` fires every `count` VM instructions.
+ */
+export interface HookOptions {
+    line?: boolean;
+    call?: boolean;
+    return?: boolean;
+    count?: number;
+}
+
+/**
+ * Passed to the `setHook` callback. `source` is the chunk name, `line` and `name` are `null`
+ * when Lua doesn't know them, e.g. for C functions.
+ */
+export interface HookEvent {
+    event: "line" | "call" | "tailcall" | "return" | "count";
+    source: string | null;
+    line: number | null;
+    name: string | null;
+}
+
+/**
+ * Where `startDebugger` listens. By default it's an ephemeral TCP port on `127.0.0.1`. With
+ * `stdio`, the process's stdin/stdout carry the protocol, for when the editor launches it as
+ * the debug adapter. With `waitForClient`, `startDebugger` blocks until a client has connected
+ * and finished configuring breakpoints.
+ */
+export interface DebuggerOptions {
+    port?: number;
+    host?: string;
+    stdio?: boolean;
+    waitForClient?: boolean;
+}
+
+/**
+ * How `startProfiling` measures. `"sampling"` (the default) records the stack every `interval`
+ * microseconds (1000 by default) and estimates times from the samples. `"instrumented"` times
+ * every call exactly, at a higher overhead, and still samples stacks every `interval`.
+ */
+export interface ProfilingOptions {
+    mode?: "sampling" | "instrumented";
+    interval?: number;
+}
+
+/**
+ * A function seen while profiling. `source` is its chunk name and `line` the line it's defined
+ * on, which is `-1` for C functions. Times are in milliseconds; `totalTime` includes callees.
+ */
+export interface ProfiledFunction {
+    name: string;
+    source: string;
+    line: number;
+    calls: number;
+    selfTime: number;
+    totalTime: number;
+}
+
+/**
+ * A Chrome DevTools CPU profile, which can be saved as a `.cpuprofile` file.
+ */
+export interface CpuProfile {
+    nodes: {
+        id: number;
+        callFrame: {
+            functionName: string;
+            scriptId: string;
+            url: string;
+            lineNumber: number;
+            columnNumber: number;
+        };
+        hitCount: number;
+        children: number[];
+    }[];
+    startTime: number;
+    endTime: number;
+    samples: number[];
+    timeDeltas: number[];
+}
+
+/**
+ * Returned by `stopProfiling`. Functions are sorted by self time. Each sample's `stack` lists
+ * `name (source:line)` labels, outermost first, and `time` is milliseconds since profiling
+ * started. `folded` holds the samples as `outer;inner count` lines for flamegraph tools.
+ */
+export interface Profile {
+    mode: "sampling" | "instrumented";
+    duration: number;
+    functions: ProfiledFunction[];
+    samples: { stack: string[]; time: number }[];
+    cpuProfile: CpuProfile;
+    folded: string;
+}
+
+/**
+ * Line and function coverage of one chunk. `lines` maps line numbers to how often they ran, and
+ * `functions` lists the functions defined in the chunk by the line they start on.
+ */
+export interface FileCoverage {
+    lines: { [line: number]: number };
+    functions: { name: string; line: number; calls: number }[];
+}
+
+/**
+ * Returned by `takeCoverage`. `files` is keyed by chunk name. `lcov` is an LCOV tracefile and
+ * `istanbul` the JSON nyc writes to `coverage-final.json`, with a statement per line.
+ */
+export interface Coverage {
+    files: { [chunkName: string]: FileCoverage };
+    lcov: string;
+    istanbul: { [path: string]: object };
+}
+
+/**
+ * Settings for `gc.setMode`, as percentages like Lua's `collectgarbage`. Settings that are left
+ * out keep their current value. `stepSize` and the generational multipliers need Lua 5.4.
+ */
+export interface GcModeOptions {
+    pause?: number;
+    stepMultiplier?: number;
+    stepSize?: number;
+    minorMultiplier?: number;
+    majorMultiplier?: number;
+}
+
+/**
+ * `memory` is the memory in use by the state, in bytes. `running` is `null` on Lua 5.1 and
+ * LuaJIT, which can't tell.
+ */
+export interface GcStats {
+    memory: number;
+    running: boolean | null;
+}
+
+/**
+ * Controls the state's garbage collector. Anything the Lua version doesn't support throws.
+ */
+export interface GarbageCollector {
+    /**
+     * Runs a full collection cycle.
+     */
+    collect(): void;
+
+    /**
+     * Runs a step of `kb` kilobytes worth of collection, or a basic step. Returns `true` if the
+     * step finished a cycle.
+     */
+    step(kb?: number): boolean;
+
+    /**
+     * Stops automatic collection until `restart()`.
+     */
+    stop(): void;
+
+    restart(): void;
+
+    /**
+     * Switches between the incremental and (Lua 5.4 only) generational collectors, returning the
+     * previous mode. Older versions only accept `"incremental"`, to change its settings.
+     */
+    setMode(mode: "incremental" | "generational", options?: GcModeOptions): "incremental" | "generational";
+
+    /**
+     * Sets how long the collector waits before a new cycle, returning the previous value.
+     */
+    setPause(pause: number): number;
+
+    /**
+     * Sets how much work each incremental step does, returning the previous value.
+     */
+    setStepMultiplier(multiplier: number): number;
+
+    stats(): GcStats;
+}
+
+declare global {
+    interface SymbolConstructor {
+        // Defined by lib/index.js where Node doesn't have it yet
+        readonly dispose: unique symbol;
+    }
+}
+
 export class LuaState {
 
+    /**
+     * Names of the libraries that were opened, e.g. `["table", "string", "math"]`. Libraries
+     * removed by the sandbox are left out.
+     */
+    readonly loadedLibraries: string[];
+
+    /**
+     * Garbage collector control, e.g. `state.gc.collect()`.
+     */
+    readonly gc: GarbageCollector;
+
     /**
      * Executes a string of code synchronously.
      *
@@ -103,10 +525,60 @@ export class LuaState {
      * multiple times. Event Emitters were more difficult to construct than
      * a reusable callback. This may change in the future.
      *
+     * Calls are queued and delivered on a later tick, in the order Lua made them. Exceptions
+     * thrown by `cb` are emitted as an `"error"` event.
+     *
+     * With `batch`, `cb` is called once per tick with the argument arrays of every call queued
+     * since the last one. With `maxQueued`, a Lua call finding that many calls waiting delivers
+     * them synchronously before returning, holding up the script until JS has caught up. Exceptions
+     * thrown by `cb` then are raised in Lua instead.
+     *
      * @param name
      * @param cb
+     * @param options
      */
-    registerFunction<T extends any[]>(name: string, cb: (args: T) => void): void;
+    registerFunction<T extends any[]>(name: string, cb: (args: T) => void, options?: { batch?: false, maxQueued?: number }): void;
+    registerFunction<T extends any[]>(name: string, cb: (calls: T[]) => void, options: { batch: true, maxQueued?: number }): void;
+
+    /**
+     * Registers a JS class as a Lua userdata type, available as the global `name`:
+     *
+     * ```
+     * state.registerClass("Vector", { constructor: Vector, methods: { length() { ... } } });
+     * state.callChunk("function() return Vector.new(3, 4):length() end", []) === 5
+     * ```
+     *
+     * Instances returned to JS are the original JS objects, and instances of the class passed
+     * into Lua become userdata again.
+     *
+     * @param name
+     * @param definition
+     */
+    registerClass<T extends object>(name: string, definition: LuaClassDefinition<T>): void;
+
+    /**
+     * Adds a hook consulted before the built-in rules when converting JS values to Lua. Hooks
+     * are tried in the order they were added. Combined with `addFromLuaHook`, domain types can
+     * round-trip as tagged tables:
+     *
+     * ```
+     * state.addToLuaHook({ class: Money, metatable: "Money", serialize: (m) => ({ cents: m.cents }) });
+     * state.addFromLuaHook({ metatable: "Money", deserialize: (t) => new Money(t.cents) });
+     * ```
+     *
+     * Conversion hooks are kept when calling `reset()`.
+     *
+     * @param hook
+     */
+    addToLuaHook<T>(hook: ToLuaHook<T>): void;
+
+    /**
+     * Adds a hook consulted before the built-in rules when converting Lua tables to JS. Lua code
+     * can tag its own tables with `setmetatable(t, { __name = "Money" })`.
+     *
+     * @param hook
+     */
+    addFromLuaHook<T>(hook: FromLuaHook<T>): void;
 
 
     /**
@@ -122,7 +594,7 @@ export class LuaState {
      *
      * ```
      * state.callChunk('function(a,b) return a + b end', 1, 2) === 3
-     * state.callChunk('tostring', 1) === "1.0"
+     * state.callChunk('tostring', 1) === "1"
      * ```
      *
      * @param code
@@ -131,6 +603,18 @@ export class LuaState {
     callChunk<T extends any[], R>(code: string, args: T): R;
     callChunk<T extends any[], R>(code: string, chunkName: string, args: T): R;
 
+    /**
+     * `callChunk` with the arguments (a MessagePack array) and the result as MessagePack. The
+     * data is decoded into Lua values and back in Rust, without a call into JS per property,
+     * which makes large payloads much cheaper to pass. See `setGlobalPacked` for how tables are
+     * shaped.
+     *
+     * @param code
+     * @param args
+     */
+    callChunkPacked(code: string, args: Buffer): Buffer;
+    callChunkPacked(code: string, chunkName: string, args: Buffer): Buffer;
+
     // /**
     //  * @async
     //  * @name doString
@@ -163,6 +647,27 @@ export class LuaState {
      */
     setGlobal(name: string, value: any): void;
 
+    /**
+     * Sets a global from MessagePack data, decoded straight into Lua values. Strings and binary
+     * data both become Lua strings, and `nil` values in maps are left out. Extension types aren't
+     * supported. This is for data that's already MessagePack, e.g. from a socket or a cache;
+     * lua-js doesn't include a JS encoder or decoder.
+     *
+     * @param name
+     * @param value
+     */
+    setGlobalPacked(name: string, value: Buffer): void;
+
+    /**
+     * Gets a global as MessagePack data. Tables with only positive integer keys are packed as
+     * arrays (holes become `nil`), like `getGlobal` makes them arrays, other tables as maps, and
+     * empty tables as empty maps. Strings that aren't valid UTF-8 are packed as binary data.
+     * Functions and userdata can't be packed.
+     *
+     * @param name
+     */
+    getGlobalPacked(name: string): Buffer;
+
     /**
      * Get a global from the current context.
      *
@@ -172,16 +677,178 @@ export class LuaState {
      */
     getGlobal<T>(name: string): T;
 
+    /**
+     * Describes the global environment, e.g. to check what a sandbox left available. Each global
+     * maps to its Lua type, except tables, which map to the sorted names of their fields:
+     *
+     * ```
+     * { os: ["clock", "date", "difftime", "time"], print: "function", _VERSION: "string", ... }
+     * ```
+     */
+    getEnvironment(): { [name: string]: string | string[] };
+
+    /**
+     * Calls `callback` for the selected execution events, e.g. to audit which lines run:
+     *
+     * ```
+     * state.setHook({ line: true }, ({ source, line }) => console.log(`${source}:${line}`));
+     * ```
+     *
+     * Throwing from the callback aborts the running script with that error. Scripts can't
+     * recover with `pcall`, as every later hook event raises the error again until control
+     * returns to JS. Replaces any previous `setHook` callback, and is removed by `reset()`. The
+     * debugger, profiling and coverage keep working alongside it.
+     *
+     * @param options
+     * @param callback
+     */
+    setHook(options: HookOptions, callback: (event: HookEvent) => void): void;
+
+    /**
+     * Removes the hook installed with `setHook`.
+     */
+    removeHook(): void;
+
+    /**
+     * Starts a Debug Adapter Protocol server for this state, returning the TCP port it listens
+     * on. Clients can set breakpoints by chunk name and line, step in/over/out, inspect the
+     * locals and upvalues of each stack frame, and evaluate expressions in a paused frame. Use
+     * the file path as the chunk name (the default for `doFileSync`) so editors can match
+     * breakpoints to files.
+     *
+     * Needs the `debug` library, and so the `unsafe: true` option. While paused, the thread
+     * running Lua is blocked. It stays attached across `reset()`, and is stopped by `close()`.
+     *
+     * @param options
+     */
+    startDebugger(options?: DebuggerOptions): number | undefined;
+
+    /**
+     * Stops the debugger started with `startDebugger`.
+     */
+    stopDebugger(): void;
+
+    /**
+     * Starts profiling the Lua code run by this state, replacing any running profile.
+     * `reset()` discards the profile.
+     *
+     * @param options
+     */
+    startProfiling(options?: ProfilingOptions): void;
+
+    /**
+     * Stops profiling and returns the results. Throws if profiling wasn't started.
+     */
+    stopProfiling(): Profile;
+
+    /**
+     * Starts recording which lines run, by chunk name, discarding any earlier coverage. Use file
+     * paths as chunk names (the default for `doFileSync`) for reports that tools can match to
+     * files. Coverage keeps accumulating across `reset()`.
+     *
+     * Lines are only reported once they run, unless the `debug` library is loaded: then the first
+     * call of a function also reports its lines that haven't run, with zero hits.
+     */
+    startCoverage(): void;
+
+    /**
+     * Returns the coverage collected since `startCoverage`, and keeps collecting. Throws if
+     * coverage wasn't started.
+     */
+    takeCoverage(): Coverage;
+
+    /**
+     * Stops collecting coverage and discards it.
+     */
+    stopCoverage(): void;
+
+    /**
+     * Serializes everything reachable from the globals (tables, strings, numbers and booleans)
+     * into a Buffer that `restore` can load into another state, even in another process. Shared
+     * tables and cycles are preserved, metatables are not. The standard libraries and base
+     * functions are left out, as every state has its own.
+     *
+     * Functions, userdata and threads can't be serialized. By default they're skipped, and
+     * `restore` leaves whatever the state has in their place. With `strict: true`, they throw an
+     * error naming where they were found instead.
+     *
+     * @param options
+     */
+    snapshot(options?: { strict?: boolean }): Buffer;
+
+    /**
+     * Assigns the globals saved by `snapshot`, typically in a fresh state.
+     *
+     * @param snapshot
+     */
+    restore(snapshot: Buffer): void;
+
     /**
      * This is a mutable reset. It closes the internal Lua context, spawning a
      * new one. This clears all globals, as well as clears the event emitters
-     * hidden behind registerFunction. Since it clears the event emitters it is
-     * also effectively `close()`, and allows the current program to exit.
+     * hidden behind registerFunction, which allows the current program to exit.
+     * Unlike `close()`, the state can still be used afterwards.
+     *
+     * With `keepRegistrations`, the new context gets back what the host put into the old one:
+     * functions from `registerFunction`, classes from `registerClass`, globals from `setGlobal`
+     * (with the value they were last set to from JS) and the `setHook` hook. Globals assigned by
+     * scripts are still cleared.
+     *
+     * @param options
      */
-    reset(): void;
+    reset(options?: { keepRegistrations?: boolean }): void;
 
     /**
-     * Alias for `reset()`
+     * Closes the internal Lua context for good, releasing the event emitters behind
+     * `registerFunction` so the program can exit. Every other method throws a "state is closed"
+     * error afterwards. Closing twice does nothing.
      */
     close(): void;
+
+    /**
+     * Whether `close()` was called.
+     */
+    readonly isClosed: boolean;
+
+    /**
+     * Closes the state, for `using` declarations.
+     */
+    [Symbol.dispose](): void;
+
+    /**
+     * Adds a listener for events from Lua's `emit(name, ...)` global. Listeners are called
+     * synchronously with the emitted values, and Lua's `emit` returns whether there were any.
+     *
+     * A listener that throws is reported to the `"error"` listeners as `(error, eventName)`. With
+     * no `"error"` listeners, the exception is rethrown into the Lua code that called `emit`.
+     *
+     * @param name
+     * @param listener
+     */
+    on(name: string, listener: (this: LuaState, ...args: any[]) => void): this;
+
+    /**
+     * Like `on`, but the listener is removed after its first call.
+     *
+     * @param name
+     * @param listener
+     */
+    once(name: string, listener: (this: LuaState, ...args: any[]) => void): this;
+
+    /**
+     * Removes a listener added with `on` or `once`.
+     *
+     * @param name
+     * @param listener
+     */
+    off(name: string, listener: (this: LuaState, ...args: any[]) => void): this;
+
+    /**
+     * Calls the listeners for `name` from JS, as Lua's `emit` would. Returns whether there were
+     * any.
+     *
+     * @param name
+     * @param args
+     */
+    emit(name: string, ...args: any[]): boolean;
 }
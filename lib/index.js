@@ -4,8 +4,161 @@ const lua52 = require("../native/lua52.node");
 const lua51 = require("../native/lua51.node");
 const luajit = require("../native/luajit.node");
 
-exports.createLuaState = function (options) {
-    const {version, ...stateOptions} = options;
+// Event listeners by state, then by event name. Lua's `emit` calls the dispatcher a state gets
+// with its first listener.
+const stateListeners = new WeakMap();
+
+function listenersOf(state) {
+    let listeners = stateListeners.get(state);
+    if (!listeners) {
+        listeners = new Map();
+        stateListeners.set(state, listeners);
+        state.setEventDispatcher((name, ...args) => dispatch(state, name, args));
+    }
+    return listeners;
+}
+
+function removeListener(listeners, name, listener) {
+    const entries = listeners.get(name) || [];
+    const i = entries.findIndex((entry) => entry.listener === listener);
+    if (i !== -1) {
+        entries.splice(i, 1);
+    }
+}
+
+// Calls the listeners for `name`, returning whether there were any. A listener that throws is
+// reported to the "error" listeners, or rethrown (into Lua, for `emit`) if there are none.
+function dispatch(state, name, args) {
+    const listeners = stateListeners.get(state);
+    const entries = listeners && listeners.get(name);
+    if (!entries || entries.length === 0) {
+        return false;
+    }
+    for (const entry of [...entries]) {
+        if (entry.once) {
+            removeListener(listeners, name, entry.listener);
+        }
+        try {
+            entry.listener.apply(state, args);
+        } catch (e) {
+            const errorListeners = listeners.get("error");
+            if (name === "error" || !errorListeners || errorListeners.length === 0) {
+                throw e;
+            }
+            dispatch(state, "error", [e, name]);
+        }
+    }
+    return true;
+}
+
+function addListener(state, name, listener, once) {
+    if (typeof listener !== "function") {
+        throw new TypeError("listener must be a function");
+    }
+    const listeners = listenersOf(state);
+    if (!listeners.has(name)) {
+        listeners.set(name, []);
+    }
+    listeners.get(name).push({ listener, once });
+    return state;
+}
+
+// Backs the `timer` library of states created with `timers: true`. Lua keeps the callbacks, and
+// asks for Node timers by id.
+function installTimers(state) {
+    const handles = new Map();
+    state.setTimerScheduler((command, id, ms) => {
+        switch (command) {
+            case "after":
+                handles.set(id, setTimeout(() => {
+                    handles.delete(id);
+                    runTimer(state, id);
+                }, ms));
+                break;
+            case "every":
+                handles.set(id, setInterval(() => runTimer(state, id), ms));
+                break;
+            case "cancel":
+                clearTimeout(handles.get(id));
+                handles.delete(id);
+                break;
+            case "clear":
+                for (const handle of handles.values()) {
+                    clearTimeout(handle);
+                }
+                handles.clear();
+                break;
+        }
+    });
+}
+
+// Errors from timer callbacks go to the "error" listeners, or are thrown from the Node timer if
+// there are none.
+function runTimer(state, id) {
+    try {
+        state.runTimer(id);
+    } catch (e) {
+        if (!dispatch(state, "error", [e, "timer"])) {
+            throw e;
+        }
+    }
+}
+
+// `using` declarations need Symbol.dispose, which older Node versions don't define
+const dispose = Symbol.dispose || Symbol.for("nodejs.dispose");
+
+// Neon classes can't declare getters, symbol-keyed or variadic methods, so `loadedLibraries`, `gc`,
+// `isClosed`, `Symbol.dispose` and the event emitter methods are defined on each prototype here.
+for (const version of [lua54, lua53, lua52, lua51, luajit]) {
+    Object.defineProperty(version.LuaState.prototype, "isClosed", {
+        get() {
+            return this.getIsClosed();
+        }
+    });
+    version.LuaState.prototype[dispose] = function () {
+        this.close();
+    };
+    Object.assign(version.LuaState.prototype, {
+        on(name, listener) {
+            return addListener(this, name, listener, false);
+        },
+        once(name, listener) {
+            return addListener(this, name, listener, true);
+        },
+        off(name, listener) {
+            const listeners = stateListeners.get(this);
+            if (listeners) {
+                removeListener(listeners, name, listener);
+            }
+            return this;
+        },
+        emit(name, ...args) {
+            return dispatch(this, name, args);
+        },
+    });
+    Object.defineProperty(version.LuaState.prototype, "loadedLibraries", {
+        get() {
+            return this.getLoadedLibraries();
+        }
+    });
+    Object.defineProperty(version.LuaState.prototype, "gc", {
+        get() {
+            const state = this;
+            return {
+                collect: () => state.collectGarbage("collect"),
+                step: (kb) => state.collectGarbage("step", kb),
+                stop: () => state.collectGarbage("stop"),
+                restart: () => state.collectGarbage("restart"),
+                setMode: (mode, options) => state.collectGarbage("setMode", mode, options),
+                setPause: (pause) => state.collectGarbage("setPause", pause),
+                setStepMultiplier: (multiplier) => state.collectGarbage("setStepMultiplier", multiplier),
+                stats: () => state.collectGarbage("stats"),
+            };
+        }
+    });
+}
+
+function constructState(version, stateOptions) {
     switch (version) {
         case "lua54": {
             return new lua54.LuaState(stateOptions);
@@ -27,6 +180,15 @@ exports.createLuaState = function (options) {
     }
 }
 
+exports.createLuaState = function (options) {
+    const {version, timers, ...stateOptions} = options;
+    const state = constructState(version, stateOptions);
+    if (timers) {
+        installTimers(state);
+    }
+    return state;
+}
+
 const SharedLibs = {
     table: 0x2,
     io: 0x4,
@@ -61,4 +223,6 @@ exports.Lua54 = {
 exports.LuaJIT = {
     Version: "luajit",
     Libs: Object.assign({bit: 0x40, jit: 0x200, ffi: 0x40000000}, SharedLibs)
-}
\ No newline at end of file
+}
+
+exports.LuaStatePool = require("./pool").LuaStatePool;
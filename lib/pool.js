@@ -0,0 +1,208 @@
+// A pool of warmed up LuaStates. States are created on demand up to `max`, initialized once, and
+// handed out by `acquire()`. On `release()` they're reset and re-initialized for the next user,
+// unless a limit says they should be thrown away instead.
+
+class LuaStatePool {
+    constructor(options) {
+        const {
+            max = 10,
+            init,
+            maxUses = Infinity,
+            maxMemory = Infinity,
+            acquireTimeout = Infinity,
+            resetOnRelease = true,
+            ...stateOptions
+        } = options;
+        if (!(max >= 1)) {
+            throw new RangeError("'max' must be at least 1");
+        }
+        this._stateOptions = stateOptions;
+        this._max = max;
+        this._init = init;
+        this._maxUses = maxUses;
+        this._maxMemory = maxMemory;
+        this._acquireTimeout = acquireTimeout;
+        this._resetOnRelease = resetOnRelease;
+
+        this._idle = [];
+        // Use counts of every live state, idle or not
+        this._uses = new Map();
+        this._waiting = [];
+        this._draining = false;
+        this._stats = {
+            created: 0,
+            discarded: 0,
+            acquisitions: 0,
+            totalWaitTime: 0,
+            maxWaitTime: 0,
+        };
+    }
+
+    get metrics() {
+        const { created, discarded, acquisitions, totalWaitTime, maxWaitTime } = this._stats;
+        return {
+            size: this._uses.size,
+            idle: this._idle.length,
+            inUse: this._uses.size - this._idle.length,
+            waiting: this._waiting.length,
+            created,
+            discarded,
+            acquisitions,
+            averageWaitTime: acquisitions > 0 ? totalWaitTime / acquisitions : 0,
+            maxWaitTime,
+        };
+    }
+
+    acquire() {
+        if (this._draining) {
+            return Promise.reject(new Error("the pool is draining"));
+        }
+        const requested = Date.now();
+        let state;
+        try {
+            state = this._take();
+        } catch (e) {
+            return Promise.reject(e);
+        }
+        if (state) {
+            this._recordWait(requested);
+            return Promise.resolve(state);
+        }
+        return new Promise((resolve, reject) => {
+            const waiter = { resolve, reject, requested, timer: null };
+            if (this._acquireTimeout !== Infinity) {
+                waiter.timer = setTimeout(() => {
+                    this._waiting.splice(this._waiting.indexOf(waiter), 1);
+                    reject(new Error(`timed out after ${this._acquireTimeout}ms waiting for a LuaState`));
+                }, this._acquireTimeout);
+            }
+            this._waiting.push(waiter);
+        });
+    }
+
+    release(state, { discard = false } = {}) {
+        const uses = this._uses.get(state);
+        if (uses === undefined || this._idle.includes(state)) {
+            throw new Error("the LuaState doesn't belong to this pool, or was already released");
+        }
+        // A state closed by its user is of no more use, and can't report its memory
+        if (
+            discard ||
+            this._draining ||
+            state.isClosed ||
+            uses >= this._maxUses ||
+            state.gc.stats().memory > this._maxMemory
+        ) {
+            this._discard(state);
+        } else if (this._resetOnRelease) {
+            try {
+                state.reset();
+                this._initialize(state);
+                this._idle.push(state);
+            } catch (e) {
+                this._discard(state);
+            }
+        } else {
+            this._idle.push(state);
+        }
+        this._serveWaiting();
+    }
+
+    // Runs `fn` with a state, releasing it afterwards. States whose callback threw are discarded,
+    // as they may have been left in a bad state.
+    async withState(fn) {
+        const state = await this.acquire();
+        let result;
+        try {
+            result = await fn(state);
+        } catch (e) {
+            this.release(state, { discard: true });
+            throw e;
+        }
+        this.release(state);
+        return result;
+    }
+
+    // Closes the idle states and rejects waiting acquisitions. States in use are closed as
+    // they're released.
+    drain() {
+        this._draining = true;
+        for (const waiter of this._waiting.splice(0)) {
+            clearTimeout(waiter.timer);
+            waiter.reject(new Error("the pool is draining"));
+        }
+        for (const state of this._idle.splice(0)) {
+            this._discard(state);
+        }
+    }
+
+    _take() {
+        let state = this._idle.pop();
+        if (!state) {
+            if (this._uses.size >= this._max) {
+                return null;
+            }
+            state = this._create();
+        }
+        this._uses.set(state, this._uses.get(state) + 1);
+        this._stats.acquisitions++;
+        return state;
+    }
+
+    _create() {
+        const { createLuaState } = require("./index");
+        const state = createLuaState(this._stateOptions);
+        try {
+            this._initialize(state);
+        } catch (e) {
+            state.close();
+            throw e;
+        }
+        this._uses.set(state, 0);
+        this._stats.created++;
+        return state;
+    }
+
+    _initialize(state) {
+        if (typeof this._init === "string") {
+            state.doStringSync(this._init, "pool init");
+        } else if (typeof this._init === "function") {
+            this._init(state);
+        }
+    }
+
+    _discard(state) {
+        this._uses.delete(state);
+        this._stats.discarded++;
+        state.close();
+    }
+
+    _serveWaiting() {
+        while (this._waiting.length > 0) {
+            let state;
+            try {
+                state = this._take();
+            } catch (e) {
+                const waiter = this._waiting.shift();
+                clearTimeout(waiter.timer);
+                waiter.reject(e);
+                continue;
+            }
+            if (!state) {
+                return;
+            }
+            const waiter = this._waiting.shift();
+            clearTimeout(waiter.timer);
+            this._recordWait(waiter.requested);
+            waiter.resolve(state);
+        }
+    }
+
+    _recordWait(requested) {
+        const wait = Date.now() - requested;
+        this._stats.totalWaitTime += wait;
+        this._stats.maxWaitTime = Math.max(this._stats.maxWaitTime, wait);
+    }
+}
+
+exports.LuaStatePool = LuaStatePool;
@@ -0,0 +1,178 @@
+//! Delivery of calls to functions added with `registerFunction`.
+//!
+//! Lua calls a registered function synchronously, but its JS callback runs later on the event
+//! loop. Calls are queued in the order Lua made them, and a single task scheduled with the
+//! `EventHandler` delivers everything queued by the time it runs: one callback call per Lua call,
+//! or one call with an array of them when batching. At most one task is scheduled at a time, so
+//! calls can't overtake each other.
+//!
+//! With `maxQueued`, a Lua call that finds the queue full delivers the waiting calls itself,
+//! synchronously, before queueing its own. The script is held up while JS catches up, rather than
+//! the queue growing without bound.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use neon::prelude::*;
+
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::js_traits::ToJs;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Deliver the calls queued during a tick as one array of argument arrays.
+    pub batch: bool,
+    /// How many calls can wait before Lua has to deliver them itself.
+    pub max_queued: Option<usize>,
+}
+
+#[derive(Default)]
+struct Queue {
+    calls: VecDeque<Vec<Value>>,
+    scheduled: bool,
+}
+
+pub struct CallQueue {
+    queue: Mutex<Queue>,
+    handler: EventHandler,
+    // The same function as the handler's, for delivering synchronously
+    callback: JsRef,
+    options: Options,
+}
+
+impl CallQueue {
+    pub fn new(handler: EventHandler, callback: JsRef, options: Options) -> Arc<CallQueue> {
+        Arc::new(CallQueue {
+            queue: Mutex::new(Queue::default()),
+            handler,
+            callback,
+            options,
+        })
+    }
+
+    /// Queues a call, scheduling its delivery if nothing is scheduled yet. Errors are exceptions
+    /// thrown by the callback while delivering a full queue.
+    pub fn push(self: &Arc<Self>, args: Vec<Value>) -> mlua::Result<()> {
+        let waiting = {
+            let mut queue = self.lock();
+            match self.options.max_queued {
+                Some(max) if queue.calls.len() >= max => queue.calls.drain(..).collect(),
+                _ => vec![],
+            }
+        };
+        if !waiting.is_empty() {
+            self.deliver_now(waiting)?;
+        }
+
+        let schedule = {
+            let mut queue = self.lock();
+            queue.calls.push_back(args);
+            !std::mem::replace(&mut queue.scheduled, true)
+        };
+        if schedule {
+            let this = self.clone();
+            self.handler
+                .schedule_with(move |cx, state, callback| this.deliver(cx, state, callback));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<Queue> {
+        // A panic while holding the lock can't leave the queue inconsistent
+        match self.queue.lock() {
+            Ok(queue) => queue,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn take(&self) -> Vec<Vec<Value>> {
+        let mut queue = self.lock();
+        queue.scheduled = false;
+        queue.calls.drain(..).collect()
+    }
+
+    /// The scheduled task. Nothing is waiting on the callback here, so exceptions go to the
+    /// state's "error" listeners.
+    fn deliver(&self, cx: &mut TaskContext, state: Handle<JsValue>, callback: Handle<JsFunction>) {
+        let calls = self.take();
+        if calls.is_empty() {
+            // Already delivered by a Lua call that found the queue full
+            return;
+        }
+        let batches = if self.options.batch {
+            vec![calls]
+        } else {
+            calls.into_iter().map(|call| vec![call]).collect()
+        };
+        for batch in batches {
+            let result = cx.try_catch(|cx| {
+                let args = callback_args(cx, &batch, self.options.batch)?;
+                callback.call(cx, state, args)
+            });
+            if let Err(exception) = result {
+                emit_error(cx, state, exception);
+            }
+        }
+    }
+
+    /// Delivers calls from inside Lua, where exceptions are raised in the calling script.
+    fn deliver_now(&self, calls: Vec<Vec<Value>>) -> mlua::Result<()> {
+        let batches = if self.options.batch {
+            vec![calls]
+        } else {
+            calls.into_iter().map(|call| vec![call]).collect()
+        };
+        for batch in batches {
+            js_context::with_context(|cx| {
+                let callback = self
+                    .callback
+                    .to_handle(cx)?
+                    .downcast_or_throw::<JsFunction, _>(cx)?;
+                let state = cx.this().upcast::<JsValue>();
+                let args = callback_args(cx, &batch, self.options.batch)?;
+                callback.call(cx, state, args)?;
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The callback's arguments: the call's argument array, or an array of them for a batch.
+fn callback_args<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    calls: &[Vec<Value>],
+    batch: bool,
+) -> NeonResult<Vec<Handle<'a, JsValue>>> {
+    let mut arrays = Vec::with_capacity(calls.len());
+    for call in calls {
+        let array = JsArray::new(cx, call.len() as u32);
+        for (i, value) in call.iter().enumerate() {
+            let value = value.to_js(cx)?;
+            array.set(cx, i as u32, value)?;
+        }
+        arrays.push(array.upcast::<JsValue>());
+    }
+    if !batch {
+        return Ok(arrays);
+    }
+    let batch = JsArray::new(cx, arrays.len() as u32);
+    for (i, array) in arrays.into_iter().enumerate() {
+        batch.set(cx, i as u32, array)?;
+    }
+    Ok(vec![batch.upcast()])
+}
+
+fn emit_error(cx: &mut TaskContext, state: Handle<JsValue>, exception: Handle<JsValue>) {
+    let emit = state
+        .downcast::<JsObject>()
+        .ok()
+        .and_then(|state| state.get(cx, "emit").ok())
+        .and_then(|emit| emit.downcast::<JsFunction>().ok());
+    if let Some(emit) = emit {
+        let name = cx.string("error");
+        let args: Vec<Handle<JsValue>> = vec![name.upcast(), exception];
+        let _ = emit.call(cx, state, args);
+    }
+}
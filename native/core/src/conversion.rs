@@ -0,0 +1,390 @@
+//! Per-state configuration consulted by the `Value` conversions before the built-in rules:
+//! classes registered with `registerClass`, and custom `toLua`/`fromLua` hooks.
+//!
+//! A `toLua` hook matches JS values by class or predicate and serializes them into something the
+//! built-in rules understand. If the hook names a metatable, the resulting Lua table is tagged
+//! with it. A `fromLua` hook matches tables whose metatable has that `__name`, and deserializes
+//! them back into a JS value, so domain types can round-trip as tagged tables.
+//!
+//! `ConversionOptions` control which properties of JS objects are converted, and what happens to
+//! symbol keys.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use mlua::prelude::LuaTable;
+use neon::prelude::*;
+
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::js_traits::FromJs;
+use crate::metatables;
+use crate::user_data::UserClass;
+use crate::value::Value;
+
+pub type ConversionRegistry = Arc<RwLock<Conversions>>;
+
+#[derive(Debug, Default)]
+pub struct Conversions {
+    pub classes: Vec<Arc<UserClass>>,
+    pub to_lua: Vec<Arc<ToLuaHook>>,
+    // Keyed by metatable name
+    pub from_lua: HashMap<String, JsRef>,
+    pub options: ConversionOptions,
+    // Symbols handed to Lua as light userdata, keyed by their reference id. These are never
+    // released, as we can't tell when Lua is done with a light userdata.
+    pub symbols: HashMap<u64, JsRef>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKeys {
+    Skip,
+    LightUserData,
+}
+
+impl Default for SymbolKeys {
+    fn default() -> Self {
+        SymbolKeys::Skip
+    }
+}
+
+/// Which properties of a JS object are converted to Lua. By default, only own enumerable string
+/// keys are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConversionOptions {
+    // Enumerable properties from the prototype chain, like `for...in`
+    pub inherited: bool,
+    // Accessor properties from the prototype chain, e.g. getters on class instances
+    pub getters: bool,
+    pub non_enumerable: bool,
+    pub symbols: SymbolKeys,
+}
+
+impl ConversionOptions {
+    fn own_enumerable_only(&self) -> bool {
+        !self.inherited && !self.getters && !self.non_enumerable && self.symbols == SymbolKeys::Skip
+    }
+}
+
+#[derive(Debug)]
+pub enum HookTest {
+    Class(JsRef),
+    Predicate(JsRef),
+}
+
+#[derive(Debug)]
+pub struct ToLuaHook {
+    pub test: HookTest,
+    pub serialize: JsRef,
+    pub metatable: Option<String>,
+}
+
+/// Checks if `constructor.prototype` is on the prototype chain of `object`.
+pub fn is_instance<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    object: Handle<'a, JsObject>,
+    constructor: Handle<'a, JsObject>,
+) -> NeonResult<bool> {
+    let class_prototype = constructor.get(cx, "prototype")?;
+
+    let global = cx.global();
+    let object_ctor = global
+        .get(cx, "Object")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    let get_prototype_of = object_ctor
+        .get(cx, "getPrototypeOf")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+
+    let mut prototype = get_prototype_of.call(cx, object_ctor, vec![object.upcast::<JsValue>()])?;
+    while prototype.is_a::<JsObject>() {
+        if prototype.strict_equals(cx, class_prototype) {
+            return Ok(true);
+        }
+        prototype = get_prototype_of.call(cx, object_ctor, vec![prototype])?;
+    }
+    Ok(false)
+}
+
+/// `is_instance` for a referenced constructor.
+pub fn is_instance_of_ref<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    object: Handle<'a, JsObject>,
+    constructor: &JsRef,
+) -> NeonResult<bool> {
+    let constructor = constructor
+        .to_handle(cx)?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    is_instance(cx, object, constructor)
+}
+
+/// Checks if `object` is a JS `Error` (or subclass) instance.
+pub fn is_error<'a, CX: Context<'a>>(cx: &mut CX, object: Handle<'a, JsObject>) -> NeonResult<bool> {
+    let global = cx.global();
+    let error_ctor = global
+        .get(cx, "Error")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    is_instance(cx, object, error_ctor)
+}
+
+/// Runs the registered `toLua` hooks against `handle`, returning the serialized value of the
+/// first one that matches.
+pub fn apply_to_lua_hooks<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    handle: Handle<'a, JsValue>,
+) -> NeonResult<Option<Value>> {
+    let hooks: Vec<Arc<ToLuaHook>> = match js_context::current_conversions() {
+        Some(registry) => match registry.read() {
+            Ok(conversions) => conversions.to_lua.clone(),
+            Err(_) => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    for hook in hooks.iter() {
+        let matched = match &hook.test {
+            HookTest::Class(constructor) => match handle.downcast::<JsObject>() {
+                Ok(object) => is_instance_of_ref(cx, object, constructor)?,
+                Err(_) => false,
+            },
+            HookTest::Predicate(predicate) => {
+                let predicate = predicate
+                    .to_handle(cx)?
+                    .downcast_or_throw::<JsFunction, CX>(cx)?;
+                let undefined = cx.undefined();
+                let result = predicate.call(cx, undefined, vec![handle])?;
+                match result.downcast::<JsBoolean>() {
+                    Ok(b) => b.value(),
+                    Err(_) => false,
+                }
+            }
+        };
+        if matched {
+            let serialize = hook
+                .serialize
+                .to_handle(cx)?
+                .downcast_or_throw::<JsFunction, CX>(cx)?;
+            let undefined = cx.undefined();
+            let serialized = serialize.call(cx, undefined, vec![handle])?;
+            let value = Value::from_js(serialized, cx)?;
+            return Ok(Some(match &hook.metatable {
+                Some(name) => Value::Tagged(name.clone(), Box::new(value)),
+                None => value,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Deserializes a tagged table with the `fromLua` hook registered for `name`. Without a hook
+/// (e.g. it was removed on the JS side) the plain table is returned.
+pub fn apply_from_lua_hook<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    name: &str,
+    table: Handle<'a, JsValue>,
+) -> JsResult<'a, JsValue> {
+    let deserialize = match js_context::current_conversions() {
+        Some(registry) => match registry.read() {
+            Ok(conversions) => conversions.from_lua.get(name).cloned(),
+            Err(_) => None,
+        },
+        None => None,
+    };
+    match deserialize {
+        Some(deserialize) => {
+            let deserialize = deserialize
+                .to_handle(cx)?
+                .downcast_or_throw::<JsFunction, CX>(cx)?;
+            let undefined = cx.undefined();
+            deserialize.call(cx, undefined, vec![table])
+        }
+        None => Ok(table),
+    }
+}
+
+/// Returns the metatable `__name` of `table` if there's a `fromLua` hook registered for it.
+pub fn hooked_metatable_name(table: &LuaTable) -> mlua::Result<Option<String>> {
+    let name = match metatables::metatable_name(table)? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let hooked = match js_context::current_conversions() {
+        Some(registry) => match registry.read() {
+            Ok(conversions) => conversions.from_lua.contains_key(&name),
+            Err(_) => false,
+        },
+        None => false,
+    };
+    Ok(if hooked { Some(name) } else { None })
+}
+
+/// The conversion options of the state driving the current call.
+pub fn current_options() -> ConversionOptions {
+    match js_context::current_conversions() {
+        Some(registry) => match registry.read() {
+            Ok(conversions) => conversions.options,
+            Err(_) => ConversionOptions::default(),
+        },
+        None => ConversionOptions::default(),
+    }
+}
+
+/// A property found by `property_keys`. Properties that are only included because of the
+/// conversion options skip function values, so class methods don't come along with getters.
+pub struct PropertyKey<'a> {
+    pub key: Handle<'a, JsValue>,
+    pub skip_functions: bool,
+}
+
+/// Collects the property keys of `object` to convert, according to `options`. Keys that are
+/// canonical array indexes are returned as numbers, matching `get_own_property_names`.
+pub fn property_keys<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    object: Handle<'a, JsObject>,
+    options: &ConversionOptions,
+) -> NeonResult<Vec<PropertyKey<'a>>> {
+    if options.own_enumerable_only() {
+        let names = object.get_own_property_names(cx)?.to_vec(cx)?;
+        return Ok(names
+            .into_iter()
+            .map(|key| PropertyKey {
+                key,
+                skip_functions: false,
+            })
+            .collect());
+    }
+
+    let global = cx.global();
+    let object_ctor = global
+        .get(cx, "Object")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    let get_prototype_of = object_ctor
+        .get(cx, "getPrototypeOf")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    let get_descriptor = object_ctor
+        .get(cx, "getOwnPropertyDescriptor")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    let reflect = global
+        .get(cx, "Reflect")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    let own_keys = reflect
+        .get(cx, "ownKeys")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    // Built-in prototypes end the walk up the chain
+    let object_prototype = object_ctor.get(cx, "prototype")?;
+    let array_prototype = global
+        .get(cx, "Array")?
+        .downcast_or_throw::<JsObject, CX>(cx)?
+        .get(cx, "prototype")?;
+
+    let mut keys: Vec<PropertyKey<'a>> = vec![];
+    let mut seen: Vec<Handle<'a, JsValue>> = vec![];
+    let mut current: Handle<'a, JsValue> = object.upcast();
+    let mut depth = 0;
+    loop {
+        let names = own_keys
+            .call(cx, reflect, vec![current])?
+            .downcast_or_throw::<JsArray, CX>(cx)?
+            .to_vec(cx)?;
+        for key in names {
+            let is_symbol = !key.is_a::<JsString>();
+            if is_symbol && options.symbols == SymbolKeys::Skip {
+                continue;
+            }
+            let mut already_seen = false;
+            for other in seen.iter() {
+                if key.strict_equals(cx, *other) {
+                    already_seen = true;
+                    break;
+                }
+            }
+            if already_seen {
+                continue;
+            }
+            // Shadowed properties are skipped further up the chain, even when not included here
+            seen.push(key);
+
+            let descriptor = get_descriptor
+                .call(cx, object_ctor, vec![current, key])?
+                .downcast_or_throw::<JsObject, CX>(cx)?;
+            let enumerable = descriptor
+                .get(cx, "enumerable")?
+                .downcast::<JsBoolean>()
+                .map(|b| b.value())
+                .unwrap_or(false);
+            let is_accessor = !descriptor.get(cx, "get")?.is_a::<JsUndefined>();
+
+            let include = if depth == 0 {
+                enumerable || options.non_enumerable || (options.getters && is_accessor)
+            } else {
+                (options.inherited && enumerable) || (options.getters && is_accessor)
+            };
+            if include {
+                keys.push(PropertyKey {
+                    key: index_key(cx, key)?,
+                    skip_functions: depth > 0 || !enumerable,
+                });
+            }
+        }
+
+        if !options.inherited && !options.getters {
+            break;
+        }
+        current = get_prototype_of.call(cx, object_ctor, vec![current])?;
+        if !current.is_a::<JsObject>()
+            || current.strict_equals(cx, object_prototype)
+            || current.strict_equals(cx, array_prototype)
+        {
+            break;
+        }
+        depth += 1;
+    }
+    Ok(keys)
+}
+
+/// `Reflect.ownKeys` returns array indexes as strings. Converts those back to numbers.
+fn index_key<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    key: Handle<'a, JsValue>,
+) -> JsResult<'a, JsValue> {
+    if let Ok(s) = key.downcast::<JsString>() {
+        let s = s.value();
+        if let Ok(index) = s.parse::<u32>() {
+            if index != u32::MAX && index.to_string() == s {
+                return Ok(cx.number(index).upcast());
+            }
+        }
+    }
+    Ok(key)
+}
+
+/// Converts a symbol to a `Value::Symbol`, reusing the reference if it was seen before.
+pub fn symbol_value<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    symbol: Handle<'a, JsValue>,
+) -> NeonResult<Value> {
+    let registry = match js_context::current_conversions() {
+        Some(registry) => registry,
+        None => return cx.throw_error("Symbols can only be converted by a LuaState"),
+    };
+    let known: Vec<JsRef> = match registry.read() {
+        Ok(conversions) => conversions.symbols.values().cloned().collect(),
+        Err(_) => vec![],
+    };
+    for reference in known {
+        let other = reference.to_handle(cx)?;
+        if symbol.strict_equals(cx, other) {
+            return Ok(Value::Symbol(reference));
+        }
+    }
+    let reference = JsRef::new(cx, symbol)?;
+    if let Ok(mut conversions) = registry.write() {
+        conversions.symbols.insert(reference.id(), reference.clone());
+    }
+    Ok(Value::Symbol(reference))
+}
+
+/// Looks up the symbol passed to Lua as the light userdata `id`.
+pub fn symbol_from_id(id: u64) -> Option<JsRef> {
+    let registry = js_context::current_conversions()?;
+    let conversions = registry.read().ok()?;
+    conversions.symbols.get(&id).cloned()
+}
@@ -0,0 +1,260 @@
+//! Line coverage for `startCoverage`/`takeCoverage`, recorded by the line hook.
+//!
+//! Hits are kept per chunk name. When the `debug` library is available, the first call of each
+//! function also records its executable lines with zero hits, so lines that never ran show up in
+//! the reports. Without it, or for functions that were never called, only executed lines are
+//! known.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::{Lua, LuaValue};
+use mlua::{Debug, DebugEvent, HookTriggers};
+use neon::prelude::*;
+
+use crate::hooks::{self, chunk_name, Handler};
+use crate::js_traits::ToJs;
+
+// Chunks loaded by lua-js itself, e.g. the filesystem's replacement for `io.open`
+const INTERNAL_PREFIX: &str = "lua-js.";
+
+#[derive(Debug, Default, Clone)]
+struct FileCoverage {
+    // Hits by line
+    lines: BTreeMap<i64, u64>,
+    // (name, calls) by the line functions are defined on
+    functions: BTreeMap<i64, (String, u64)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    files: BTreeMap<String, FileCoverage>,
+    // Functions whose executable lines were already recorded, by chunk name and line defined
+    scanned: HashSet<(String, i64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Arc<Mutex<Coverage>> {
+        Arc::new(Mutex::new(Coverage::default()))
+    }
+
+    /// The call and line hook recording into `coverage`, for `hooks::install`.
+    pub fn handler(coverage: &Arc<Mutex<Coverage>>) -> Handler {
+        let triggers = HookTriggers {
+            on_calls: true,
+            on_returns: false,
+            every_line: true,
+            every_nth_instruction: None,
+        };
+        let coverage = coverage.clone();
+        Handler::new(triggers, move |lua, debug| {
+            if let Ok(mut coverage) = coverage.lock() {
+                coverage.on_event(lua, debug)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// The coverage collected so far.
+    pub fn report(&self) -> Report {
+        Report {
+            files: self.files.clone(),
+        }
+    }
+
+    fn on_event(&mut self, lua: &Lua, debug: &Debug) -> mlua::Result<()> {
+        let source = debug.source();
+        let name = match source.source {
+            Some(source) => chunk_name(source),
+            None => return Ok(()),
+        };
+        if name.starts_with(INTERNAL_PREFIX) || source.what == Some(&b"C"[..]) {
+            return Ok(());
+        }
+        match debug.event() {
+            DebugEvent::Line => {
+                let line = debug.curr_line() as i64;
+                let file = self.files.entry(name).or_default();
+                *file.lines.entry(line).or_insert(0) += 1;
+            }
+            // In 5.1 a tail call is reported as a call, followed by a "tail return"
+            DebugEvent::TailCall if cfg!(any(feature = "lua51", feature = "luajit")) => {}
+            DebugEvent::Call | DebugEvent::TailCall => {
+                let defined = source.line_defined as i64;
+                let is_main = source.what == Some(&b"main"[..]);
+                let function_name = debug
+                    .names()
+                    .name
+                    .map(|name| String::from_utf8_lossy(name).into_owned());
+                let key = (name.clone(), defined);
+                let file = self.files.entry(name).or_default();
+                if !is_main {
+                    let entry = file.functions.entry(defined).or_insert_with(|| {
+                        (
+                            function_name.unwrap_or_else(|| format!("(anonymous:{})", defined)),
+                            0,
+                        )
+                    });
+                    entry.1 += 1;
+                }
+                if self.scanned.insert(key) {
+                    for line in active_lines(lua)? {
+                        file.lines.entry(line).or_insert(0);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Lines with code in the function the hook interrupted, from `debug.getinfo(1, "L")`.
+fn active_lines(lua: &Lua) -> mlua::Result<Vec<i64>> {
+    let debug = match hooks::debug_library(lua)? {
+        Some(debug) => debug,
+        None => return Ok(vec![]),
+    };
+    let getinfo: mlua::Function = debug.get("getinfo")?;
+    // Level 0 is getinfo itself
+    let info: LuaValue = getinfo.call((1, "L"))?;
+    let mut lines = vec![];
+    if let LuaValue::Table(info) = info {
+        if let LuaValue::Table(active) = info.get::<_, LuaValue>("activelines")? {
+            for pair in active.pairs::<i64, LuaValue>() {
+                lines.push(pair?.0);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// Coverage returned to JS by `takeCoverage`.
+pub struct Report {
+    files: BTreeMap<String, FileCoverage>,
+}
+
+impl Report {
+    /// The LCOV tracefile format read by genhtml, Codecov and most CI integrations.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (path, file) in &self.files {
+            let _ = writeln!(lcov, "TN:");
+            let _ = writeln!(lcov, "SF:{}", path);
+            for (line, (name, _)) in &file.functions {
+                let _ = writeln!(lcov, "FN:{},{}", line, name);
+            }
+            for (name, calls) in file.functions.values() {
+                let _ = writeln!(lcov, "FNDA:{},{}", calls, name);
+            }
+            let _ = writeln!(lcov, "FNF:{}", file.functions.len());
+            let hit = file.functions.values().filter(|(_, calls)| *calls > 0).count();
+            let _ = writeln!(lcov, "FNH:{}", hit);
+            for (line, hits) in &file.lines {
+                let _ = writeln!(lcov, "DA:{},{}", line, hits);
+            }
+            let _ = writeln!(lcov, "LF:{}", file.lines.len());
+            let hit = file.lines.values().filter(|hits| **hits > 0).count();
+            let _ = writeln!(lcov, "LH:{}", hit);
+            let _ = writeln!(lcov, "end_of_record");
+        }
+        lcov
+    }
+
+    /// Istanbul's coverage JSON, as written to `coverage-final.json` by nyc. Every line is a
+    /// statement, and there is no branch data.
+    fn istanbul<'a, CX: Context<'a>>(&self, cx: &mut CX) -> JsResult<'a, JsValue> {
+        let result = cx.empty_object();
+        for (path, file) in &self.files {
+            let statement_map = cx.empty_object();
+            let statements = cx.empty_object();
+            for (i, (line, hits)) in file.lines.iter().enumerate() {
+                let location = location(cx, *line)?;
+                statement_map.set(cx, i.to_string().as_str(), location)?;
+                let value = cx.number(*hits as f64);
+                statements.set(cx, i.to_string().as_str(), value)?;
+            }
+            let fn_map = cx.empty_object();
+            let functions = cx.empty_object();
+            for (i, (line, (name, calls))) in file.functions.iter().enumerate() {
+                let function = cx.empty_object();
+                let value = cx.string(name);
+                function.set(cx, "name", value)?;
+                let value = location(cx, *line)?;
+                function.set(cx, "decl", value)?;
+                let value = location(cx, *line)?;
+                function.set(cx, "loc", value)?;
+                let value = cx.number(*line as f64);
+                function.set(cx, "line", value)?;
+                fn_map.set(cx, i.to_string().as_str(), function)?;
+                let value = cx.number(*calls as f64);
+                functions.set(cx, i.to_string().as_str(), value)?;
+            }
+
+            let entry = cx.empty_object();
+            let value = cx.string(path);
+            entry.set(cx, "path", value)?;
+            entry.set(cx, "statementMap", statement_map)?;
+            entry.set(cx, "fnMap", fn_map)?;
+            let value = cx.empty_object();
+            entry.set(cx, "branchMap", value)?;
+            entry.set(cx, "s", statements)?;
+            entry.set(cx, "f", functions)?;
+            let value = cx.empty_object();
+            entry.set(cx, "b", value)?;
+            result.set(cx, path.as_str(), entry)?;
+        }
+        Ok(result.upcast())
+    }
+}
+
+/// An Istanbul location spanning the whole line.
+fn location<'a, CX: Context<'a>>(cx: &mut CX, line: i64) -> JsResult<'a, JsObject> {
+    let location = cx.empty_object();
+    for (key, column) in &[("start", 0.0), ("end", f64::from(u32::MAX))] {
+        let position = cx.empty_object();
+        let value = cx.number(line as f64);
+        position.set(cx, "line", value)?;
+        let value = cx.number(*column);
+        position.set(cx, "column", value)?;
+        location.set(cx, *key, position)?;
+    }
+    Ok(location)
+}
+
+impl ToJs for Report {
+    /// `{ files, lcov, istanbul }`, where `files` maps chunk names to `{ lines, functions }`.
+    fn to_js<'a, CX: Context<'a>>(&self, cx: &mut CX) -> JsResult<'a, JsValue> {
+        let files = cx.empty_object();
+        for (path, file) in &self.files {
+            let lines = cx.empty_object();
+            for (line, hits) in &file.lines {
+                let value = cx.number(*hits as f64);
+                lines.set(cx, *line as u32, value)?;
+            }
+            let functions = JsArray::new(cx, file.functions.len() as u32);
+            for (i, (line, (name, calls))) in file.functions.iter().enumerate() {
+                let function = cx.empty_object();
+                let value = cx.string(name);
+                function.set(cx, "name", value)?;
+                let value = cx.number(*line as f64);
+                function.set(cx, "line", value)?;
+                let value = cx.number(*calls as f64);
+                function.set(cx, "calls", value)?;
+                functions.set(cx, i as u32, function)?;
+            }
+            let entry = cx.empty_object();
+            entry.set(cx, "lines", lines)?;
+            entry.set(cx, "functions", functions)?;
+            files.set(cx, path.as_str(), entry)?;
+        }
+
+        let result = cx.empty_object();
+        result.set(cx, "files", files)?;
+        let value = cx.string(self.lcov());
+        result.set(cx, "lcov", value)?;
+        let istanbul = self.istanbul(cx)?;
+        result.set(cx, "istanbul", istanbul)?;
+        Ok(result.upcast())
+    }
+}
@@ -0,0 +1,149 @@
+-- Stack inspection for the debugger, built on the `debug` library captured when the state was
+-- created. The functions below are called directly from the Rust hook, so inside them level 1
+-- is the function itself and level 2 is the function the hook interrupted. Frame 0 is that
+-- interrupted function.
+local debug, globals = ...
+local getinfo, getlocal, getupvalue = debug.getinfo, debug.getlocal, debug.getupvalue
+local load, loadstring, setfenv, getfenv = load, loadstring, setfenv, getfenv
+local next, pcall, setmetatable, tostring, type = next, pcall, setmetatable, tostring, type
+
+local MAX_CHILDREN = 1000
+
+local helpers = {}
+-- Tables shown while paused, by variables reference
+local refs, next_ref = {}, 1
+
+local function describe(name, value)
+    local kind = type(value)
+    local ref = 0
+    if kind == "table" then
+        ref = next_ref
+        refs[ref] = value
+        next_ref = next_ref + 1
+    end
+    local text
+    if kind == "string" then
+        text = '"' .. value .. '"'
+    else
+        local ok, result = pcall(tostring, value)
+        text = ok and result or kind
+    end
+    return { name = name, value = text, type = kind, ref = ref }
+end
+
+function helpers.reset()
+    refs, next_ref = {}, 1
+end
+
+function helpers.depth()
+    local level = 2
+    while getinfo(level, "l") do
+        level = level + 1
+    end
+    return level - 2
+end
+
+function helpers.stack()
+    local frames = {}
+    local level = 2
+    while true do
+        local info = getinfo(level, "nSl")
+        if not info then break end
+        local name = info.name
+        if not name then
+            name = info.what == "main" and "main chunk" or "?"
+        end
+        frames[#frames + 1] = {
+            name = name,
+            source = info.source,
+            line = info.currentline,
+            c = info.what == "C",
+        }
+        level = level + 1
+    end
+    return frames
+end
+
+function helpers.locals(frame)
+    local variables = {}
+    local i = 1
+    while true do
+        local name, value = getlocal(frame + 2, i)
+        if not name then break end
+        variables[#variables + 1] = describe(name, value)
+        i = i + 1
+    end
+    return variables
+end
+
+function helpers.upvalues(frame)
+    local variables = {}
+    local info = getinfo(frame + 2, "f")
+    if info and info.func then
+        local i = 1
+        while true do
+            local name, value = getupvalue(info.func, i)
+            if not name then break end
+            variables[#variables + 1] = describe(name, value)
+            i = i + 1
+        end
+    end
+    return variables
+end
+
+function helpers.children(ref)
+    local variables = {}
+    local t = refs[ref]
+    if t then
+        for key, value in next, t do
+            local name = type(key) == "string" and key or "[" .. tostring(key) .. "]"
+            variables[#variables + 1] = describe(name, value)
+            if #variables >= MAX_CHILDREN then break end
+        end
+    end
+    return variables
+end
+
+-- Evaluates `expression` with the frame's upvalues and locals in scope, falling back to its
+-- environment. Returns false and a message on failure.
+function helpers.evaluate(frame, expression)
+    local level = frame + 2
+    local info = getinfo(level, "f")
+    if not info then return false, "invalid frame" end
+    local scope = {}
+    -- Upvalues first, so locals shadow them
+    if info.func then
+        local i = 1
+        while true do
+            local name, value = getupvalue(info.func, i)
+            if not name then break end
+            scope[name] = value
+            i = i + 1
+        end
+    end
+    local i = 1
+    while true do
+        local name, value = getlocal(level, i)
+        if not name then break end
+        scope[name] = value
+        i = i + 1
+    end
+    local environment = scope._ENV or (getfenv and getfenv(info.func)) or globals
+    setmetatable(scope, { __index = environment })
+
+    local chunk, err
+    if setfenv and loadstring then
+        chunk, err = loadstring("return " .. expression, "=eval")
+        if not chunk then chunk, err = loadstring(expression, "=eval") end
+        if chunk then setfenv(chunk, scope) end
+    else
+        chunk, err = load("return " .. expression, "=eval", "t", scope)
+        if not chunk then chunk, err = load(expression, "=eval", "t", scope) end
+    end
+    if not chunk then return false, err end
+    local ok, result = pcall(chunk)
+    if not ok then return false, tostring(result) end
+    return true, describe("result", result)
+end
+
+return helpers
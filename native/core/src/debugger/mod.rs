@@ -0,0 +1,596 @@
+//! Debug Adapter Protocol server for stepping through Lua scripts, enabled with the `debugger`
+//! feature.
+//!
+//! A background thread accepts one client at a time over TCP (or talks over stdio) and handles
+//! the requests it can answer on its own, like `setBreakpoints`. Execution is driven by a line
+//! hook on the Lua state: when it hits a breakpoint or finishes a step, the hook blocks the
+//! thread running Lua and serves stack, variable and evaluation requests forwarded by the
+//! background thread until the client resumes.
+//!
+//! Inspecting frames needs the `debug` library, which is captured when the state is created so a
+//! sandbox removing the global doesn't get in the way.
+mod protocol;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use mlua::prelude::{Lua, LuaTable, LuaValue};
+use mlua::{Debug, DebugEvent, Function, HookTriggers};
+use serde_json::{json, Map, Value as Json};
+
+use crate::hooks::{self, chunk_name, Handler};
+use protocol::{read_message, Output};
+
+const HELPERS_KEY: &str = "lua-js.debugger.helpers";
+const INSPECT: &str = include_str!("inspect.lua");
+
+// Variables references below this are scopes (two per frame), above it tables.
+const TABLE_REFERENCE_BASE: i64 = 1_000_000;
+const THREAD_ID: i64 = 1;
+
+pub enum Transport {
+    Tcp(SocketAddr),
+    Stdio,
+}
+
+enum Mode {
+    Run,
+    StepIn,
+    // Stops once the stack is at most / less than this deep
+    StepOver(i64),
+    StepOut(i64),
+}
+
+struct State {
+    // Lines by source path, as sent by the client
+    breakpoints: HashMap<String, HashSet<i64>>,
+    mode: Mode,
+    pause_requested: bool,
+    paused: bool,
+    configured: bool,
+}
+
+pub struct Debugger {
+    state: Mutex<State>,
+    configured: Condvar,
+    output: Output,
+    // Requests that need the paused Lua thread
+    requests: Mutex<Receiver<Json>>,
+    forward: Mutex<Sender<Json>>,
+    // Set while the hook is running inspection code, which mustn't trigger the hook again
+    inspecting: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl Debugger {
+    /// Starts listening, and attaches to `lua`. Returns the bound port for TCP transports.
+    pub fn start(lua: &Lua, transport: Transport) -> Result<(Arc<Debugger>, Option<u16>), String> {
+        let (forward, requests) = channel();
+        let debugger = Arc::new(Debugger {
+            state: Mutex::new(State {
+                breakpoints: HashMap::new(),
+                mode: Mode::Run,
+                pause_requested: false,
+                paused: false,
+                configured: false,
+            }),
+            configured: Condvar::new(),
+            output: Output::new(),
+            requests: Mutex::new(requests),
+            forward: Mutex::new(forward),
+            inspecting: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        });
+        debugger.attach(lua).map_err(|e| e.to_string())?;
+
+        let port = match transport {
+            Transport::Tcp(address) => {
+                let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+                let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+                // Polled, so `stop` can end the thread
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                let server = debugger.clone();
+                thread::spawn(move || server.accept(listener));
+                Some(port)
+            }
+            Transport::Stdio => {
+                let server = debugger.clone();
+                server.output.connect(Box::new(io::stdout()));
+                thread::spawn(move || server.serve(BufReader::new(io::stdin())));
+                None
+            }
+        };
+        Ok((debugger, port))
+    }
+
+    /// Loads the inspection helpers into `lua`, e.g. after the state was reset. The hook itself is
+    /// the `handler`.
+    pub fn attach(self: &Arc<Self>, lua: &Lua) -> mlua::Result<()> {
+        match hooks::debug_library(lua)? {
+            Some(debug) => {
+                let helpers: LuaTable = lua
+                    .load(INSPECT)
+                    .set_name("=lua-js.debugger")?
+                    .into_function()?
+                    .call((debug, lua.globals()))?;
+                lua.set_named_registry_value(HELPERS_KEY, helpers)?;
+            }
+            None => {
+                return Err(mlua::Error::RuntimeError(String::from(
+                    "the debugger needs the debug library, which requires the `unsafe: true` option",
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// The line hook pausing at breakpoints and steps, for `hooks::install`.
+    pub fn handler(self: &Arc<Self>) -> Handler {
+        let triggers = HookTriggers {
+            on_calls: false,
+            on_returns: false,
+            every_line: true,
+            every_nth_instruction: None,
+        };
+        let debugger = self.clone();
+        Handler::new(triggers, move |lua, debug| debugger.on_line(lua, debug))
+    }
+
+    /// Stops serving clients and lets paused or future executions run freely. The caller removes
+    /// the handler from the state's hook.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.output.disconnect();
+        if let Ok(mut state) = self.state.lock() {
+            state.breakpoints.clear();
+            state.mode = Mode::Run;
+            state.configured = true;
+        }
+        self.configured.notify_all();
+    }
+
+    /// Blocks until a client has sent `configurationDone`, so breakpoints are set before any
+    /// code runs.
+    pub fn wait_for_configuration(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            while !state.configured {
+                state = match self.configured.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                };
+            }
+        }
+    }
+
+    fn accept(self: Arc<Self>, listener: TcpListener) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nonblocking(false);
+                    let writer = match stream.try_clone() {
+                        Ok(writer) => writer,
+                        Err(_) => continue,
+                    };
+                    self.output.connect(Box::new(writer));
+                    self.serve(BufReader::new(stream));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Handles a client's requests until it disconnects.
+    fn serve<R: io::BufRead>(&self, mut reader: R) {
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let request = match read_message(&mut reader) {
+                Ok(Some(request)) => request,
+                _ => break,
+            };
+            if request["type"] == "request" {
+                self.handle_request(request);
+            }
+        }
+        // Don't leave the script paused without a client
+        self.output.disconnect();
+        self.forward_request(json!({ "type": "request", "command": "disconnect", "seq": 0 }));
+    }
+
+    fn forward_request(&self, request: Json) {
+        if let Ok(forward) = self.forward.lock() {
+            let _ = forward.send(request);
+        }
+    }
+
+    fn handle_request(&self, request: Json) {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        match command.as_str() {
+            "initialize" => {
+                self.output.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                );
+                self.output.event("initialized", json!({}));
+            }
+            "launch" | "attach" => self.output.respond(&request, json!({})),
+            "setBreakpoints" => {
+                let arguments = &request["arguments"];
+                let source = arguments["source"]["path"]
+                    .as_str()
+                    .or_else(|| arguments["source"]["name"].as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let lines: Vec<i64> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_i64())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if let Ok(mut state) = self.state.lock() {
+                    state
+                        .breakpoints
+                        .insert(source, lines.iter().cloned().collect());
+                }
+                let breakpoints: Vec<Json> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                self.output
+                    .respond(&request, json!({ "breakpoints": breakpoints }));
+            }
+            "setExceptionBreakpoints" => {
+                self.output.respond(&request, json!({ "breakpoints": [] }))
+            }
+            "configurationDone" => {
+                if let Ok(mut state) = self.state.lock() {
+                    state.configured = true;
+                }
+                self.configured.notify_all();
+                self.output.respond(&request, json!({}));
+            }
+            "threads" => self.output.respond(
+                &request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "Lua" }] }),
+            ),
+            "pause" => {
+                if let Ok(mut state) = self.state.lock() {
+                    state.pause_requested = true;
+                }
+                self.output.respond(&request, json!({}));
+            }
+            _ => {
+                let paused = self.state.lock().map(|state| state.paused).unwrap_or(false);
+                if paused {
+                    self.forward_request(request);
+                } else if command == "disconnect" {
+                    if let Ok(mut state) = self.state.lock() {
+                        state.breakpoints.clear();
+                        state.mode = Mode::Run;
+                    }
+                    self.output.respond(&request, json!({}));
+                } else if command == "continue" {
+                    self.output
+                        .respond(&request, json!({ "allThreadsContinued": true }));
+                } else {
+                    self.output.respond_error(&request, "the script isn't paused");
+                }
+            }
+        }
+    }
+
+    fn on_line(&self, lua: &Lua, debug: &Debug) -> mlua::Result<()> {
+        if self.inspecting.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if !matches!(debug.event(), DebugEvent::Line) {
+            return Ok(());
+        }
+        let line = debug.curr_line() as i64;
+        let source = debug.source().source.map(chunk_name);
+        enum Check {
+            Stop(&'static str),
+            Depth(i64, bool),
+            Continue,
+        }
+        let check = {
+            let mut state = match self.state.lock() {
+                Ok(state) => state,
+                Err(_) => return Ok(()),
+            };
+            let at_breakpoint = match &source {
+                Some(source) => state.breakpoints.iter().any(|(path, lines)| {
+                    lines.contains(&line) && source_matches(path, source)
+                }),
+                None => false,
+            };
+            if state.pause_requested {
+                state.pause_requested = false;
+                Check::Stop("pause")
+            } else if at_breakpoint {
+                Check::Stop("breakpoint")
+            } else {
+                match state.mode {
+                    Mode::Run => Check::Continue,
+                    Mode::StepIn => Check::Stop("step"),
+                    Mode::StepOver(depth) => Check::Depth(depth, false),
+                    Mode::StepOut(depth) => Check::Depth(depth, true),
+                }
+            }
+        };
+        let reason = match check {
+            Check::Stop(reason) => reason,
+            Check::Continue => return Ok(()),
+            Check::Depth(target, out) => {
+                let depth = self.inspect(|| self.depth(lua))?;
+                if depth < target || (!out && depth == target) {
+                    "step"
+                } else {
+                    return Ok(());
+                }
+            }
+        };
+        self.inspect(|| self.pause(lua, reason))
+    }
+
+    /// Runs `f` without triggering the hook from the inspection code.
+    fn inspect<T, F: FnOnce() -> mlua::Result<T>>(&self, f: F) -> mlua::Result<T> {
+        self.inspecting.store(true, Ordering::SeqCst);
+        let result = f();
+        self.inspecting.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn helper<'lua>(&self, lua: &'lua Lua, name: &str) -> mlua::Result<Function<'lua>> {
+        let helpers: LuaTable = lua.named_registry_value(HELPERS_KEY)?;
+        helpers.get(name)
+    }
+
+    fn depth(&self, lua: &Lua) -> mlua::Result<i64> {
+        self.helper(lua, "depth")?.call(())
+    }
+
+    /// Serves requests for the paused script until the client resumes it.
+    fn pause(&self, lua: &Lua, reason: &str) -> mlua::Result<()> {
+        self.helper(lua, "reset")?.call::<_, ()>(())?;
+        let requests = match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(_) => return Ok(()),
+        };
+        // Anything queued was meant for an earlier pause
+        while let Ok(request) = requests.try_recv() {
+            self.output.respond_error(&request, "the script isn't paused");
+        }
+        if let Ok(mut state) = self.state.lock() {
+            state.paused = true;
+        }
+        self.output.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        let resume = loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                break Mode::Run;
+            }
+            let request = match requests.recv_timeout(Duration::from_millis(100)) {
+                Ok(request) => request,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(_) => break Mode::Run,
+            };
+            let command = request["command"].as_str().unwrap_or("");
+            let arguments = &request["arguments"];
+            let frame = arguments["frameId"].as_i64().unwrap_or(0);
+            let result = match command {
+                "continue" => {
+                    self.output
+                        .respond(&request, json!({ "allThreadsContinued": true }));
+                    break Mode::Run;
+                }
+                "next" => {
+                    let depth = self.depth(lua)?;
+                    self.output.respond(&request, json!({}));
+                    break Mode::StepOver(depth);
+                }
+                "stepIn" => {
+                    self.output.respond(&request, json!({}));
+                    break Mode::StepIn;
+                }
+                "stepOut" => {
+                    let depth = self.depth(lua)?;
+                    self.output.respond(&request, json!({}));
+                    break Mode::StepOut(depth);
+                }
+                "disconnect" => {
+                    if let Ok(mut state) = self.state.lock() {
+                        state.breakpoints.clear();
+                    }
+                    self.output.respond(&request, json!({}));
+                    break Mode::Run;
+                }
+                "stackTrace" => self.stack_trace(lua),
+                "scopes" => Ok(scopes(frame)),
+                "variables" => {
+                    let reference = arguments["variablesReference"].as_i64().unwrap_or(0);
+                    self.variables(lua, reference)
+                }
+                "evaluate" => {
+                    let expression = arguments["expression"].as_str().unwrap_or("");
+                    self.evaluate(lua, frame, expression)
+                }
+                _ => Err(format!("unsupported request '{}'", command)),
+            };
+            match result {
+                Ok(body) => self.output.respond(&request, body),
+                Err(message) => self.output.respond_error(&request, &message),
+            }
+        };
+
+        if let Ok(mut state) = self.state.lock() {
+            state.paused = false;
+            state.mode = resume;
+        }
+        self.output
+            .event("continued", json!({ "threadId": THREAD_ID, "allThreadsContinued": true }));
+        Ok(())
+    }
+
+    fn stack_trace(&self, lua: &Lua) -> Result<Json, String> {
+        let frames = self
+            .helper(lua, "stack")
+            .and_then(|stack| stack.call::<_, LuaValue>(()))
+            .map_err(|e| e.to_string())?;
+        let frames: Vec<Json> = match to_json(frames) {
+            Json::Array(frames) => frames,
+            _ => vec![],
+        };
+        let frames: Vec<Json> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let mut stack_frame = json!({
+                    "id": id,
+                    "name": frame["name"],
+                    "line": frame["line"].as_i64().unwrap_or(0).max(0),
+                    "column": 1,
+                });
+                if frame["c"] == json!(true) {
+                    stack_frame["presentationHint"] = json!("subtle");
+                } else if let Some(source) = frame["source"].as_str() {
+                    let name = chunk_name(source.as_bytes());
+                    stack_frame["source"] = json!({ "name": name, "path": name });
+                }
+                stack_frame
+            })
+            .collect();
+        Ok(json!({ "totalFrames": frames.len(), "stackFrames": frames }))
+    }
+
+    fn variables(&self, lua: &Lua, reference: i64) -> Result<Json, String> {
+        let (helper, argument) = if reference >= TABLE_REFERENCE_BASE {
+            ("children", reference - TABLE_REFERENCE_BASE)
+        } else if reference % 2 == 1 {
+            ("locals", (reference - 1) / 2)
+        } else {
+            ("upvalues", (reference - 2) / 2)
+        };
+        let variables = self
+            .helper(lua, helper)
+            .and_then(|f| f.call::<_, LuaValue>(argument))
+            .map_err(|e| e.to_string())?;
+        let variables: Vec<Json> = match to_json(variables) {
+            Json::Array(variables) => variables,
+            _ => vec![],
+        };
+        let variables: Vec<Json> = variables
+            .into_iter()
+            // Lua's internal locals, e.g. `(for index)` or `(temporary)`
+            .filter(|variable| {
+                !variable["name"]
+                    .as_str()
+                    .map(|name| name.starts_with('(') || name.is_empty())
+                    .unwrap_or(true)
+            })
+            .map(|variable| {
+                json!({
+                    "name": variable["name"],
+                    "value": variable["value"],
+                    "type": variable["type"],
+                    "variablesReference": variables_reference(&variable),
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&self, lua: &Lua, frame: i64, expression: &str) -> Result<Json, String> {
+        let (ok, result) = self
+            .helper(lua, "evaluate")
+            .and_then(|f| f.call::<_, (bool, LuaValue)>((frame, expression)))
+            .map_err(|e| e.to_string())?;
+        let result = to_json(result);
+        if !ok {
+            return Err(result.as_str().unwrap_or("evaluation failed").to_string());
+        }
+        Ok(json!({
+            "result": result["value"],
+            "type": result["type"],
+            "variablesReference": variables_reference(&result),
+        }))
+    }
+}
+
+fn scopes(frame: i64) -> Json {
+    json!({
+        "scopes": [
+            { "name": "Locals", "variablesReference": frame * 2 + 1, "expensive": false },
+            { "name": "Upvalues", "variablesReference": frame * 2 + 2, "expensive": false },
+        ]
+    })
+}
+
+fn variables_reference(variable: &Json) -> i64 {
+    match variable["ref"].as_i64() {
+        Some(reference) if reference > 0 => reference + TABLE_REFERENCE_BASE,
+        _ => 0,
+    }
+}
+
+/// Chunk names are usually relative paths, while clients send absolute ones.
+fn source_matches(path: &str, chunk: &str) -> bool {
+    path == chunk
+        || path.ends_with(&format!("/{}", chunk.trim_start_matches("./")))
+        || chunk.ends_with(&format!("/{}", path))
+}
+
+/// Converts the plain data returned by the inspection helpers.
+fn to_json(value: LuaValue) -> Json {
+    match value {
+        LuaValue::Nil => Json::Null,
+        LuaValue::Boolean(b) => json!(b),
+        LuaValue::Integer(i) => json!(i),
+        LuaValue::Number(f) => json!(f),
+        LuaValue::String(s) => json!(String::from_utf8_lossy(s.as_bytes())),
+        LuaValue::Table(table) => {
+            if table.raw_len() > 0 {
+                Json::Array(
+                    table
+                        .sequence_values::<LuaValue>()
+                        .filter_map(|value| value.ok())
+                        .map(to_json)
+                        .collect(),
+                )
+            } else {
+                let mut object = Map::new();
+                for pair in table.pairs::<String, LuaValue>() {
+                    if let Ok((key, value)) = pair {
+                        object.insert(key, to_json(value));
+                    }
+                }
+                // Empty tables are arrays of variables or frames
+                if object.is_empty() {
+                    Json::Array(vec![])
+                } else {
+                    Json::Object(object)
+                }
+            }
+        }
+        _ => Json::Null,
+    }
+}
@@ -0,0 +1,107 @@
+//! Wire format of the Debug Adapter Protocol: JSON messages preceded by a `Content-Length`
+//! header.
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+
+use serde_json::{json, Value as Json};
+
+/// Reads the next message, or returns `None` once the client disconnects.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            // An empty line ends the headers, unless there weren't any yet
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            let length = length.trim().parse::<usize>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length header")
+            })?;
+            content_length = Some(length);
+        }
+    }
+    let mut body = vec![0; content_length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The client connection messages are sent to. Messages are dropped while no client is
+/// connected.
+pub struct Output {
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
+    seq: AtomicI64,
+}
+
+impl Output {
+    pub fn new() -> Self {
+        Output {
+            writer: Mutex::new(None),
+            seq: AtomicI64::new(1),
+        }
+    }
+
+    pub fn connect(&self, writer: Box<dyn Write + Send>) {
+        if let Ok(mut current) = self.writer.lock() {
+            *current = Some(writer);
+        }
+    }
+
+    pub fn disconnect(&self) {
+        if let Ok(mut current) = self.writer.lock() {
+            *current = None;
+        }
+    }
+
+    fn send(&self, mut message: Json) {
+        message["seq"] = json!(self.seq.fetch_add(1, Ordering::Relaxed));
+        let body = message.to_string();
+        if let Ok(mut writer) = self.writer.lock() {
+            let failed = match writer.as_mut() {
+                Some(writer) => {
+                    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+                        .and_then(|_| writer.flush())
+                        .is_err()
+                }
+                None => false,
+            };
+            if failed {
+                *writer = None;
+            }
+        }
+    }
+
+    pub fn event(&self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    pub fn respond(&self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    pub fn respond_error(&self, request: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+}
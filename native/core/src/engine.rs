@@ -0,0 +1,135 @@
+//! A Lua state for Rust code, without neon. Scripts run through the same `lua_execution` paths as
+//! `LuaState`'s, so values convert, errors carry their values and libraries are chosen the same
+//! way: a script behaves the same in a Rust service as it does under Node.
+//!
+//! What needs JS isn't here: registered classes, conversion hooks, events, timers, the
+//! filesystem, debugger, profiler and coverage. `LuaState` starts from an engine and adds those.
+use mlua::{Lua, MultiValue, StdLib};
+
+use crate::error::{Error, Result};
+use crate::libraries;
+use crate::lua_execution;
+use crate::value::Value;
+
+pub struct Engine {
+    libraries: StdLib,
+    lua: Lua,
+}
+
+impl Engine {
+    /// A state with the safe standard libraries, like `createLuaState` without `libraries`.
+    pub fn new() -> Result<Engine> {
+        Engine::with_libraries(StdLib::ALL_SAFE, false)
+    }
+
+    /// A state with `libraries`, e.g. from `std_lib_from_flags`. `debug` and `ffi` are refused
+    /// unless `allow_unsafe` is set, which is the `unsafe: true` option of `createLuaState`.
+    pub fn with_libraries(libraries: StdLib, allow_unsafe: bool) -> Result<Engine> {
+        libraries::check_unsafe(libraries, allow_unsafe)?;
+        let lua = libraries::new_lua(libraries, allow_unsafe)?;
+        Ok(Engine { libraries, lua })
+    }
+
+    /// Adds the `json` library, like the `json: true` option.
+    #[cfg(feature = "json")]
+    pub fn load_json(&self) -> Result<()> {
+        Ok(crate::json::install(&self.lua)?)
+    }
+
+    /// Names of the libraries that were opened, like `loadedLibraries`.
+    pub fn loaded_libraries(&self) -> Vec<String> {
+        libraries::names(self.libraries)
+    }
+
+    /// Runs `code`, like `doStringSync`.
+    pub fn do_string(&self, code: &str, chunk_name: Option<&str>) -> Result<()> {
+        lua_execution::do_string_sync(&self.lua, code.to_owned(), chunk_name.map(String::from))?;
+        Ok(())
+    }
+
+    /// Calls the function `code` evaluates to with `args`, like `callChunk`.
+    pub fn call_chunk(&self, code: &str, chunk_name: Option<&str>, args: Vec<Value>) -> Result<Value> {
+        lua_execution::call_chunk(&self.lua, code.to_owned(), chunk_name.map(String::from), args)
+    }
+
+    /// The global `name`, or `Value::Undefined` if it isn't set.
+    pub fn get_global(&self, name: &str) -> Result<Value> {
+        lua_execution::get_global(&self.lua, name.to_owned())
+    }
+
+    pub fn set_global(&self, name: &str, value: Value) -> Result<()> {
+        lua_execution::set_global(&self.lua, name.to_owned(), value)?;
+        Ok(())
+    }
+
+    /// Sets the global `name` to a function calling `callback`. Unlike `registerFunction`, the
+    /// callback runs synchronously, and its result is returned to Lua. Its errors are raised in
+    /// Lua as strings.
+    pub fn register_function<F>(&self, name: &str, callback: F) -> Result<()>
+    where
+        F: 'static + Send + Fn(Vec<Value>) -> Result<Value>,
+    {
+        let f = self.lua.create_function(move |lua, args: MultiValue| {
+            let values = Value::into_vec_for_lua_multi(args, lua)?;
+            callback(values).map_err(|e: Error| mlua::Error::RuntimeError(e.to_string()))
+        })?;
+        self.lua.globals().set(name, f)?;
+        Ok(())
+    }
+
+    /// The underlying mlua state, for anything this doesn't cover.
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    /// Gives up the state to `LuaState`, which builds the JS side on top of it.
+    #[cfg(feature = "node")]
+    pub(crate) fn into_lua(self) -> Lua {
+        self.lua
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::std_lib_from_flags;
+
+    // The README's example. Lua 5.1, 5.2 and LuaJIT pass numbers as doubles.
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    #[test]
+    fn register_function_and_call_chunk() {
+        let engine = Engine::with_libraries(std_lib_from_flags(&[0x2, 0x10]).unwrap(), false).unwrap();
+        engine
+            .register_function("add", |args| match args.as_slice() {
+                [Value::Integer(a), Value::Integer(b)] => Ok(Value::Integer(a + b)),
+                _ => Err(Error::Lua(String::from("expected two integers"))),
+            })
+            .unwrap();
+        let sum = engine
+            .call_chunk("return function(n) return add(n, 1) end", None, vec![Value::Integer(41)])
+            .unwrap();
+        assert!(matches!(sum, Value::Integer(42)));
+
+        let error = engine
+            .call_chunk("return function() return add('a') end", None, vec![])
+            .unwrap_err();
+        assert!(error.to_string().contains("expected two integers"));
+    }
+
+    #[test]
+    fn globals() {
+        let engine = Engine::new().unwrap();
+        engine.set_global("greeting", Value::String(String::from("hi"))).unwrap();
+        engine.do_string("reply = greeting .. '!'", Some("greet")).unwrap();
+        assert!(matches!(engine.get_global("reply").unwrap(), Value::String(s) if s == "hi!"));
+        assert!(matches!(engine.get_global("missing").unwrap(), Value::Undefined));
+    }
+
+    #[test]
+    fn libraries() {
+        let engine = Engine::with_libraries(std_lib_from_flags(&[0x2, 0x10]).unwrap(), false).unwrap();
+        assert_eq!(engine.loaded_libraries(), vec!["table", "string"]);
+        assert!(Engine::with_libraries(StdLib::DEBUG, false).is_err());
+        assert!(Engine::with_libraries(StdLib::DEBUG, true).is_ok());
+    }
+}
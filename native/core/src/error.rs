@@ -1,4 +1,10 @@
+#[cfg(feature = "node")]
+use crate::js_traits::ToJs;
+use crate::value::Value;
 use mlua::prelude::LuaError;
+#[cfg(feature = "node")]
+use neon::prelude::*;
+#[cfg(feature = "node")]
 use neon::result::Throw;
 use std::fmt::Formatter;
 
@@ -6,6 +12,9 @@ use std::fmt::Formatter;
 pub enum Error {
     Js(String),
     Lua(String),
+    // A Lua error raised with a non-string value, e.g. `error({ code = 404 })`. The value is
+    // attached to the thrown JS error as `value`.
+    LuaValue(String, Value),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,6 +24,25 @@ impl std::fmt::Display for Error {
         match self {
             Error::Lua(e) => write!(f, "{}", e),
             Error::Js(e) => write!(f, "{}", e),
+            Error::LuaValue(e, _) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(feature = "node")]
+impl Error {
+    /// Throws this error as a JS `Error`.
+    pub fn throw<'a, CX: Context<'a>, T>(self, cx: &mut CX) -> NeonResult<T> {
+        match self {
+            Error::LuaValue(message, value) => {
+                let error = JsError::error(cx, message)?;
+                let js_value = value.to_js(cx)?;
+                error.set(cx, "value", js_value)?;
+                cx.throw(error)
+            }
+            e => cx.throw_error(e.to_string()),
         }
     }
 }
@@ -25,6 +53,7 @@ impl From<LuaError> for Error {
     }
 }
 
+#[cfg(feature = "node")]
 impl From<Throw> for Error {
     fn from(err: Throw) -> Self {
         Error::Js(format!("{}", err))
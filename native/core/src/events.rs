@@ -0,0 +1,41 @@
+//! The `emit(name, ...)` global, which hands events from Lua to the listeners added with
+//! `on`/`once` in JS.
+//!
+//! Listeners are kept in JS (see lib/index.js), which gives the state a single dispatch function
+//! the first time a listener is added. Events are dispatched synchronously, so listeners see them
+//! in order and while the script is still running.
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::{Lua, LuaMultiValue};
+
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::value::Value;
+
+/// The JS dispatch function, shared by the state and its `emit` global, and kept across resets.
+pub type Dispatcher = Arc<Mutex<Option<JsRef>>>;
+
+pub fn install(lua: &Lua, dispatcher: &Dispatcher) -> mlua::Result<()> {
+    let dispatcher = dispatcher.clone();
+    let emit = js_context::create_function(lua, move |lua, args: LuaMultiValue| {
+        let values = Value::into_vec_for_lua_multi(args, lua)?;
+        match values.first() {
+            Some(Value::String(_)) => {}
+            _ => {
+                return Err(mlua::Error::RuntimeError(String::from(
+                    "bad argument #1 to 'emit' (event name expected)",
+                )))
+            }
+        }
+        let dispatch = match dispatcher.lock() {
+            Ok(dispatcher) => dispatcher.clone(),
+            Err(_) => None,
+        };
+        match dispatch {
+            // Returns whether there were listeners, like EventEmitter#emit
+            Some(dispatch) => js_context::call(&dispatch, None, &values),
+            None => Ok(Value::Boolean(false)),
+        }
+    })?;
+    lua.globals().set("emit", emit)
+}
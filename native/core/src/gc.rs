@@ -0,0 +1,123 @@
+//! Garbage collector control for the `gc` object on `LuaState`.
+//!
+//! Each version crate only gets what its Lua supports: generational mode is 5.4 only, and Lua 5.1
+//! and LuaJIT can't tell whether the collector is running. Asking for something the version
+//! doesn't have is an error rather than a silent no-op.
+use mlua::Lua;
+
+use crate::value::Value;
+
+/// Settings for `setMode`. Zero leaves a setting as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModeOptions {
+    pub pause: i32,
+    pub step_multiplier: i32,
+    // Log2 of the step size in KB, 5.4 only
+    pub step_size: i32,
+    // Generational mode, 5.4 only
+    pub minor_multiplier: i32,
+    pub major_multiplier: i32,
+}
+
+pub fn collect(lua: &Lua) -> mlua::Result<()> {
+    lua.gc_collect()
+}
+
+/// Runs a step of `kbytes` worth of collection, or a basic step. Returns whether it finished a
+/// cycle.
+pub fn step(lua: &Lua, kbytes: Option<i32>) -> mlua::Result<bool> {
+    match kbytes {
+        Some(kbytes) => lua.gc_step_kbytes(kbytes),
+        None => lua.gc_step(),
+    }
+}
+
+pub fn stop(lua: &Lua) {
+    lua.gc_stop();
+}
+
+pub fn restart(lua: &Lua) {
+    lua.gc_restart();
+}
+
+/// Returns the previous pause.
+pub fn set_pause(lua: &Lua, pause: i32) -> i32 {
+    lua.gc_set_pause(pause)
+}
+
+/// Returns the previous step multiplier.
+pub fn set_step_multiplier(lua: &Lua, step_multiplier: i32) -> i32 {
+    lua.gc_set_step_multiplier(step_multiplier)
+}
+
+/// Switches to `"incremental"` or `"generational"` mode, returning the previous mode on 5.4.
+#[cfg(feature = "lua54")]
+pub fn set_mode(lua: &Lua, mode: &str, options: ModeOptions) -> mlua::Result<Value> {
+    let previous = match mode {
+        "incremental" => lua.gc_inc(options.pause, options.step_multiplier, options.step_size),
+        "generational" => lua.gc_gen(options.minor_multiplier, options.major_multiplier),
+        _ => return Err(unknown_mode(mode)),
+    };
+    Ok(Value::String(String::from(match previous {
+        mlua::GCMode::Incremental => "incremental",
+        mlua::GCMode::Generational => "generational",
+    })))
+}
+
+/// Older versions only have the incremental collector, so this just applies its settings.
+#[cfg(not(feature = "lua54"))]
+pub fn set_mode(lua: &Lua, mode: &str, options: ModeOptions) -> mlua::Result<Value> {
+    let unsupported = |what: &str| {
+        mlua::Error::RuntimeError(format!(
+            "{} is not supported by {}",
+            what,
+            crate::libraries::lua_version()
+        ))
+    };
+    match mode {
+        "incremental" => {}
+        "generational" => return Err(unsupported("generational mode")),
+        _ => return Err(unknown_mode(mode)),
+    }
+    if options.step_size != 0 {
+        return Err(unsupported("stepSize"));
+    }
+    if options.pause != 0 {
+        lua.gc_set_pause(options.pause);
+    }
+    if options.step_multiplier != 0 {
+        lua.gc_set_step_multiplier(options.step_multiplier);
+    }
+    Ok(Value::String(String::from("incremental")))
+}
+
+fn unknown_mode(mode: &str) -> mlua::Error {
+    mlua::Error::RuntimeError(format!(
+        "unknown garbage collector mode '{}', expected 'incremental' or 'generational'",
+        mode
+    ))
+}
+
+/// `{ memory, running }`, with `memory` in bytes. `running` is `null` where Lua can't tell.
+pub fn stats(lua: &Lua) -> Value {
+    Value::ObjectLike(
+        vec![
+            (
+                Value::String(String::from("memory")),
+                Value::Integer(lua.gc_count() as i64),
+            ),
+            (Value::String(String::from("running")), running(lua)),
+        ],
+        vec![],
+    )
+}
+
+#[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+fn running(lua: &Lua) -> Value {
+    Value::Boolean(lua.gc_is_running())
+}
+
+#[cfg(not(any(feature = "lua54", feature = "lua53", feature = "lua52")))]
+fn running(_lua: &Lua) -> Value {
+    Value::Null
+}
@@ -0,0 +1,204 @@
+//! Lua debug hooks calling into JS, for `setHook`/`removeHook`, and the dispatcher they share the
+//! state's hook through.
+//!
+//! The JS callback receives the event along with where it happened. If it throws, the hook
+//! raises the exception in Lua to abort the script. Scripts could catch that with `pcall`, so
+//! every later hook event raises it again until control returns to JS.
+//!
+//! mlua allows a single hook per state, which the user hook, the debugger, coverage and the
+//! profiler all need. Each describes the events it wants as a `Handler`, and `install` sets one
+//! hook asking for all of them that passes each event on to the handlers that asked for it.
+use mlua::prelude::{Lua, LuaTable, LuaValue};
+use mlua::{Debug, DebugEvent, HookTriggers};
+
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::value::Value;
+
+const ABORT_KEY: &str = "lua-js.hook.abort";
+const DEBUG_KEY: &str = "lua-js.debug";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HookOptions {
+    pub line: bool,
+    pub call: bool,
+    pub ret: bool,
+    // Every n VM instructions
+    pub count: Option<u32>,
+}
+
+/// Something sharing the state's hook, and the events it wants.
+pub struct Handler {
+    triggers: HookTriggers,
+    callback: Box<dyn Fn(&Lua, &Debug) -> mlua::Result<()>>,
+}
+
+impl Handler {
+    pub fn new<F>(triggers: HookTriggers, callback: F) -> Handler
+    where
+        F: 'static + Fn(&Lua, &Debug) -> mlua::Result<()>,
+    {
+        Handler {
+            triggers,
+            callback: Box::new(callback),
+        }
+    }
+
+    fn wants(&self, event: DebugEvent) -> bool {
+        match event {
+            DebugEvent::Call | DebugEvent::TailCall => self.triggers.on_calls,
+            DebugEvent::Ret => self.triggers.on_returns,
+            DebugEvent::Line => self.triggers.every_line,
+            DebugEvent::Count => self.triggers.every_nth_instruction.is_some(),
+            DebugEvent::Unknown(_) => false,
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Replaces the state's hook with one serving `handlers`, or removes it if there are none. Count
+/// events come every greatest common divisor of the handlers' counts, and each handler gets
+/// those adding up to its own count.
+pub fn install(lua: &Lua, handlers: Vec<Handler>) -> mlua::Result<()> {
+    if handlers.is_empty() {
+        lua.remove_hook();
+        return Ok(());
+    }
+    let count = handlers
+        .iter()
+        .filter_map(|handler| handler.triggers.every_nth_instruction)
+        .fold(0, gcd);
+    let triggers = HookTriggers {
+        on_calls: handlers.iter().any(|handler| handler.triggers.on_calls),
+        on_returns: handlers.iter().any(|handler| handler.triggers.on_returns),
+        every_line: handlers.iter().any(|handler| handler.triggers.every_line),
+        every_nth_instruction: if count > 0 { Some(count) } else { None },
+    };
+    // Instructions since each handler's last count event
+    let mut instructions = vec![0; handlers.len()];
+    lua.set_hook(triggers, move |lua, debug| {
+        let event = debug.event();
+        for (handler, instructions) in handlers.iter().zip(instructions.iter_mut()) {
+            if !handler.wants(event) {
+                continue;
+            }
+            if let (DebugEvent::Count, Some(every)) = (event, handler.triggers.every_nth_instruction) {
+                *instructions += count;
+                if *instructions < every {
+                    continue;
+                }
+                *instructions = 0;
+            }
+            (handler.callback)(lua, &debug)?;
+        }
+        Ok(())
+    })
+}
+
+/// The handler for a `setHook` callback.
+pub fn user_handler(options: HookOptions, callback: JsRef) -> Handler {
+    let triggers = HookTriggers {
+        on_calls: options.call,
+        on_returns: options.ret,
+        every_line: options.line,
+        every_nth_instruction: options.count,
+    };
+    Handler::new(triggers, move |lua, debug| {
+        if let LuaValue::String(message) = lua.named_registry_value::<_, LuaValue>(ABORT_KEY)? {
+            let message = message.to_str()?.to_owned();
+            return Err(mlua::Error::RuntimeError(message));
+        }
+        let event = describe_event(debug);
+        match js_context::call(&callback, None, &[event]) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let message = match &e {
+                    mlua::Error::ExternalError(exception) => exception.to_string(),
+                    e => e.to_string(),
+                };
+                lua.set_named_registry_value(ABORT_KEY, message)?;
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Re-arms the hook after an abort. Called before running code from JS.
+pub fn clear_abort(lua: &Lua) -> mlua::Result<()> {
+    lua.set_named_registry_value(ABORT_KEY, LuaValue::Nil)
+}
+
+/// Keeps a reference to the `debug` library in the registry, if it was loaded, so a sandbox
+/// removing the global doesn't get in the way of the debugger and coverage.
+pub fn capture_debug_library(lua: &Lua) -> mlua::Result<()> {
+    if let LuaValue::Table(debug) = lua.globals().raw_get::<_, LuaValue>("debug")? {
+        lua.set_named_registry_value(DEBUG_KEY, debug)?;
+    }
+    Ok(())
+}
+
+pub fn debug_library(lua: &Lua) -> mlua::Result<Option<LuaTable>> {
+    match lua.named_registry_value::<_, LuaValue>(DEBUG_KEY)? {
+        LuaValue::Table(debug) => Ok(Some(debug)),
+        _ => Ok(None),
+    }
+}
+
+/// `{ event, source, line, name }` for the JS callback. `line` and `name` are `null` when Lua
+/// doesn't know them, e.g. for calls into C functions.
+fn describe_event(debug: &Debug) -> Value {
+    let event = match debug.event() {
+        DebugEvent::Call => "call",
+        DebugEvent::TailCall => "tailcall",
+        DebugEvent::Ret => "return",
+        DebugEvent::Line => "line",
+        DebugEvent::Count => "count",
+        DebugEvent::Unknown(_) => "unknown",
+    };
+    let source = debug.source();
+    let names = debug.names();
+    let line = debug.curr_line();
+    let pairs = vec![
+        (Value::String(String::from("event")), Value::String(String::from(event))),
+        (
+            Value::String(String::from("source")),
+            optional_string(source.source.map(chunk_name)),
+        ),
+        (
+            Value::String(String::from("line")),
+            if line >= 0 {
+                Value::Integer(line as i64)
+            } else {
+                Value::Null
+            },
+        ),
+        (
+            Value::String(String::from("name")),
+            optional_string(names.name.map(|name| String::from_utf8_lossy(name).into_owned())),
+        ),
+    ];
+    Value::ObjectLike(pairs, vec![])
+}
+
+/// Strips the `@` (file) or `=` (literal) prefix Lua puts on chunk names.
+pub fn chunk_name(source: &[u8]) -> String {
+    let source = String::from_utf8_lossy(source);
+    match source.chars().next() {
+        Some('@') | Some('=') => source[1..].to_string(),
+        _ => source.into_owned(),
+    }
+}
+
+fn optional_string(s: Option<String>) -> Value {
+    match s {
+        Some(s) => Value::String(s),
+        None => Value::Null,
+    }
+}
@@ -0,0 +1,215 @@
+//! Synchronous calls from Lua back into JS.
+//!
+//! Lua code only runs while one of the `JsLuaState` methods is on the stack, so each method that
+//! executes Lua enters a scope holding a pointer to its context. Callbacks created on the Lua side
+//! (userdata methods, constructors, ...) use the innermost scope to call the JS functions they wrap.
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::fmt::Formatter;
+use std::sync::Arc;
+
+use mlua::{Function, Lua, MultiValue};
+
+use neon::prelude::*;
+
+use crate::conversion::ConversionRegistry;
+use crate::js_lua_state::JsLuaState;
+use crate::js_ref::JsRef;
+use crate::js_traits::{FromJs, ToJs};
+use crate::value::Value;
+
+struct Frame {
+    cx: *mut c_void,
+    conversions: ConversionRegistry,
+}
+
+thread_local! {
+    static FRAMES: RefCell<Vec<Frame>> = RefCell::new(vec![]);
+}
+
+/// An exception thrown by a JS function called from Lua, along with the thrown value.
+#[derive(Debug)]
+pub struct JsException {
+    pub message: String,
+    pub value: Value,
+}
+
+impl std::fmt::Display for JsException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for JsException {}
+
+/// Pops the frame pushed by `enter` once the calling method returns.
+pub struct Scope {
+    _private: (),
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        FRAMES.with(|frames| frames.borrow_mut().pop());
+    }
+}
+
+/// Makes `cx` available to Lua callbacks until the returned `Scope` is dropped. The scope must
+/// not outlive `cx`.
+pub fn enter(cx: &mut MethodContext<JsLuaState>, conversions: ConversionRegistry) -> Scope {
+    // Dropped references only need cleaning up eventually, so a failure here isn't fatal.
+    let _ = crate::js_ref::release_dropped(cx);
+    let frame = Frame {
+        cx: cx as *mut MethodContext<JsLuaState> as *mut c_void,
+        conversions,
+    };
+    FRAMES.with(|frames| frames.borrow_mut().push(frame));
+    Scope { _private: () }
+}
+
+/// The conversion configuration of the state driving the current call, if any.
+pub fn current_conversions() -> Option<ConversionRegistry> {
+    FRAMES.with(|frames| {
+        frames
+            .borrow()
+            .last()
+            .map(|frame| frame.conversions.clone())
+    })
+}
+
+/// Runs `f` with the innermost JS context. Exceptions thrown while `f` runs are caught and
+/// returned as a `JsException`.
+pub fn with_context<T, F>(f: F) -> mlua::Result<T>
+where
+    F: for<'b> FnOnce(&mut MethodContext<'b, JsLuaState>) -> NeonResult<T>,
+{
+    let ptr = FRAMES.with(|frames| frames.borrow().last().map(|frame| frame.cx));
+    let ptr = match ptr {
+        Some(ptr) => ptr,
+        None => {
+            return Err(mlua::Error::RuntimeError(
+                "JS functions can only be called while the LuaState is executing".to_string(),
+            ))
+        }
+    };
+    // Safety: the pointer was pushed by `enter`, whose scope is still alive further up the stack.
+    let cx = unsafe { &mut *(ptr as *mut MethodContext<JsLuaState>) };
+
+    let mut output = None;
+    let caught = cx.try_catch(|cx| {
+        output = Some(f(cx)?);
+        Ok(cx.undefined())
+    });
+    match (caught, output) {
+        (Ok(_), Some(output)) => Ok(output),
+        (Err(exception), _) => {
+            let message = exception_message(cx, exception);
+            let mut value = None;
+            let _ = cx.try_catch(|cx| {
+                value = Some(Value::from_js(exception, cx)?);
+                Ok(cx.undefined())
+            });
+            let value = value.unwrap_or_else(|| Value::String(message.clone()));
+            Err(mlua::Error::ExternalError(Arc::new(JsException {
+                message,
+                value,
+            })))
+        }
+        (Ok(_), None) => Err(mlua::Error::RuntimeError(
+            "JS call completed without a result".to_string(),
+        )),
+    }
+}
+
+/// Calls a referenced JS function with `this` bound to `this` (or `undefined`).
+pub fn call(function: &JsRef, this: Option<&Value>, args: &[Value]) -> mlua::Result<Value> {
+    with_context(|cx| {
+        let function = function
+            .to_handle(cx)?
+            .downcast_or_throw::<JsFunction, _>(cx)?;
+        let this = match this {
+            Some(value) => value.to_js(cx)?,
+            None => cx.undefined().upcast(),
+        };
+        let js_args = to_js_args(cx, args)?;
+        let result = function.call(cx, this, js_args)?;
+        Value::from_js(result, cx)
+    })
+}
+
+/// Calls a referenced JS function as a constructor, returning a reference to the new object.
+pub fn construct(constructor: &JsRef, args: &[Value]) -> mlua::Result<JsRef> {
+    with_context(|cx| {
+        let constructor = constructor
+            .to_handle(cx)?
+            .downcast_or_throw::<JsFunction, _>(cx)?;
+        let js_args = to_js_args(cx, args)?;
+        let object = constructor.construct(cx, js_args)?;
+        JsRef::new(cx, object.upcast())
+    })
+}
+
+/// Creates a Lua function from a callback that calls into JS. A `JsException` returned by the
+/// callback is re-raised in Lua with the converted exception as the error value, instead of an
+/// opaque callback error, so `pcall` handlers can inspect it.
+pub fn create_function<'lua, F>(lua: &'lua Lua, f: F) -> mlua::Result<Function<'lua>>
+where
+    F: 'static + Send + for<'cb> Fn(&'cb Lua, MultiValue<'cb>) -> mlua::Result<Value>,
+{
+    let inner = lua.create_function(move |lua, args: MultiValue| match f(lua, args) {
+        Ok(value) => Ok((true, value)),
+        Err(mlua::Error::ExternalError(e)) => match e.downcast_ref::<JsException>() {
+            Some(exception) => Ok((false, exception.value.clone())),
+            None => Err(mlua::Error::ExternalError(e)),
+        },
+        Err(e) => Err(e),
+    })?;
+    rethrow_factory(lua)?.call(inner)
+}
+
+/// Lua wrapper raising the error value returned by functions built with `create_function`. `error`
+/// is captured when the factory is first created, in case scripts remove it.
+fn rethrow_factory(lua: &Lua) -> mlua::Result<Function> {
+    const RETHROW_KEY: &str = "lua-js.rethrow";
+    if let mlua::Value::Function(f) = lua.named_registry_value::<_, mlua::Value>(RETHROW_KEY)? {
+        return Ok(f);
+    }
+    let factory: Function = lua
+        .load(
+            r#"
+            local error = error
+            return function(f)
+                return function(...)
+                    local ok, result = f(...)
+                    if not ok then error(result, 0) end
+                    return result
+                end
+            end
+            "#,
+        )
+        .set_name("=lua-js")?
+        .eval()?;
+    lua.set_named_registry_value(RETHROW_KEY, factory.clone())?;
+    Ok(factory)
+}
+
+fn to_js_args<'a, CX: Context<'a>>(cx: &mut CX, args: &[Value]) -> NeonResult<Vec<Handle<'a, JsValue>>> {
+    let mut js_args = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        js_args.push(arg.to_js(cx)?);
+    }
+    Ok(js_args)
+}
+
+fn exception_message<'a, CX: Context<'a>>(cx: &mut CX, exception: Handle<'a, JsValue>) -> String {
+    if let Ok(error) = exception.downcast::<JsObject>() {
+        if let Ok(message) = error.get(cx, "message") {
+            if let Ok(message) = message.downcast::<JsString>() {
+                return message.value();
+            }
+        }
+    }
+    match exception.to_string(cx) {
+        Ok(message) => message.value(),
+        Err(_) => String::from("unknown JS exception"),
+    }
+}
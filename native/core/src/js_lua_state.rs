@@ -1,9 +1,34 @@
-use std::sync::Arc;
-use std::{fs, thread};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use std::fs;
+#[cfg(feature = "debugger")]
+use std::net::ToSocketAddrs;
 
+use crate::callback_queue::{self, CallQueue};
+use crate::conversion::{
+    ConversionOptions, ConversionRegistry, Conversions, HookTest, SymbolKeys, ToLuaHook,
+};
+#[cfg(feature = "debugger")]
+use crate::debugger::{Debugger, Transport};
+use crate::coverage::Coverage;
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::events::{self, Dispatcher};
+use crate::gc;
+use crate::hooks::{self, HookOptions};
+use crate::js_context;
+use crate::js_ref::JsRef;
 use crate::js_traits::{FromJs, ToJs};
+use crate::libraries;
 use crate::lua_execution;
+use crate::profiler::{self, Profiler};
+use crate::sandbox::{self, Sandbox};
+use crate::snapshot;
+use crate::timers;
+use crate::user_data::UserClass;
 use crate::value::Value;
+use crate::vfs::FileSystem;
 
 use mlua::{Lua, StdLib};
 use neon::context::Context;
@@ -12,125 +37,284 @@ use neon::prelude::*;
 
 use neon::declare_types;
 
-fn lua_version() -> &'static str {
-    if cfg!(feature = "lua54") {
-        "lua54"
-    } else if cfg!(feature = "lua53") {
-        "lua53"
-    } else if cfg!(feature = "lua52") {
-        "lua52"
-    } else if cfg!(feature = "lua51") {
-        "lua51"
-    } else if cfg!(feature = "luajit") {
-        "luajit"
-    } else {
-        panic!("No version specified")
-    }
-}
-
 /// LuaState Class wrapper. Holds on to the lua context reference,
 /// as well as the set of active lua libraries, and (eventually) the registered functions
 pub struct LuaState {
     libraries: StdLib,
-    lua: Arc<Lua>,
+    // Set by the `unsafe: true` option, which allows `debug`, `ffi` and loading C modules
+    allow_unsafe: bool,
+    // Set by the `json: true` option
+    json: bool,
+    // Dropped by `close()`, after which every method throws
+    lua: Option<Arc<Lua>>,
+    conversions: ConversionRegistry,
+    sandbox: Sandbox,
+    filesystem: Option<Arc<FileSystem>>,
+    #[cfg(feature = "debugger")]
+    debugger: Option<Arc<Debugger>>,
+    profiler: Option<Arc<Mutex<Profiler>>>,
+    coverage: Option<Arc<Mutex<Coverage>>>,
+    // Replayed by `reset({ keepRegistrations: true })`
+    registrations: Vec<Registration>,
+    hook: Option<(HookOptions, JsRef)>,
+    events: Dispatcher,
+    // The scheduler behind the `timer` library, if the state has it
+    timers: Option<JsRef>,
+}
+
+type FunctionCallback = Arc<dyn Fn(Vec<Value>) -> mlua::Result<()> + Send + Sync>;
+
+/// Something the host put into the state, which a reset can put back.
+#[derive(Clone)]
+enum Registration {
+    Function(String, FunctionCallback),
+    Class(String, Arc<UserClass>),
+    Global(String, Value),
+    // From `setGlobalPacked`, kept as MessagePack
+    PackedGlobal(String, Arc<Vec<u8>>),
+}
+
+impl Registration {
+    fn name(&self) -> &str {
+        match self {
+            Registration::Function(name, _)
+            | Registration::Class(name, _)
+            | Registration::Global(name, _)
+            | Registration::PackedGlobal(name, _) => name,
+        }
+    }
+
+    fn apply(&self, lua: &Lua) -> crate::error::Result<Value> {
+        match self {
+            Registration::Function(name, callback) => {
+                let callback = callback.clone();
+                lua_execution::register_function(lua, name.clone(), move |values| callback(values))
+            }
+            Registration::Class(name, class) => {
+                lua_execution::register_class(lua, name.clone(), class.clone())
+            }
+            Registration::Global(name, value) => {
+                lua_execution::set_global(lua, name.clone(), value.clone())
+            }
+            Registration::PackedGlobal(name, data) => {
+                lua_execution::set_global_packed(lua, name.clone(), data)
+            }
+        }
+    }
+}
+
+/// Creates a Lua state the way `Engine` does, with the libraries and optionally `json`, then adds
+/// `emit`, installs the filesystem and applies the sandbox.
+fn build_lua(
+    libraries: StdLib,
+    allow_unsafe: bool,
+    json: bool,
+    filesystem: &Option<Arc<FileSystem>>,
+    sandbox: &Sandbox,
+    events: &Dispatcher,
+) -> crate::error::Result<Lua> {
+    let engine = Engine::with_libraries(libraries, allow_unsafe)?;
+    #[cfg(feature = "json")]
+    {
+        if json {
+            engine.load_json()?;
+        }
+    }
+    #[cfg(not(feature = "json"))]
+    let _ = json;
+    let lua = engine.into_lua();
+    hooks::capture_debug_library(&lua)?;
+    events::install(&lua, events)?;
+    // The filesystem captures `load` and friends, so it goes in before the sandbox removes them
+    if let Some(filesystem) = filesystem {
+        filesystem.install(&lua)?;
+    }
+    sandbox.apply(&lua)?;
+    Ok(lua)
 }
 
 impl LuaState {
-    fn reset(&mut self) -> () {
+    fn reset(&mut self, keep_registrations: bool) -> crate::error::Result<()> {
         // By creating a new lua state, we remove all references allowing the js runtime
         // to exit if we've attached any event emitters. Without this, the program won't
         // close. Is there a more explicit way to close event listeners, or is relying on
         // the GC a normal/reasonable approach?
-        let lua = unsafe { Lua::unsafe_new_with(self.libraries) };
-        self.lua = Arc::new(lua)
+        let lua = build_lua(
+            self.libraries,
+            self.allow_unsafe,
+            self.json,
+            &self.filesystem,
+            &self.sandbox,
+            &self.events,
+        )?;
+        if keep_registrations {
+            for registration in &self.registrations {
+                registration.apply(&lua)?;
+            }
+        } else {
+            self.registrations.clear();
+            self.hook = None;
+        }
+        // Timers are cancelled by the caller, the library itself comes back empty
+        if let Some(scheduler) = &self.timers {
+            timers::install(&lua, scheduler)?;
+        }
+        // The debugger outlives the state it's attached to
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(debugger) = &self.debugger {
+                debugger.attach(&lua)?;
+            }
+        }
+        self.lua = Some(Arc::new(lua));
+        // The profile described the old state. Coverage keeps accumulating across resets.
+        self.profiler = None;
+        self.install_hooks()?;
+        // Symbols lived in the old state, but conversion hooks don't depend on it. Classes are
+        // only kept if they were registered again.
+        if let Ok(mut conversions) = self.conversions.write() {
+            if !keep_registrations {
+                conversions.classes.clear();
+            }
+            conversions.symbols.clear();
+        }
+        Ok(())
+    }
+
+    /// Remembers a registration that was applied, replacing any earlier one under the same name.
+    fn remember(&mut self, registration: Registration) {
+        self.registrations
+            .retain(|existing| existing.name() != registration.name());
+        self.registrations.push(registration);
+    }
+
+    /// The Lua state. Only `close` and `isClosed` run without `check_open`, so it's there for
+    /// everything that asks.
+    fn lua(&self) -> &Arc<Lua> {
+        self.lua.as_ref().expect("state is closed")
+    }
+
+    fn is_closed(&self) -> bool {
+        self.lua.is_none()
+    }
+
+    /// Sets the state's hook to serve whichever of the user hook, the debugger, coverage and the
+    /// profiler are active. Called whenever one of them starts or stops.
+    fn install_hooks(&self) -> mlua::Result<()> {
+        let lua = match &self.lua {
+            Some(lua) => lua,
+            None => return Ok(()),
+        };
+        let mut handlers = vec![];
+        if let Some((options, callback)) = &self.hook {
+            handlers.push(hooks::user_handler(*options, callback.clone()));
+        }
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(debugger) = &self.debugger {
+                handlers.push(debugger.handler());
+            }
+        }
+        if let Some(coverage) = &self.coverage {
+            handlers.push(Coverage::handler(coverage));
+        }
+        if let Some(profiler) = &self.profiler {
+            handlers.push(Profiler::handler(profiler));
+        }
+        hooks::install(lua, handlers)
+    }
+
+    #[cfg(feature = "debugger")]
+    fn stop_debugger(&mut self) -> mlua::Result<()> {
+        if let Some(debugger) = self.debugger.take() {
+            debugger.stop();
+        }
+        self.install_hooks()
+    }
+
+    fn stop_coverage(&mut self) -> mlua::Result<()> {
+        self.coverage = None;
+        self.install_hooks()
+    }
+
+    /// Drops the Lua state along with everything holding on to JS: registered functions and
+    /// their `EventHandler`s, classes, hooks and the filesystem.
+    fn close(&mut self) {
+        // The hook goes with the state, so there's nothing to re-install
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(debugger) = self.debugger.take() {
+                debugger.stop();
+            }
+        }
+        self.coverage = None;
+        self.profiler = None;
+        self.registrations.clear();
+        self.hook = None;
+        self.filesystem = None;
+        self.timers = None;
+        if let Ok(mut dispatcher) = self.events.lock() {
+            *dispatcher = None;
+        }
+        if let Ok(mut conversions) = self.conversions.write() {
+            conversions.classes.clear();
+            conversions.symbols.clear();
+        }
+        self.lua = None;
+    }
+
+    /// Names of the libraries that were opened, leaving out any the sandbox removed.
+    fn loaded_libraries(&self) -> Vec<String> {
+        libraries::names(self.libraries)
+            .into_iter()
+            .filter(|name| !self.sandbox.remove.contains(name))
+            .collect()
     }
 }
 
 impl Default for LuaState {
     fn default() -> Self {
+        let events = Dispatcher::default();
+        let lua = build_lua(StdLib::ALL_SAFE, false, false, &None, &Sandbox::default(), &events);
         LuaState {
             libraries: StdLib::ALL_SAFE,
-            lua: Arc::new(Lua::new_with(StdLib::ALL_SAFE).unwrap()),
-        }
-    }
-}
-
-fn flag_into_std_lib(flag: u32) -> Option<StdLib> {
-    const ALL_SAFE: u32 = u32::MAX - 1;
-    match flag {
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-        0x1 => Some(StdLib::COROUTINE),
-        0x2 => Some(StdLib::TABLE),
-        0x4 => Some(StdLib::IO),
-        0x8 => Some(StdLib::OS),
-        0x10 => Some(StdLib::STRING),
-        #[cfg(any(feature = "lua54", feature = "lua53"))]
-        0x20 => Some(StdLib::UTF8),
-        #[cfg(any(feature = "lua52", feature = "luajit"))]
-        0x40 => Some(StdLib::BIT),
-        0x80 => Some(StdLib::MATH),
-        0x100 => Some(StdLib::PACKAGE),
-        #[cfg(any(feature = "luajit"))]
-        0x200 => Some(StdLib::JIT),
-        #[cfg(any(feature = "luajit"))]
-        0x4000_0000 => Some(StdLib::FFI),
-        0x8000_0000 => Some(StdLib::DEBUG),
-        u32::MAX => Some(StdLib::ALL),
-        ALL_SAFE => Some(StdLib::ALL_SAFE),
-        _ => None,
-    }
-}
-
-/// These correspond to our JS Enum. Used for a clearer error notification when including them in
-/// incompatible versions.
-fn flag_to_string(flag: u32) -> String {
-    const ALL_SAFE: u32 = u32::MAX - 1;
-    match flag {
-        0x1 => String::from("Coroutine"),
-        0x2 => String::from("Table"),
-        0x4 => String::from("Io"),
-        0x8 => String::from("Os"),
-        0x10 => String::from("String"),
-        0x20 => String::from("Utf8"),
-        0x40 => String::from("Bit"),
-        0x80 => String::from("Math"),
-        0x100 => String::from("Package"),
-        0x200 => String::from("Jit"),
-        0x4000_0000 => String::from("Ffi"),
-        0x8000_0000 => String::from("Debug"),
-        u32::MAX => String::from("All"),
-        ALL_SAFE => String::from("AllSafe"),
-        _ => flag.to_string(),
+            allow_unsafe: false,
+            json: false,
+            lua: Some(Arc::new(lua.unwrap())),
+            conversions: Arc::new(RwLock::new(Conversions::default())),
+            sandbox: Sandbox::default(),
+            filesystem: None,
+            #[cfg(feature = "debugger")]
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            registrations: vec![],
+            hook: None,
+            events,
+            timers: None,
+        }
     }
 }
 
 fn build_libraries_option(
-    mut cx: CallContext<JsUndefined>,
+    cx: &mut CallContext<JsUndefined>,
     libs: Handle<JsValue>,
 ) -> NeonResult<StdLib> {
     if libs.is_a::<JsArray>() {
         let libflags: Vec<Handle<JsValue>> = libs
-            .downcast_or_throw::<JsArray, CallContext<JsUndefined>>(&mut cx)?
-            .to_vec(&mut cx)?;
-
-        // Hack to get a StdLib(0)
-        let mut libset = StdLib::TABLE ^ StdLib::TABLE;
+            .downcast_or_throw::<JsArray, CallContext<JsUndefined>>(cx)?
+            .to_vec(cx)?;
+        let mut flags = Vec::with_capacity(libflags.len());
         for value in libflags.into_iter() {
             let flag = value
-                .downcast_or_throw::<JsNumber, CallContext<JsUndefined>>(&mut cx)?
+                .downcast_or_throw::<JsNumber, CallContext<JsUndefined>>(cx)?
                 .value() as u32;
-
-            if let Some(lib) = flag_into_std_lib(flag) {
-                libset |= lib;
-            } else {
-                return cx.throw_error(format!(
-                    "unrecognized Library flag \"{}\" for {}",
-                    flag_to_string(flag),
-                    lua_version()
-                ));
-            }
+            flags.push(flag);
+        }
+        match libraries::std_lib_from_flags(&flags) {
+            Ok(libset) => Ok(libset),
+            Err(e) => e.throw(cx),
         }
-        Ok(libset)
     } else if libs.is_a::<JsUndefined>() {
         Ok(StdLib::ALL_SAFE)
     } else {
@@ -138,6 +322,196 @@ fn build_libraries_option(
     }
 }
 
+fn build_conversion_option(
+    cx: &mut CallContext<JsUndefined>,
+    conversion: Handle<JsValue>,
+) -> NeonResult<ConversionOptions> {
+    let mut options = ConversionOptions::default();
+    if conversion.is_a::<JsUndefined>() {
+        return Ok(options);
+    }
+    let conversion = conversion.downcast_or_throw::<JsObject, CallContext<JsUndefined>>(cx)?;
+    let flag = |cx: &mut CallContext<JsUndefined>, key: &str| -> NeonResult<bool> {
+        let value = conversion.get(cx, key)?;
+        if value.is_a::<JsUndefined>() {
+            Ok(false)
+        } else {
+            Ok(value
+                .downcast_or_throw::<JsBoolean, CallContext<JsUndefined>>(cx)?
+                .value())
+        }
+    };
+    options.inherited = flag(cx, "inherited")?;
+    options.getters = flag(cx, "getters")?;
+    options.non_enumerable = flag(cx, "nonEnumerable")?;
+
+    let symbols = conversion.get(cx, "symbols")?;
+    if !symbols.is_a::<JsUndefined>() {
+        let symbols = symbols
+            .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+            .value();
+        options.symbols = match symbols.as_str() {
+            "skip" => SymbolKeys::Skip,
+            "lightuserdata" => SymbolKeys::LightUserData,
+            _ => {
+                return cx.throw_error(format!(
+                    "unrecognized symbols option \"{}\", expected \"skip\" or \"lightuserdata\"",
+                    symbols
+                ))
+            }
+        };
+    }
+    Ok(options)
+}
+
+/// Reads an optional `{ [library]: string[] }` object off of the sandbox options.
+fn library_lists(
+    cx: &mut CallContext<JsUndefined>,
+    options: Handle<JsObject>,
+    key: &str,
+) -> NeonResult<HashMap<String, Vec<String>>> {
+    let mut lists = HashMap::new();
+    let value = options.get(cx, key)?;
+    if value.is_a::<JsUndefined>() {
+        return Ok(lists);
+    }
+    let value = value.downcast_or_throw::<JsObject, CallContext<JsUndefined>>(cx)?;
+    let libraries = value.get_own_property_names(cx)?.to_vec(cx)?;
+    for library in libraries {
+        let library = library
+            .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+            .value();
+        let names = value.get(cx, library.as_str())?;
+        lists.insert(library, string_list(cx, names)?);
+    }
+    Ok(lists)
+}
+
+fn string_list(cx: &mut CallContext<JsUndefined>, value: Handle<JsValue>) -> NeonResult<Vec<String>> {
+    let values = value
+        .downcast_or_throw::<JsArray, CallContext<JsUndefined>>(cx)?
+        .to_vec(cx)?;
+    let mut strings = Vec::with_capacity(values.len());
+    for value in values {
+        strings.push(
+            value
+                .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+                .value(),
+        );
+    }
+    Ok(strings)
+}
+
+/// Builds the sandbox from either a profile name, or an object with an optional `profile`, and
+/// `allow`, `deny` and `remove` lists merged on top of it.
+fn build_sandbox_option(
+    cx: &mut CallContext<JsUndefined>,
+    sandbox: Handle<JsValue>,
+) -> NeonResult<Sandbox> {
+    let profile = |cx: &mut CallContext<JsUndefined>, name: String| match Sandbox::profile(&name) {
+        Some(sandbox) => Ok(sandbox),
+        None => cx.throw_error(format!("unrecognized sandbox profile \"{}\"", name)),
+    };
+    if sandbox.is_a::<JsUndefined>() {
+        Ok(Sandbox::default())
+    } else if sandbox.is_a::<JsString>() {
+        let name = sandbox
+            .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+            .value();
+        profile(cx, name)
+    } else {
+        let options = sandbox.downcast_or_throw::<JsObject, CallContext<JsUndefined>>(cx)?;
+        let name = options.get(cx, "profile")?;
+        let mut sandbox = if name.is_a::<JsUndefined>() {
+            Sandbox::default()
+        } else {
+            let name = name
+                .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+                .value();
+            profile(cx, name)?
+        };
+        let remove = options.get(cx, "remove")?;
+        let remove = if remove.is_a::<JsUndefined>() {
+            vec![]
+        } else {
+            string_list(cx, remove)?
+        };
+        sandbox.merge(Sandbox {
+            allow: library_lists(cx, options, "allow")?,
+            deny: library_lists(cx, options, "deny")?,
+            remove,
+        });
+        Ok(sandbox)
+    }
+}
+
+/// Builds the virtual filesystem from exactly one of `files` (in-memory), `root` (a directory on
+/// disk) or `read`/`write` callbacks, with an optional `readOnly` flag.
+fn build_filesystem_option(
+    cx: &mut CallContext<JsUndefined>,
+    filesystem: Handle<JsValue>,
+) -> NeonResult<Option<Arc<FileSystem>>> {
+    if filesystem.is_a::<JsUndefined>() {
+        return Ok(None);
+    }
+    let options = filesystem.downcast_or_throw::<JsObject, CallContext<JsUndefined>>(cx)?;
+    let read_only = options.get(cx, "readOnly")?;
+    let read_only = if read_only.is_a::<JsUndefined>() {
+        false
+    } else {
+        read_only
+            .downcast_or_throw::<JsBoolean, CallContext<JsUndefined>>(cx)?
+            .value()
+    };
+    let files = options.get(cx, "files")?;
+    let root = options.get(cx, "root")?;
+    let read = options.get(cx, "read")?;
+    let backends = [files, root, read]
+        .iter()
+        .filter(|backend| !backend.is_a::<JsUndefined>())
+        .count();
+    if backends != 1 {
+        return cx.throw_error("'filesystem' needs exactly one of 'files', 'root' or 'read'");
+    }
+
+    let filesystem = if !files.is_a::<JsUndefined>() {
+        let files = files.downcast_or_throw::<JsObject, CallContext<JsUndefined>>(cx)?;
+        let paths = files.get_own_property_names(cx)?.to_vec(cx)?;
+        let mut contents = HashMap::with_capacity(paths.len());
+        for path in paths {
+            let path = path
+                .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+                .value();
+            let file = files
+                .get(cx, path.as_str())?
+                .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+                .value();
+            contents.insert(path, file.into_bytes());
+        }
+        FileSystem::memory(contents, read_only)
+    } else if !root.is_a::<JsUndefined>() {
+        let root = root
+            .downcast_or_throw::<JsString, CallContext<JsUndefined>>(cx)?
+            .value();
+        FileSystem::disk(&root, read_only)
+    } else {
+        let read = read.downcast_or_throw::<JsFunction, CallContext<JsUndefined>>(cx)?;
+        let read = JsRef::new(cx, read.upcast())?;
+        let write = options.get(cx, "write")?;
+        let write = if write.is_a::<JsUndefined>() {
+            None
+        } else {
+            let write = write.downcast_or_throw::<JsFunction, CallContext<JsUndefined>>(cx)?;
+            Some(JsRef::new(cx, write.upcast())?)
+        };
+        Ok(FileSystem::js(read, write, read_only))
+    };
+    match filesystem {
+        Ok(filesystem) => Ok(Some(Arc::new(filesystem))),
+        Err(e) => cx.throw_error(e),
+    }
+}
+
 fn init(mut cx: CallContext<JsUndefined>) -> NeonResult<LuaState> {
     let opt_options = cx.argument_opt(0);
 
@@ -147,16 +521,65 @@ fn init(mut cx: CallContext<JsUndefined>) -> NeonResult<LuaState> {
     let options: Handle<JsObject> = opt_options.unwrap().downcast_or_throw(&mut cx)?;
     let libraries_key = cx.string("libraries");
     let libs = options.get(&mut cx, libraries_key)?;
-    let libraries = build_libraries_option(cx, libs)?;
+    let libraries = build_libraries_option(&mut cx, libs)?;
+    let conversion_key = cx.string("conversion");
+    let conversion = options.get(&mut cx, conversion_key)?;
+    let conversion_options = build_conversion_option(&mut cx, conversion)?;
+    let sandbox_key = cx.string("sandbox");
+    let sandbox = options.get(&mut cx, sandbox_key)?;
+    let sandbox = build_sandbox_option(&mut cx, sandbox)?;
+    let filesystem_key = cx.string("filesystem");
+    let filesystem = options.get(&mut cx, filesystem_key)?;
+    let filesystem = build_filesystem_option(&mut cx, filesystem)?;
+
+    let unsafe_key = cx.string("unsafe");
+    let allow_unsafe = options.get(&mut cx, unsafe_key)?;
+    let allow_unsafe = if allow_unsafe.is_a::<JsUndefined>() {
+        false
+    } else {
+        allow_unsafe
+            .downcast_or_throw::<JsBoolean, CallContext<JsUndefined>>(&mut cx)?
+            .value()
+    };
 
-    // Because we're allowing the end user to dynamically choose their libraries,
-    // we're using the unsafe call in case they include `debug`. We need to notify
-    // the end user in the documentation about the caveats of `debug`.
-    let lua = unsafe {
-        let lua = Lua::unsafe_new_with(libraries);
-        Arc::new(lua)
+    let json_key = cx.string("json");
+    let json = options.get(&mut cx, json_key)?;
+    let json = if json.is_a::<JsUndefined>() {
+        false
+    } else {
+        json.downcast_or_throw::<JsBoolean, CallContext<JsUndefined>>(&mut cx)?
+            .value()
+    };
+    if json && !cfg!(feature = "json") {
+        return cx.throw_error("lua-js was built without the json feature");
+    }
+
+    let events = Dispatcher::default();
+    let lua = match build_lua(libraries, allow_unsafe, json, &filesystem, &sandbox, &events) {
+        Ok(lua) => lua,
+        Err(e) => return e.throw(&mut cx),
     };
-    Ok(LuaState { lua, libraries })
+    let conversions = Conversions {
+        options: conversion_options,
+        ..Conversions::default()
+    };
+    Ok(LuaState {
+        lua: Some(Arc::new(lua)),
+        libraries,
+        allow_unsafe,
+        json,
+        conversions: Arc::new(RwLock::new(conversions)),
+        sandbox,
+        filesystem,
+        #[cfg(feature = "debugger")]
+        debugger: None,
+        profiler: None,
+        coverage: None,
+        registrations: vec![],
+        hook: None,
+        events,
+        timers: None,
+    })
 }
 
 fn do_string_sync(
@@ -165,15 +588,16 @@ fn do_string_sync(
     name: Option<String>,
 ) -> JsResult<JsValue> {
     let this = cx.this();
-    let lua: &Lua = {
+    let (lua, conversions) = {
         let guard = cx.lock();
         let state = this.borrow(&guard);
-        &state.lua.clone()
+        (state.lua().clone(), state.conversions.clone())
     };
 
-    match lua_execution::do_string_sync(lua, code, name) {
+    let _scope = js_context::enter(&mut cx, conversions);
+    match lua_execution::do_string_sync(&lua, code, name) {
         Ok(v) => v.to_js(&mut cx),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => e.throw(&mut cx),
     }
 }
 
@@ -182,9 +606,23 @@ fn do_file_sync(
     filename: String,
     chunk_name: Option<String>,
 ) -> JsResult<JsValue> {
-    match fs::read_to_string(filename) {
+    let this = cx.this();
+    let (filesystem, conversions) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.filesystem.clone(), state.conversions.clone())
+    };
+    let contents = match filesystem {
+        Some(filesystem) => {
+            // JS-backed filesystems call back into JS while reading
+            let _scope = js_context::enter(&mut cx, conversions);
+            filesystem.read_to_string(&filename).map_err(Error::from)
+        }
+        None => fs::read_to_string(filename).map_err(|e| Error::Js(e.to_string())),
+    };
+    match contents {
         Ok(contents) => do_string_sync(cx, contents, chunk_name),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => e.throw(&mut cx),
     }
 }
 
@@ -195,20 +633,21 @@ fn call_chunk<'a>(
     js_args: Handle<'a, JsArray>,
 ) -> JsResult<'a, JsValue> {
     let this = cx.this();
+    let (lua, conversions) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.lua().clone(), state.conversions.clone())
+    };
+    let _scope = js_context::enter(&mut cx, conversions);
     let mut args: Vec<Value> = vec![];
     let js_args = js_args.to_vec(&mut cx)?;
     for arg in js_args.iter() {
         let value = Value::from_js(*arg, &mut cx)?;
         args.push(value);
     }
-    let lua: &Lua = {
-        let guard = cx.lock();
-        let state = this.borrow(&guard);
-        &state.lua.clone()
-    };
     match lua_execution::call_chunk(&lua, code, chunk_name, args) {
         Ok(v) => v.to_js(&mut cx),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => e.throw(&mut cx),
     }
 }
 
@@ -216,36 +655,29 @@ fn register_function<'a>(
     mut cx: MethodContext<'a, JsLuaState>,
     name: String,
     cb: Handle<JsFunction>,
+    options: callback_queue::Options,
 ) -> JsResult<'a, JsValue> {
-    let this = cx.this();
+    let mut this = cx.this();
     let handler = EventHandler::new(&cx, this, cb);
-    let lua: &Lua = {
+    let callback_ref = JsRef::new(&mut cx, cb.upcast())?;
+    let queue = CallQueue::new(handler, callback_ref, options);
+
+    let callback: FunctionCallback = Arc::new(move |values: Vec<Value>| queue.push(values));
+    let registration = Registration::Function(name, callback);
+    let lua = {
         let guard = cx.lock();
         let state = this.borrow(&guard);
-        &state.lua.clone()
-    };
-
-    let callback = move |values: Vec<Value>| {
-        let handler = handler.clone();
-
-        thread::spawn(move || {
-            handler.schedule_with(move |event_ctx, this, callback| {
-                let arr = JsArray::new(event_ctx, values.len() as u32);
-                // TODO remove unwraps, handle errors, and pass to callback if needed.
-                for (i, value) in values.into_iter().enumerate() {
-                    let js_val = value.to_js(event_ctx).unwrap();
-                    arr.set(event_ctx, i as u32, js_val).unwrap();
-                }
-                // TODO How to pass an error via on('error') vs the current setup?
-                let args: Vec<Handle<JsValue>> = vec![arr.upcast()];
-                let _result = callback.call(event_ctx, this, args);
-            });
-        });
+        state.lua().clone()
     };
-    match lua_execution::register_function(lua, name, callback) {
-        Ok(_) => Ok(cx.undefined().upcast()),
-        Err(e) => cx.throw_error(e.to_string()),
+    if let Err(e) = registration.apply(&lua) {
+        return e.throw(&mut cx);
     }
+    {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.remember(registration);
+    }
+    Ok(cx.undefined().upcast())
 }
 
 fn set_global<'a>(
@@ -254,31 +686,730 @@ fn set_global<'a>(
     handle: Handle<'a, JsValue>,
 ) -> JsResult<'a, JsValue> {
     let this: Handle<JsLuaState> = cx.this();
-    let lua: &Lua = {
+    let (lua, conversions) = {
         let guard = cx.lock();
         let state = this.borrow(&guard);
-        &state.lua.clone()
+        (state.lua().clone(), state.conversions.clone())
     };
+    let _scope = js_context::enter(&mut cx, conversions);
     let set_value = Value::from_js(handle, &mut cx)?;
-    match lua_execution::set_global(lua, name, set_value) {
+    let registration = Registration::Global(name, set_value);
+    let value = match registration.apply(&lua) {
+        Ok(v) => v,
+        Err(e) => return e.throw(&mut cx),
+    };
+    {
+        let mut this = cx.this();
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.remember(registration);
+    }
+    value.to_js(&mut cx)
+}
+
+fn set_global_packed<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    name: String,
+    buffer: Handle<'a, JsBuffer>,
+) -> JsResult<'a, JsValue> {
+    let data = cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec());
+    let registration = Registration::PackedGlobal(name, Arc::new(data));
+    let mut this = cx.this();
+    let lua = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.lua().clone()
+    };
+    if let Err(e) = registration.apply(&lua) {
+        return e.throw(&mut cx);
+    }
+    {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.remember(registration);
+    }
+    Ok(cx.undefined().upcast())
+}
+
+fn get_global_packed(mut cx: MethodContext<JsLuaState>, name: String) -> JsResult<JsValue> {
+    let this = cx.this();
+    let lua = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.lua().clone()
+    };
+    match lua_execution::get_global_packed(&lua, name) {
+        Ok(bytes) => to_buffer(&mut cx, &bytes),
+        Err(e) => e.throw(&mut cx),
+    }
+}
+
+fn call_chunk_packed<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    code: String,
+    chunk_name: Option<String>,
+    args: Handle<'a, JsBuffer>,
+) -> JsResult<'a, JsValue> {
+    let args = cx.borrow(&args, |data| data.as_slice::<u8>().to_vec());
+    let this = cx.this();
+    let (lua, conversions) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.lua().clone(), state.conversions.clone())
+    };
+    // The chunk can still call into JS
+    let _scope = js_context::enter(&mut cx, conversions);
+    match lua_execution::call_chunk_packed(&lua, code, chunk_name, &args) {
+        Ok(bytes) => to_buffer(&mut cx, &bytes),
+        Err(e) => e.throw(&mut cx),
+    }
+}
+
+fn get_environment(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let this: Handle<JsLuaState> = cx.this();
+    let lua = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.lua().clone()
+    };
+    match sandbox::environment(&lua) {
+        Ok(v) => v.to_js(&mut cx),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+fn loaded_libraries(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let this: Handle<JsLuaState> = cx.this();
+    let names = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.loaded_libraries()
+    };
+    let array = JsArray::new(&mut cx, names.len() as u32);
+    for (i, name) in names.iter().enumerate() {
+        let name = cx.string(name);
+        array.set(&mut cx, i as u32, name)?;
+    }
+    Ok(array.upcast())
+}
+
+fn set_hook<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    options: Handle<'a, JsObject>,
+    callback: Handle<'a, JsFunction>,
+) -> JsResult<'a, JsValue> {
+    let flag = |cx: &mut MethodContext<'a, JsLuaState>, key: &str| -> NeonResult<bool> {
+        let value = options.get(cx, key)?;
+        if value.is_a::<JsUndefined>() {
+            Ok(false)
+        } else {
+            Ok(value.downcast_or_throw::<JsBoolean, _>(cx)?.value())
+        }
+    };
+    let mut hook_options = HookOptions {
+        line: flag(&mut cx, "line")?,
+        call: flag(&mut cx, "call")?,
+        ret: flag(&mut cx, "return")?,
+        count: None,
+    };
+    let count = options.get(&mut cx, "count")?;
+    if !count.is_a::<JsUndefined>() {
+        let count = count.downcast_or_throw::<JsNumber, _>(&mut cx)?.value();
+        if count < 1.0 || count > u32::MAX as f64 {
+            return cx.throw_range_error("'count' must be a positive number of instructions");
+        }
+        hook_options.count = Some(count as u32);
+    }
+
+    let callback = JsRef::new(&mut cx, callback.upcast())?;
+    let mut this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.hook = Some((hook_options, callback));
+        state.install_hooks()
+    };
+    match result {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+fn remove_hook(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let mut this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.hook = None;
+        state.install_hooks()
+    };
+    match result {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+/// Starts the debugger from `{ port, host, stdio, waitForClient }`, returning the bound port.
+#[cfg(feature = "debugger")]
+fn start_debugger<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    options: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let stdio = options.get(&mut cx, "stdio")?;
+    let stdio = stdio
+        .downcast::<JsBoolean>()
+        .map(|stdio| stdio.value())
+        .unwrap_or(false);
+    let wait = options.get(&mut cx, "waitForClient")?;
+    let wait = wait
+        .downcast::<JsBoolean>()
+        .map(|wait| wait.value())
+        .unwrap_or(false);
+    let transport = if stdio {
+        Transport::Stdio
+    } else {
+        let port = options.get(&mut cx, "port")?;
+        let port = match port.downcast::<JsNumber>() {
+            Ok(port) => port.value() as u16,
+            Err(_) => 0,
+        };
+        let host = options.get(&mut cx, "host")?;
+        let host = match host.downcast::<JsString>() {
+            Ok(host) => host.value(),
+            Err(_) => String::from("127.0.0.1"),
+        };
+        // Host names like "localhost" resolve to their first address
+        match (host.as_str(), port).to_socket_addrs().map(|mut addresses| addresses.next()) {
+            Ok(Some(address)) => Transport::Tcp(address),
+            _ => return cx.throw_error(format!("invalid debugger address {}:{}", host, port)),
+        }
+    };
+
+    let this = cx.this();
+    let (lua, previous) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.lua().clone(), state.debugger.clone())
+    };
+    if let Some(previous) = previous {
+        previous.stop();
+    }
+    let (debugger, port) = match Debugger::start(&lua, transport) {
+        Ok(started) => started,
+        Err(e) => return cx.throw_error(e),
+    };
+    let result = {
+        let mut this = cx.this();
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.debugger = Some(debugger.clone());
+        state.install_hooks()
+    };
+    if let Err(e) = result {
+        return Error::from(e).throw(&mut cx);
+    }
+    if wait {
+        debugger.wait_for_configuration();
+    }
+    match port {
+        Some(port) => Ok(cx.number(port).upcast()),
+        None => Ok(cx.undefined().upcast()),
+    }
+}
+
+#[cfg(not(feature = "debugger"))]
+fn start_debugger<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    _options: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    cx.throw_error("lua-js was built without the debugger feature")
+}
+
+fn stop_debugger(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    #[cfg(feature = "debugger")]
+    {
+        let mut this = cx.this();
+        let result = {
+            let guard = cx.lock();
+            let mut state = this.borrow_mut(&guard);
+            state.stop_debugger()
+        };
+        if let Err(e) = result {
+            return Error::from(e).throw(&mut cx);
+        }
+    }
+    Ok(cx.undefined().upcast())
+}
+
+/// Starts profiling from `{ mode, interval }`, with `interval` in microseconds.
+fn start_profiling<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    options: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let mode = options.get(&mut cx, "mode")?;
+    let mode = if mode.is_a::<JsUndefined>() {
+        profiler::Mode::Sampling
+    } else {
+        let mode = mode.downcast_or_throw::<JsString, _>(&mut cx)?.value();
+        match profiler::Mode::from_str(&mode) {
+            Some(mode) => mode,
+            None => {
+                return cx.throw_type_error(format!(
+                    "unknown profiling mode '{}', expected 'sampling' or 'instrumented'",
+                    mode
+                ))
+            }
+        }
+    };
+    let interval = options.get(&mut cx, "interval")?;
+    let interval = if interval.is_a::<JsUndefined>() {
+        1000.0
+    } else {
+        interval.downcast_or_throw::<JsNumber, _>(&mut cx)?.value()
+    };
+    if !(interval >= 1.0) {
+        return cx.throw_range_error("'interval' must be at least 1 microsecond");
+    }
+
+    let mut this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.profiler = Some(Profiler::new(mode, Duration::from_micros(interval as u64)));
+        state.install_hooks()
+    };
+    match result {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+fn stop_profiling(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let mut this = cx.this();
+    let profile = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        let profile = match state.profiler.take() {
+            Some(profiler) => profiler.lock().ok().map(|mut profiler| profiler.stop()),
+            None => None,
+        };
+        state.install_hooks().map(|_| profile)
+    };
+    match profile {
+        Ok(Some(profile)) => profile.to_js(&mut cx),
+        Ok(None) => cx.throw_error("profiling was not started"),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+fn start_coverage(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let mut this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.coverage = Some(Coverage::new());
+        state.install_hooks()
+    };
+    match result {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+fn take_coverage(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let this = cx.this();
+    let report = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        match &state.coverage {
+            Some(coverage) => coverage.lock().ok().map(|coverage| coverage.report()),
+            None => None,
+        }
+    };
+    match report {
+        Some(report) => report.to_js(&mut cx),
+        None => cx.throw_error("coverage was not started"),
+    }
+}
+
+fn stop_coverage(mut cx: MethodContext<JsLuaState>) -> JsResult<JsValue> {
+    let mut this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.stop_coverage()
+    };
+    match result {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+/// Backs the `gc` object from lib/index.js: `collectGarbage(command, argument?)`, much like Lua's
+/// `collectgarbage`.
+fn collect_garbage<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    command: String,
+    argument: Option<Handle<'a, JsValue>>,
+) -> JsResult<'a, JsValue> {
+    let this = cx.this();
+    let lua = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.lua().clone()
+    };
+    let integer = |cx: &mut MethodContext<'a, JsLuaState>,
+                   value: Option<Handle<'a, JsValue>>,
+                   name: &str|
+     -> NeonResult<Option<i32>> {
+        match value {
+            Some(value) if !value.is_a::<JsUndefined>() => {
+                let number = value.downcast_or_throw::<JsNumber, _>(cx)?.value();
+                if number < 0.0 || number > i32::MAX as f64 {
+                    return cx.throw_range_error(format!("'{}' must be a positive integer", name));
+                }
+                Ok(Some(number as i32))
+            }
+            _ => Ok(None),
+        }
+    };
+    let result = match command.as_str() {
+        "collect" => gc::collect(&lua).map(|_| Value::Undefined),
+        "step" => {
+            let kbytes = integer(&mut cx, argument, "kb")?;
+            gc::step(&lua, kbytes).map(Value::Boolean)
+        }
+        "stop" => {
+            gc::stop(&lua);
+            Ok(Value::Undefined)
+        }
+        "restart" => {
+            gc::restart(&lua);
+            Ok(Value::Undefined)
+        }
+        "setPause" => match integer(&mut cx, argument, "pause")? {
+            Some(pause) => Ok(Value::Integer(gc::set_pause(&lua, pause) as i64)),
+            None => return cx.throw_type_error("expected a pause"),
+        },
+        "setStepMultiplier" => match integer(&mut cx, argument, "stepMultiplier")? {
+            Some(multiplier) => Ok(Value::Integer(
+                gc::set_step_multiplier(&lua, multiplier) as i64,
+            )),
+            None => return cx.throw_type_error("expected a step multiplier"),
+        },
+        "setMode" => {
+            let mode = cx.argument::<JsString>(1)?.value();
+            let options = match cx.argument_opt(2) {
+                Some(options) if !options.is_a::<JsUndefined>() => {
+                    options.downcast_or_throw::<JsObject, _>(&mut cx)?
+                }
+                _ => cx.empty_object(),
+            };
+            let mut mode_options = gc::ModeOptions::default();
+            for (key, setting) in &mut [
+                ("pause", &mut mode_options.pause),
+                ("stepMultiplier", &mut mode_options.step_multiplier),
+                ("stepSize", &mut mode_options.step_size),
+                ("minorMultiplier", &mut mode_options.minor_multiplier),
+                ("majorMultiplier", &mut mode_options.major_multiplier),
+            ] {
+                let value = options.get(&mut cx, *key)?;
+                if let Some(value) = integer(&mut cx, Some(value), *key)? {
+                    **setting = value;
+                }
+            }
+            gc::set_mode(&lua, &mode, mode_options)
+        }
+        "stats" => Ok(gc::stats(&lua)),
+        _ => return cx.throw_type_error(format!("unknown garbage collector command '{}'", command)),
+    };
+    match result {
         Ok(v) => v.to_js(&mut cx),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+/// Serializes the globals into a Buffer, from `{ strict }`.
+fn take_snapshot<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    options: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let strict = options.get(&mut cx, "strict")?;
+    let strict = strict
+        .downcast::<JsBoolean>()
+        .map(|strict| strict.value())
+        .unwrap_or(false);
+    let this = cx.this();
+    let result = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        build_lua(
+            state.libraries,
+            state.allow_unsafe,
+            state.json,
+            &state.filesystem,
+            &state.sandbox,
+            &state.events,
+        )
+        .and_then(|baseline| Ok(snapshot::snapshot(state.lua(), &baseline, strict)?))
+    };
+    let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(e) => return e.throw(&mut cx),
+    };
+    to_buffer(&mut cx, &bytes)
+}
+
+fn to_buffer<'a>(cx: &mut MethodContext<'a, JsLuaState>, bytes: &[u8]) -> JsResult<'a, JsValue> {
+    let mut buffer = JsBuffer::new(cx, bytes.len() as u32)?;
+    cx.borrow_mut(&mut buffer, |data| {
+        data.as_mut_slice::<u8>().copy_from_slice(bytes);
+    });
+    Ok(buffer.upcast())
+}
+
+fn restore_snapshot<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    buffer: Handle<'a, JsBuffer>,
+) -> JsResult<'a, JsValue> {
+    let bytes = cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec());
+    let this = cx.this();
+    let lua = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.lua().clone()
+    };
+    match snapshot::restore(&lua, &bytes) {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+/// Sets the JS function `emit` dispatches events to, from lib/index.js.
+fn set_event_dispatcher<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    dispatcher: Handle<'a, JsFunction>,
+) -> JsResult<'a, JsValue> {
+    let dispatcher = JsRef::new(&mut cx, dispatcher.upcast())?;
+    let this = cx.this();
+    {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        if let Ok(mut events) = state.events.lock() {
+            *events = Some(dispatcher);
+        }
+    }
+    Ok(cx.undefined().upcast())
+}
+
+fn set_timer_scheduler<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    scheduler: Handle<'a, JsFunction>,
+) -> JsResult<'a, JsValue> {
+    let scheduler = JsRef::new(&mut cx, scheduler.upcast())?;
+    let mut this = cx.this();
+    let previous = {
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        let previous = state.timers.replace(scheduler.clone());
+        timers::install(state.lua(), &scheduler).map(|_| previous)
+    };
+    match previous {
+        // Timers started through the old scheduler were forgotten by the new library
+        Ok(previous) => {
+            cancel_timers(&mut cx, previous)?;
+            Ok(cx.undefined().upcast())
+        }
+        Err(e) => Error::from(e).throw(&mut cx),
+    }
+}
+
+/// Tells a timer scheduler to cancel everything it was asked to schedule.
+fn cancel_timers(cx: &mut MethodContext<JsLuaState>, scheduler: Option<JsRef>) -> NeonResult<()> {
+    if let Some(scheduler) = scheduler {
+        let scheduler = scheduler
+            .to_handle(cx)?
+            .downcast_or_throw::<JsFunction, _>(cx)?;
+        let this = cx.this();
+        let args: Vec<Handle<JsValue>> = vec![cx.string("clear").upcast()];
+        scheduler.call(cx, this, args)?;
+    }
+    Ok(())
+}
+
+fn run_timer(mut cx: MethodContext<JsLuaState>, id: i64) -> JsResult<JsValue> {
+    let this = cx.this();
+    let (lua, conversions) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.lua().clone(), state.conversions.clone())
+    };
+    let _scope = js_context::enter(&mut cx, conversions);
+    match timers::run(&lua, id) {
+        Ok(_) => Ok(cx.undefined().upcast()),
+        Err(e) => e.throw(&mut cx),
     }
 }
 
 fn get_global(mut cx: MethodContext<JsLuaState>, name: String) -> JsResult<JsValue> {
     let this: Handle<JsLuaState> = cx.this();
-    let lua: &Lua = {
+    let (lua, conversions) = {
         let guard = cx.lock();
         let state = this.borrow(&guard);
-        &state.lua.clone()
+        (state.lua().clone(), state.conversions.clone())
     };
-    match lua_execution::get_global(lua, name) {
+    let _scope = js_context::enter(&mut cx, conversions);
+    match lua_execution::get_global(&lua, name) {
         Ok(v) => v.to_js(&mut cx),
-        Err(e) => cx.throw_error(e.to_string()),
+        Err(e) => e.throw(&mut cx),
     }
 }
 
+/// Reads an optional object of functions off of a class definition, e.g. `definition.methods`.
+fn function_map<'a>(
+    cx: &mut MethodContext<'a, JsLuaState>,
+    definition: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<HashMap<String, JsRef>> {
+    let mut functions = HashMap::new();
+    let value = definition.get(cx, key)?;
+    if value.is_a::<JsUndefined>() {
+        return Ok(functions);
+    }
+    let object = value.downcast_or_throw::<JsObject, _>(cx)?;
+    let names = object.get_own_property_names(cx)?.to_vec(cx)?;
+    for name in names {
+        let name = name.downcast_or_throw::<JsString, _>(cx)?;
+        let function = object
+            .get(cx, name)?
+            .downcast_or_throw::<JsFunction, _>(cx)?;
+        let function = JsRef::new(cx, function.upcast())?;
+        functions.insert(name.value(), function);
+    }
+    Ok(functions)
+}
+
+fn register_class<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    name: String,
+    definition: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let this = cx.this();
+    let constructor = definition
+        .get(&mut cx, "constructor")?
+        .downcast_or_throw::<JsFunction, _>(&mut cx)?;
+    let constructor = JsRef::new(&mut cx, constructor.upcast())?;
+    let methods = function_map(&mut cx, definition, "methods")?;
+    let getters = function_map(&mut cx, definition, "getters")?;
+    let setters = function_map(&mut cx, definition, "setters")?;
+    let metamethods = function_map(&mut cx, definition, "metamethods")?;
+    let class = Arc::new(UserClass {
+        name: name.clone(),
+        constructor,
+        methods,
+        getters,
+        setters,
+        metamethods,
+    });
+
+    let (lua, conversions) = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        (state.lua().clone(), state.conversions.clone())
+    };
+    if let Ok(mut conversions) = conversions.write() {
+        conversions.classes.push(class.clone());
+    }
+    let registration = Registration::Class(name, class);
+    if let Err(e) = registration.apply(&lua) {
+        return e.throw(&mut cx);
+    }
+    {
+        let mut this = cx.this();
+        let guard = cx.lock();
+        let mut state = this.borrow_mut(&guard);
+        state.remember(registration);
+    }
+    Ok(cx.undefined().upcast())
+}
+
+/// Throws if the state was closed.
+fn check_open(cx: &mut MethodContext<JsLuaState>) -> NeonResult<()> {
+    let this = cx.this();
+    let closed = {
+        let guard = cx.lock();
+        let state = this.borrow(&guard);
+        state.is_closed()
+    };
+    if closed {
+        cx.throw_error("state is closed")
+    } else {
+        Ok(())
+    }
+}
+
+fn conversions(cx: &mut MethodContext<JsLuaState>) -> ConversionRegistry {
+    let this = cx.this();
+    let guard = cx.lock();
+    let state = this.borrow(&guard);
+    state.conversions.clone()
+}
+
+fn add_to_lua_hook<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    hook: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let class = hook.get(&mut cx, "class")?;
+    let test = hook.get(&mut cx, "test")?;
+    let test = if let Ok(class) = class.downcast::<JsFunction>() {
+        HookTest::Class(JsRef::new(&mut cx, class.upcast())?)
+    } else if let Ok(test) = test.downcast::<JsFunction>() {
+        HookTest::Predicate(JsRef::new(&mut cx, test.upcast())?)
+    } else {
+        return cx.throw_type_error("Expected a 'class' or 'test' function");
+    };
+    let serialize = hook
+        .get(&mut cx, "serialize")?
+        .downcast_or_throw::<JsFunction, _>(&mut cx)?;
+    let serialize = JsRef::new(&mut cx, serialize.upcast())?;
+    let metatable = hook.get(&mut cx, "metatable")?;
+    let metatable = if metatable.is_a::<JsUndefined>() {
+        None
+    } else {
+        Some(metatable.downcast_or_throw::<JsString, _>(&mut cx)?.value())
+    };
+
+    let registry = conversions(&mut cx);
+    if let Ok(mut conversions) = registry.write() {
+        conversions.to_lua.push(Arc::new(ToLuaHook {
+            test,
+            serialize,
+            metatable,
+        }));
+    }
+    Ok(cx.undefined().upcast())
+}
+
+fn add_from_lua_hook<'a>(
+    mut cx: MethodContext<'a, JsLuaState>,
+    hook: Handle<'a, JsObject>,
+) -> JsResult<'a, JsValue> {
+    let metatable = hook
+        .get(&mut cx, "metatable")?
+        .downcast_or_throw::<JsString, _>(&mut cx)?
+        .value();
+    let deserialize = hook
+        .get(&mut cx, "deserialize")?
+        .downcast_or_throw::<JsFunction, _>(&mut cx)?;
+    let deserialize = JsRef::new(&mut cx, deserialize.upcast())?;
+
+    let registry = conversions(&mut cx);
+    if let Ok(mut conversions) = registry.write() {
+        conversions.from_lua.insert(metatable, deserialize);
+    }
+    Ok(cx.undefined().upcast())
+}
+
 declare_types! {
     pub class JsLuaState for LuaState {
 
@@ -287,32 +1418,97 @@ declare_types! {
         }
 
         method registerFunction(mut cx) {
+            check_open(&mut cx)?;
             let name = cx.argument::<JsString>(0)?.value();
             let cb = cx.argument::<JsFunction>(1)?;
-            register_function(cx, name, cb)
+            let mut options = callback_queue::Options::default();
+            if let Some(js_options) = cx.argument_opt(2) {
+                let js_options = js_options.downcast_or_throw::<JsObject, _>(&mut cx)?;
+                let batch = js_options.get(&mut cx, "batch")?;
+                options.batch = batch.downcast::<JsBoolean>().map(|batch| batch.value()).unwrap_or(false);
+                let max_queued = js_options.get(&mut cx, "maxQueued")?;
+                if !max_queued.is_a::<JsUndefined>() {
+                    let max_queued = max_queued.downcast_or_throw::<JsNumber, _>(&mut cx)?.value();
+                    if max_queued.is_nan() || max_queued < 1.0 {
+                        return cx.throw_range_error("'maxQueued' must be at least 1");
+                    }
+                    options.max_queued = Some(max_queued as usize);
+                }
+            }
+            register_function(cx, name, cb, options)
+        }
+
+        method registerClass(mut cx) {
+            check_open(&mut cx)?;
+            let name = cx.argument::<JsString>(0)?.value();
+            let definition = cx.argument::<JsObject>(1)?;
+            register_class(cx, name, definition)
+        }
+
+        method addToLuaHook(mut cx) {
+            check_open(&mut cx)?;
+            let hook = cx.argument::<JsObject>(0)?;
+            add_to_lua_hook(cx, hook)
+        }
+
+        method addFromLuaHook(mut cx) {
+            check_open(&mut cx)?;
+            let hook = cx.argument::<JsObject>(0)?;
+            add_from_lua_hook(cx, hook)
         }
 
         method reset(mut cx) {
+            check_open(&mut cx)?;
+            let keep_registrations = match cx.argument_opt(0) {
+                Some(options) => {
+                    let options = options.downcast_or_throw::<JsObject, _>(&mut cx)?;
+                    let keep = options.get(&mut cx, "keepRegistrations")?;
+                    keep.downcast::<JsBoolean>().map(|keep| keep.value()).unwrap_or(false)
+                }
+                None => false,
+            };
+            let conversions = conversions(&mut cx);
+            // Globals being set again may hold JS values
+            let _scope = js_context::enter(&mut cx, conversions);
             let mut this = cx.this();
-            {
+            let (result, scheduler) = {
                 let guard = cx.lock();
                 let mut state = this.borrow_mut(&guard);
-                state.reset();
+                (state.reset(keep_registrations), state.timers.clone())
+            };
+            cancel_timers(&mut cx, scheduler)?;
+            match result {
+                Ok(_) => Ok(cx.undefined().upcast()),
+                Err(e) => e.throw(&mut cx),
             }
-            Ok(cx.undefined().upcast())
         }
 
         method close(mut cx) {
             let mut this = cx.this();
-            {
+            let scheduler = {
                 let guard = cx.lock();
                 let mut state = this.borrow_mut(&guard);
-                state.reset();
-            }
+                // Closing drops the scheduler, which still has to cancel the pending timers
+                let scheduler = state.timers.clone();
+                state.close();
+                scheduler
+            };
+            cancel_timers(&mut cx, scheduler)?;
             Ok(cx.undefined().upcast())
         }
 
+        method getIsClosed(mut cx) {
+            let this = cx.this();
+            let closed = {
+                let guard = cx.lock();
+                let state = this.borrow(&guard);
+                state.is_closed()
+            };
+            Ok(cx.boolean(closed).upcast())
+        }
+
         method doStringSync(mut cx) {
+            check_open(&mut cx)?;
             let code = cx.argument::<JsString>(0)?.value();
             let chunk_name = match cx.argument_opt(1) {
                 Some(arg) => Some(arg.downcast::<JsString>().or_throw(&mut cx)?.value()),
@@ -322,6 +1518,7 @@ declare_types! {
         }
 
         method doFileSync(mut cx) {
+            check_open(&mut cx)?;
             let filename = cx.argument::<JsString>(0)?.value();
             // TODO chop the filename on error a bit so it's legible.
             //  currently the `root/stuff/...` is at the end vs `.../stuff/things.lua`
@@ -333,6 +1530,7 @@ declare_types! {
         }
 
         method callChunk(mut cx) {
+            check_open(&mut cx)?;
             let code = cx.argument::<JsString>(0)?.value();
             let (chunk_name, args) = match cx.len() {
                 2 => {
@@ -352,15 +1550,152 @@ declare_types! {
             call_chunk(cx, code, chunk_name, args)
         }
 
+        method callChunkPacked(mut cx) {
+            check_open(&mut cx)?;
+            let code = cx.argument::<JsString>(0)?.value();
+            let (chunk_name, args) = match cx.len() {
+                2 => (None, cx.argument::<JsBuffer>(1)?),
+                3 => {
+                    let chunk_name = cx.argument::<JsString>(1)?.value();
+                    (Some(chunk_name), cx.argument::<JsBuffer>(2)?)
+                }
+                _ => {
+                    let e = cx.string(format!("expected 2 or 3 arguments. Found: {}", cx.len()));
+                    return cx.throw(e);
+                }
+            };
+            call_chunk_packed(cx, code, chunk_name, args)
+        }
+
+        method setGlobalPacked(mut cx) {
+            check_open(&mut cx)?;
+            let name = cx.argument::<JsString>(0)?.value();
+            let buffer = cx.argument::<JsBuffer>(1)?;
+            set_global_packed(cx, name, buffer)
+        }
+
+        method getGlobalPacked(mut cx) {
+            check_open(&mut cx)?;
+            let name = cx.argument::<JsString>(0)?.value();
+            get_global_packed(cx, name)
+        }
+
         method setGlobal(mut cx) {
+            check_open(&mut cx)?;
             let name = cx.argument::<JsString>(0)?.value();
             let value = cx.argument::<JsValue>(1)?;
             set_global(cx, name, value)
         }
 
         method getGlobal(mut cx) {
+            check_open(&mut cx)?;
             let name = cx.argument::<JsString>(0)?.value();
             get_global(cx, name)
         }
+
+        method getEnvironment(mut cx) {
+            check_open(&mut cx)?;
+            get_environment(cx)
+        }
+
+        method getLoadedLibraries(mut cx) {
+            check_open(&mut cx)?;
+            loaded_libraries(cx)
+        }
+
+        method setHook(mut cx) {
+            check_open(&mut cx)?;
+            let options = cx.argument::<JsObject>(0)?;
+            let callback = cx.argument::<JsFunction>(1)?;
+            set_hook(cx, options, callback)
+        }
+
+        method removeHook(mut cx) {
+            check_open(&mut cx)?;
+            remove_hook(cx)
+        }
+
+        method startDebugger(mut cx) {
+            check_open(&mut cx)?;
+            let options = match cx.argument_opt(0) {
+                Some(options) => options.downcast_or_throw::<JsObject, _>(&mut cx)?,
+                None => cx.empty_object(),
+            };
+            start_debugger(cx, options)
+        }
+
+        method stopDebugger(mut cx) {
+            check_open(&mut cx)?;
+            stop_debugger(cx)
+        }
+
+        method startProfiling(mut cx) {
+            check_open(&mut cx)?;
+            let options = match cx.argument_opt(0) {
+                Some(options) => options.downcast_or_throw::<JsObject, _>(&mut cx)?,
+                None => cx.empty_object(),
+            };
+            start_profiling(cx, options)
+        }
+
+        method stopProfiling(mut cx) {
+            check_open(&mut cx)?;
+            stop_profiling(cx)
+        }
+
+        method startCoverage(mut cx) {
+            check_open(&mut cx)?;
+            start_coverage(cx)
+        }
+
+        method takeCoverage(mut cx) {
+            check_open(&mut cx)?;
+            take_coverage(cx)
+        }
+
+        method stopCoverage(mut cx) {
+            check_open(&mut cx)?;
+            stop_coverage(cx)
+        }
+
+        method snapshot(mut cx) {
+            check_open(&mut cx)?;
+            let options = match cx.argument_opt(0) {
+                Some(options) => options.downcast_or_throw::<JsObject, _>(&mut cx)?,
+                None => cx.empty_object(),
+            };
+            take_snapshot(cx, options)
+        }
+
+        method restore(mut cx) {
+            check_open(&mut cx)?;
+            let buffer = cx.argument::<JsBuffer>(0)?;
+            restore_snapshot(cx, buffer)
+        }
+
+        method setTimerScheduler(mut cx) {
+            check_open(&mut cx)?;
+            let scheduler = cx.argument::<JsFunction>(0)?;
+            set_timer_scheduler(cx, scheduler)
+        }
+
+        method runTimer(mut cx) {
+            check_open(&mut cx)?;
+            let id = cx.argument::<JsNumber>(0)?.value();
+            run_timer(cx, id as i64)
+        }
+
+        method setEventDispatcher(mut cx) {
+            check_open(&mut cx)?;
+            let dispatcher = cx.argument::<JsFunction>(0)?;
+            set_event_dispatcher(cx, dispatcher)
+        }
+
+        method collectGarbage(mut cx) {
+            check_open(&mut cx)?;
+            let command = cx.argument::<JsString>(0)?.value();
+            let argument = cx.argument_opt(1);
+            collect_garbage(cx, command, argument)
+        }
     }
 }
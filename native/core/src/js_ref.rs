@@ -0,0 +1,109 @@
+//! Persistent references to JS values held from the Rust/Lua side.
+//!
+//! The legacy runtime doesn't give us persistent handles for arbitrary values, so referenced
+//! values are kept alive in a `Map` on the JS global object and looked up by id. The map is keyed
+//! by a registered symbol, and is non-enumerable and read-only, so scripts can't stumble on it or
+//! replace it. Dropping
+//! the last clone of a `JsRef` queues its id, and the entry is deleted the next time we're back
+//! on the JS thread with a context (see `release_dropped`).
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use neon::prelude::*;
+
+// The `Symbol.for` key of the reference table
+const REFERENCE_TABLE: &str = "lua-js.references";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static RELEASED: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+struct RefId(u64);
+
+impl Drop for RefId {
+    fn drop(&mut self) {
+        if let Ok(mut released) = RELEASED.lock() {
+            released.push(self.0);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JsRef(Arc<RefId>);
+
+impl JsRef {
+    pub fn new<'a, CX: Context<'a>>(cx: &mut CX, value: Handle<'a, JsValue>) -> NeonResult<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let table = reference_table(cx)?;
+        let key = cx.number(id as f64).upcast::<JsValue>();
+        call_method(cx, table, "set", vec![key, value])?;
+        Ok(JsRef(Arc::new(RefId(id))))
+    }
+
+    pub fn id(&self) -> u64 {
+        (self.0).0
+    }
+
+    pub fn to_handle<'a, CX: Context<'a>>(&self, cx: &mut CX) -> JsResult<'a, JsValue> {
+        let table = reference_table(cx)?;
+        let key = cx.number(self.id() as f64).upcast::<JsValue>();
+        call_method(cx, table, "get", vec![key])
+    }
+}
+
+/// Deletes the table entries of every `JsRef` dropped since the last call.
+pub fn release_dropped<'a, CX: Context<'a>>(cx: &mut CX) -> NeonResult<()> {
+    let released: Vec<u64> = match RELEASED.lock() {
+        Ok(mut released) => released.drain(..).collect(),
+        Err(_) => return Ok(()),
+    };
+    if released.is_empty() {
+        return Ok(());
+    }
+    let table = reference_table(cx)?;
+    for id in released {
+        let key = cx.number(id as f64).upcast::<JsValue>();
+        call_method(cx, table, "delete", vec![key])?;
+    }
+    Ok(())
+}
+
+fn reference_table<'a, CX: Context<'a>>(cx: &mut CX) -> JsResult<'a, JsObject> {
+    let global = cx.global();
+    let symbol_ctor = global
+        .get(cx, "Symbol")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    let name = cx.string(REFERENCE_TABLE).upcast::<JsValue>();
+    let key = call_method(cx, symbol_ctor, "for", vec![name])?;
+    let existing = global.get(cx, key)?;
+    if let Ok(table) = existing.downcast::<JsObject>() {
+        return Ok(table);
+    }
+    let map_ctor = global
+        .get(cx, "Map")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    let no_args: Vec<Handle<JsValue>> = vec![];
+    let table = map_ctor.construct(cx, no_args)?;
+
+    // Non-enumerable, non-writable and non-configurable
+    let descriptor = cx.empty_object();
+    descriptor.set(cx, "value", table)?;
+    let object_ctor = global
+        .get(cx, "Object")?
+        .downcast_or_throw::<JsObject, CX>(cx)?;
+    let args = vec![global.upcast::<JsValue>(), key, descriptor.upcast()];
+    call_method(cx, object_ctor, "defineProperty", args)?;
+    Ok(table)
+}
+
+fn call_method<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    object: Handle<'a, JsObject>,
+    name: &str,
+    args: Vec<Handle<'a, JsValue>>,
+) -> JsResult<'a, JsValue> {
+    let method = object
+        .get(cx, name)?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    method.call(cx, object, args)
+}
@@ -0,0 +1,201 @@
+//! The opt-in `json` library: `json.encode(value, { pretty, sort_keys })`, `json.decode(text)` and
+//! the `json.null` sentinel.
+//!
+//! Encoding goes through `Value` and `value::json_shape`, so tables are shaped the way they'd reach
+//! JS and the way the serde_json conversion shapes them: a table with only positive integer keys is
+//! an array (holes become `null`), one without any is an object, and an empty table is `{}`. Decoding builds Lua values directly, with `null` decoded as `json.null` so
+//! it keeps its place in arrays and objects. `json.null` converts to JS `null` too.
+use std::ptr;
+
+use mlua::prelude::{FromLua, Lua, LuaLightUserData, LuaString, LuaTable, LuaValue};
+use serde_json::Value as Json;
+
+use crate::value::{self, JsonShape, Value};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct EncodeOptions {
+    pretty: bool,
+    sort_keys: bool,
+}
+
+/// Adds the `json` table, also available as `require("json")` when `package` is loaded.
+pub fn install(lua: &Lua) -> mlua::Result<()> {
+    let json = lua.create_table()?;
+    json.raw_set("null", null())?;
+
+    let encode = lua.create_function(|lua, (value, options): (LuaValue, Option<LuaTable>)| {
+        let mut encode_options = EncodeOptions::default();
+        if let Some(options) = options {
+            encode_options.pretty = options.get::<_, Option<bool>>("pretty")?.unwrap_or(false);
+            encode_options.sort_keys = options.get::<_, Option<bool>>("sort_keys")?.unwrap_or(false);
+        }
+        let value = Value::from_lua(value, lua)?;
+        let mut out = String::new();
+        encode_value(&mut out, &value, encode_options, 0)?;
+        Ok(out)
+    })?;
+    json.raw_set("encode", encode)?;
+
+    let decode = lua.create_function(|lua, text: LuaString| {
+        let json: Json = serde_json::from_slice(text.as_bytes())
+            .map_err(|e| mlua::Error::RuntimeError(format!("invalid JSON: {}", e)))?;
+        decode_value(lua, json)
+    })?;
+    json.raw_set("decode", decode)?;
+
+    if let Ok(LuaValue::Table(package)) = lua.globals().raw_get::<_, LuaValue>("package") {
+        if let LuaValue::Table(loaded) = package.raw_get::<_, LuaValue>("loaded")? {
+            loaded.raw_set("json", json.clone())?;
+        }
+    }
+    lua.globals().raw_set("json", json)
+}
+
+/// `json.null`, a null light userdata. No JS symbol has id 0, so it can't be mistaken for one.
+fn null() -> LuaLightUserData {
+    LuaLightUserData(ptr::null_mut())
+}
+
+fn error(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+fn encode_value(
+    out: &mut String,
+    value: &Value,
+    options: EncodeOptions,
+    depth: usize,
+) -> mlua::Result<()> {
+    match value {
+        Value::Null | Value::Undefined => out.push_str("null"),
+        Value::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        // Like JSON.stringify, non-finite numbers are `null`
+        Value::Double(f) if !f.is_finite() => out.push_str("null"),
+        Value::Double(f) if *f == 0.0 => out.push('0'),
+        Value::Double(f) => out.push_str(&value::float_key(*f)),
+        Value::String(s) => encode_string(out, s),
+        Value::Tagged(_, value) => encode_value(out, value, options, depth)?,
+        Value::ObjectLike(pairs, array_like) => match value::json_shape(pairs, array_like) {
+            Ok(JsonShape::Array(items)) => encode_array(out, items, options, depth)?,
+            Ok(JsonShape::Object(entries)) => encode_object(out, entries, options, depth)?,
+            Err(what) => return Err(error(format!("can't encode {} as JSON", what))),
+        },
+        Value::Error { .. } => return Err(error(String::from("can't encode an error as JSON"))),
+        #[cfg(feature = "node")]
+        Value::UserData(..) => return Err(error(String::from("can't encode userdata as JSON"))),
+        #[cfg(feature = "node")]
+        Value::Symbol(_) => return Err(error(String::from("can't encode a symbol as JSON"))),
+    }
+    Ok(())
+}
+
+fn encode_array(
+    out: &mut String,
+    items: Vec<Option<&Value>>,
+    options: EncodeOptions,
+    depth: usize,
+) -> mlua::Result<()> {
+    if items.is_empty() {
+        out.push_str("[]");
+        return Ok(());
+    }
+    out.push('[');
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, options, depth + 1);
+        match item {
+            Some(item) => encode_value(out, item, options, depth + 1)?,
+            None => out.push_str("null"),
+        }
+    }
+    newline(out, options, depth);
+    out.push(']');
+    Ok(())
+}
+
+fn encode_object(
+    out: &mut String,
+    mut entries: Vec<(String, &Value)>,
+    options: EncodeOptions,
+    depth: usize,
+) -> mlua::Result<()> {
+    if options.sort_keys {
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+    if entries.is_empty() {
+        out.push_str("{}");
+        return Ok(());
+    }
+    out.push('{');
+    for (i, (key, value)) in entries.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, options, depth + 1);
+        encode_string(out, &key);
+        out.push(':');
+        if options.pretty {
+            out.push(' ');
+        }
+        encode_value(out, value, options, depth + 1)?;
+    }
+    newline(out, options, depth);
+    out.push('}');
+    Ok(())
+}
+
+fn newline(out: &mut String, options: EncodeOptions, depth: usize) {
+    if options.pretty {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+fn encode_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn decode_value(lua: &Lua, json: Json) -> mlua::Result<LuaValue<'_>> {
+    Ok(match json {
+        Json::Null => LuaValue::LightUserData(null()),
+        Json::Bool(b) => LuaValue::Boolean(b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => LuaValue::Integer(i as _),
+            None => LuaValue::Number(n.as_f64().unwrap_or(f64::NAN) as _),
+        },
+        Json::String(s) => LuaValue::String(lua.create_string(&s)?),
+        Json::Array(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, decode_value(lua, item)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Json::Object(entries) => {
+            let table = lua.create_table()?;
+            for (key, value) in entries {
+                table.raw_set(key, decode_value(lua, value)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
@@ -1,8 +1,58 @@
+//! lua-js's core. With the `node` feature this is the `LuaState` class of the Node addon; without
+//! it, `Engine` runs scripts from Rust with the same conversions, error values and library flags,
+//! and neon isn't built at all.
+#[cfg(feature = "node")]
+mod callback_queue;
+#[cfg(feature = "node")]
+mod conversion;
+#[cfg(feature = "node")]
+mod coverage;
+#[cfg(feature = "debugger")]
+mod debugger;
+mod engine;
 mod error;
+#[cfg(feature = "node")]
+mod events;
+#[cfg(feature = "node")]
+mod gc;
+#[cfg(feature = "node")]
+mod hooks;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "node")]
+mod js_context;
+#[cfg(feature = "node")]
 mod js_lua_state;
+#[cfg(feature = "node")]
+mod js_ref;
+#[cfg(feature = "node")]
 mod js_traits;
+mod libraries;
 mod lua_execution;
+mod metatables;
+#[cfg(feature = "node")]
+mod msgpack;
+#[cfg(feature = "node")]
+mod profiler;
+#[cfg(feature = "node")]
+mod sandbox;
+#[cfg(feature = "node")]
+mod snapshot;
+#[cfg(feature = "node")]
+mod timers;
+#[cfg(feature = "node")]
+mod user_data;
 mod value;
+#[cfg(feature = "node")]
+mod vfs;
 
+pub use engine::Engine;
+pub use error::{Error, Result};
+pub use libraries::{lua_version, std_lib_from_flags};
+pub use mlua::StdLib;
+pub use value::Value;
+
+#[cfg(feature = "node")]
 pub use js_lua_state::JsLuaState;
+#[cfg(feature = "node")]
 pub use neon::register_module;
@@ -0,0 +1,169 @@
+//! The Lua standard libraries a state opens, and the flags they're chosen with. The flags are the
+//! values of the `Libs` enums in lib/index.js, so `Engine` users and JS pick libraries the same way.
+use mlua::{Lua, StdLib, Value};
+
+use crate::error::{Error, Result};
+
+/// The Lua version this crate was built for, e.g. `"lua54"`.
+pub fn lua_version() -> &'static str {
+    if cfg!(feature = "lua54") {
+        "lua54"
+    } else if cfg!(feature = "lua53") {
+        "lua53"
+    } else if cfg!(feature = "lua52") {
+        "lua52"
+    } else if cfg!(feature = "lua51") {
+        "lua51"
+    } else if cfg!(feature = "luajit") {
+        "luajit"
+    } else {
+        panic!("No version specified")
+    }
+}
+
+// Flags of the individual libraries, in the order they're reported by `loadedLibraries`
+const LIBRARY_FLAGS: [u32; 12] = [
+    0x1, 0x2, 0x4, 0x8, 0x10, 0x20, 0x40, 0x80, 0x100, 0x200, 0x4000_0000, 0x8000_0000,
+];
+
+fn flag_into_std_lib(flag: u32) -> Option<StdLib> {
+    const ALL_SAFE: u32 = u32::MAX - 1;
+    match flag {
+        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        0x1 => Some(StdLib::COROUTINE),
+        0x2 => Some(StdLib::TABLE),
+        0x4 => Some(StdLib::IO),
+        0x8 => Some(StdLib::OS),
+        0x10 => Some(StdLib::STRING),
+        #[cfg(any(feature = "lua54", feature = "lua53"))]
+        0x20 => Some(StdLib::UTF8),
+        #[cfg(any(feature = "lua52", feature = "luajit"))]
+        0x40 => Some(StdLib::BIT),
+        0x80 => Some(StdLib::MATH),
+        0x100 => Some(StdLib::PACKAGE),
+        #[cfg(feature = "luajit")]
+        0x200 => Some(StdLib::JIT),
+        #[cfg(feature = "luajit")]
+        0x4000_0000 => Some(StdLib::FFI),
+        0x8000_0000 => Some(StdLib::DEBUG),
+        u32::MAX => Some(StdLib::ALL),
+        ALL_SAFE => Some(StdLib::ALL_SAFE),
+        _ => None,
+    }
+}
+
+/// These correspond to our JS Enum. Used for a clearer error notification when including them in
+/// incompatible versions.
+fn flag_to_string(flag: u32) -> String {
+    const ALL_SAFE: u32 = u32::MAX - 1;
+    match flag {
+        0x1 => String::from("Coroutine"),
+        0x2 => String::from("Table"),
+        0x4 => String::from("Io"),
+        0x8 => String::from("Os"),
+        0x10 => String::from("String"),
+        0x20 => String::from("Utf8"),
+        0x40 => String::from("Bit"),
+        0x80 => String::from("Math"),
+        0x100 => String::from("Package"),
+        0x200 => String::from("Jit"),
+        0x4000_0000 => String::from("Ffi"),
+        0x8000_0000 => String::from("Debug"),
+        u32::MAX => String::from("All"),
+        ALL_SAFE => String::from("AllSafe"),
+        _ => flag.to_string(),
+    }
+}
+
+/// Combines library flags, failing on any this Lua version doesn't have.
+pub fn std_lib_from_flags(flags: &[u32]) -> Result<StdLib> {
+    // Hack to get a StdLib(0)
+    #[allow(clippy::eq_op)]
+    let mut libset = StdLib::TABLE ^ StdLib::TABLE;
+    for &flag in flags {
+        match flag_into_std_lib(flag) {
+            Some(lib) => libset |= lib,
+            None => {
+                return Err(Error::Lua(format!(
+                    "unrecognized Library flag \"{}\" for {}",
+                    flag_to_string(flag),
+                    lua_version()
+                )))
+            }
+        }
+    }
+    Ok(libset)
+}
+
+/// Lowercase names of the libraries in `libraries`, in flag order.
+pub fn names(libraries: StdLib) -> Vec<String> {
+    LIBRARY_FLAGS
+        .iter()
+        .filter_map(|flag| flag_into_std_lib(*flag).map(|lib| (*flag, lib)))
+        .filter(|(_, lib)| libraries.contains(*lib))
+        .map(|(flag, _)| flag_to_string(flag).to_lowercase())
+        .collect()
+}
+
+/// Libraries that can break memory safety, which mlua's safe constructor refuses to load.
+fn unsafe_libraries(libraries: StdLib) -> Vec<&'static str> {
+    let mut names = vec![];
+    if libraries.contains(StdLib::DEBUG) {
+        names.push("debug");
+    }
+    #[cfg(feature = "luajit")]
+    {
+        if libraries.contains(StdLib::FFI) {
+            names.push("ffi");
+        }
+    }
+    names
+}
+
+/// `debug` and `ffi` can break memory safety, so they have to be acknowledged explicitly. Note
+/// that `StdLib::ALL` includes them.
+pub(crate) fn check_unsafe(libraries: StdLib, allow_unsafe: bool) -> Result<()> {
+    let unsafe_libs = unsafe_libraries(libraries);
+    if !allow_unsafe && !unsafe_libs.is_empty() {
+        return Err(Error::Lua(format!(
+            "the {} library is unsafe and requires the `unsafe: true` option",
+            unsafe_libs.join(" and ")
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn new_lua(libraries: StdLib, allow_unsafe: bool) -> mlua::Result<Lua> {
+    if allow_unsafe {
+        return Ok(unsafe { Lua::unsafe_new_with(libraries) });
+    }
+    // The safe constructor disables C modules through `package`, and panics if it isn't loaded. So
+    // it always is, and its globals are removed again when it wasn't asked for.
+    let lua = Lua::new_with(libraries | StdLib::PACKAGE)?;
+    if !libraries.contains(StdLib::PACKAGE) {
+        let globals = lua.globals();
+        for name in &["package", "require", "module"] {
+            globals.raw_set(*name, Value::Nil)?;
+        }
+    }
+    Ok(lua)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_is_only_there_when_asked_for() {
+        let lua = new_lua(StdLib::TABLE | StdLib::MATH, false).unwrap();
+        let missing: bool = lua
+            .load("return package == nil and require == nil and math.floor(1.5) == 1")
+            .eval()
+            .unwrap();
+        assert!(missing);
+
+        let lua = new_lua(StdLib::TABLE | StdLib::PACKAGE, false).unwrap();
+        let present: bool = lua.load("return type(require) == 'function'").eval().unwrap();
+        assert!(present);
+    }
+}
@@ -1,8 +1,18 @@
 //! Connection point from lua-js to mlua itself.
-use crate::error::Result;
+use crate::error::{Error, Result};
+#[cfg(feature = "node")]
+use crate::hooks;
+#[cfg(feature = "node")]
+use crate::js_context;
+#[cfg(feature = "node")]
+use crate::msgpack;
+#[cfg(feature = "node")]
+use crate::user_data::{self, UserClass};
 use crate::value::Value;
 use mlua::prelude::LuaValue;
 use mlua::{FromLua, Function, Lua, MultiValue, ToLua};
+#[cfg(feature = "node")]
+use std::sync::Arc;
 
 pub fn do_string_sync(lua: &Lua, code: String, chunk_name: Option<String>) -> Result<Value> {
     let chunk = lua.load(&code);
@@ -10,10 +20,19 @@ pub fn do_string_sync(lua: &Lua, code: String, chunk_name: Option<String>) -> Re
         None => Ok(chunk),
         Some(name) => chunk.set_name(&name),
     }?;
-    match named_chunk.exec() {
-        Ok(_) => Ok(Value::Undefined),
-        Err(e) => Err(e.into()),
-    }
+    let f = named_chunk.into_function()?;
+    protected_call(lua, f, vec![])?;
+    Ok(Value::Undefined)
+}
+
+/// Evaluates `code` to the function `callChunk` calls.
+fn chunk_function(lua: &Lua, code: String, chunk_name: Option<String>) -> Result<Function<'_>> {
+    let chunk = lua.load(&code);
+    let named_chunk = match chunk_name {
+        None => Ok(chunk),
+        Some(name) => chunk.set_name(&name),
+    }?;
+    Ok(named_chunk.eval()?)
 }
 
 pub fn call_chunk(
@@ -22,22 +41,97 @@ pub fn call_chunk(
     chunk_name: Option<String>,
     args: Vec<Value>,
 ) -> Result<Value> {
-    match {
-        let chunk = lua.load(&code);
-        let named_chunk = match chunk_name {
-            None => Ok(chunk),
-            Some(name) => chunk.set_name(&name),
-        }?;
-        let f: Function = named_chunk.eval()?;
-        let lua_args: Vec<LuaValue> = args
-            .into_iter()
-            .map(|value| value.to_lua(lua))
-            .collect::<mlua::Result<Vec<LuaValue>>>()?;
-        let r = f.call(MultiValue::from_vec(lua_args))?;
-        Value::from_lua(r, lua)
-    } {
-        Ok(r) => Ok(r),
-        Err(e) => Err(e.into()),
+    let f = chunk_function(lua, code, chunk_name)?;
+    let lua_args: Vec<LuaValue> = args
+        .into_iter()
+        .map(|value| value.to_lua(lua))
+        .collect::<mlua::Result<Vec<LuaValue>>>()?;
+    let r = protected_call(lua, f, lua_args)?;
+    Ok(Value::from_lua(r, lua)?)
+}
+
+/// `call_chunk` with MessagePack arguments (an array) and result.
+#[cfg(feature = "node")]
+pub fn call_chunk_packed(
+    lua: &Lua,
+    code: String,
+    chunk_name: Option<String>,
+    args: &[u8],
+) -> Result<Vec<u8>> {
+    let f = chunk_function(lua, code, chunk_name)?;
+    let lua_args = msgpack::unpack_args(lua, args)?;
+    let r = protected_call(lua, f, lua_args)?;
+    Ok(msgpack::pack(&r)?)
+}
+
+/// Calls `f` through Lua's own `pcall`. mlua only keeps string error values, so this is what lets
+/// errors raised with tables, numbers, etc. keep their value.
+pub fn protected_call<'lua>(
+    lua: &'lua Lua,
+    f: Function<'lua>,
+    args: Vec<LuaValue<'lua>>,
+) -> Result<LuaValue<'lua>> {
+    // A hook that aborted an earlier call shouldn't abort this one
+    #[cfg(feature = "node")]
+    hooks::clear_abort(lua)?;
+    let pcall = registry_pcall(lua)?;
+    let mut pcall_args = Vec::with_capacity(args.len() + 1);
+    pcall_args.push(LuaValue::Function(f));
+    pcall_args.extend(args);
+    let results: MultiValue = pcall.call(MultiValue::from_vec(pcall_args))?;
+    let mut results = results.into_vec().into_iter();
+    match results.next() {
+        Some(LuaValue::Boolean(true)) => Ok(results.next().unwrap_or(LuaValue::Nil)),
+        _ => Err(error_from_value(lua, results.next().unwrap_or(LuaValue::Nil))),
+    }
+}
+
+/// `pcall` as it was when the state was first used, so scripts replacing the global can't
+/// interfere.
+fn registry_pcall(lua: &Lua) -> mlua::Result<Function<'_>> {
+    const PCALL_KEY: &str = "lua-js.pcall";
+    if let LuaValue::Function(pcall) = lua.named_registry_value::<_, LuaValue>(PCALL_KEY)? {
+        return Ok(pcall);
+    }
+    let pcall: Function = lua.globals().get("pcall")?;
+    lua.set_named_registry_value(PCALL_KEY, pcall.clone())?;
+    Ok(pcall)
+}
+
+fn error_from_value<'lua>(lua: &'lua Lua, value: LuaValue<'lua>) -> Error {
+    match value {
+        LuaValue::String(s) => {
+            let message = String::from_utf8_lossy(s.as_bytes()).into_owned();
+            mlua::Error::RuntimeError(message).into()
+        }
+        // Errors raised from Rust callbacks
+        LuaValue::Error(e) => e.into(),
+        other => {
+            let message = describe_error_value(&other);
+            match Value::from_lua(other, lua) {
+                Ok(value) => Error::LuaValue(message, value),
+                Err(_) => Error::Lua(message),
+            }
+        }
+    }
+}
+
+/// Describes a non-string error value the way the standalone `lua` interpreter does.
+fn describe_error_value(value: &LuaValue) -> String {
+    let tostring = match value {
+        LuaValue::Table(t) => t.get_metatable(),
+        _ => None,
+    }
+    .and_then(|mt| mt.raw_get::<_, Option<Function>>("__tostring").ok().flatten());
+    if let Some(tostring) = tostring {
+        if let Ok(s) = tostring.call::<_, String>(value.clone()) {
+            return s;
+        }
+    }
+    match value {
+        LuaValue::Integer(i) => i.to_string(),
+        LuaValue::Number(n) => n.to_string(),
+        _ => format!("(error object is a {} value)", value.type_name()),
     }
 }
 
@@ -54,26 +148,47 @@ pub fn get_global(lua: &Lua, name: String) -> Result<Value> {
     }
 }
 
+#[cfg(feature = "node")]
+pub fn get_global_packed(lua: &Lua, name: String) -> Result<Vec<u8>> {
+    let value: LuaValue = lua.globals().get(name)?;
+    Ok(msgpack::pack(&value)?)
+}
+
+#[cfg(feature = "node")]
+pub fn set_global_packed(lua: &Lua, name: String, data: &[u8]) -> Result<Value> {
+    let value = msgpack::unpack(lua, data)?;
+    lua.globals().set(name, value)?;
+    Ok(Value::Undefined)
+}
+
 pub fn set_global(lua: &Lua, name: String, value: Value) -> Result<Value> {
     let globals = lua.globals();
-    let _ = globals.set(name, value)?;
+    globals.set(name, value)?;
     Ok(Value::Boolean(true))
 }
 
 // TODO not sure how else to approach this regarding EventHandler
-pub fn register_function<F: 'static + Send + Sync + Fn(Vec<Value>) -> ()>(
-    lua: &Lua,
-    name: String,
-    callback: F,
-) -> Result<Value> {
+/// Sets the global `name` to a function handing its arguments to `callback`. The callback's errors
+/// are raised in Lua, with JS exceptions rethrown as their value.
+#[cfg(feature = "node")]
+pub fn register_function<F>(lua: &Lua, name: String, callback: F) -> Result<Value>
+where
+    F: 'static + Send + Sync + Fn(Vec<Value>) -> mlua::Result<()>,
+{
     let globals = lua.globals();
-    // TODO if this function fails, it should be passed to an event emitter, on("error")
-    let f = lua.create_function(move |c, args: MultiValue| {
+    let f = js_context::create_function(lua, move |c, args: MultiValue| {
         let values = Value::into_vec_for_lua_multi(args, c)?;
-        // TODO this should have an error CB?
-        callback(values);
+        callback(values)?;
         Ok(Value::Undefined)
     })?;
     let _ = globals.set(name, f)?;
     Ok(Value::Undefined)
 }
+
+#[cfg(feature = "node")]
+pub fn register_class(lua: &Lua, name: String, class: Arc<UserClass>) -> Result<Value> {
+    let globals = lua.globals();
+    let class_table = user_data::create_class_table(lua, class)?;
+    let _ = globals.set(name, class_table)?;
+    Ok(Value::Undefined)
+}
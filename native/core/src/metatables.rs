@@ -0,0 +1,68 @@
+//! Metatables lua-js tags tables with, so they can be told apart on the way back out of Lua:
+//! tables converted from JS errors, and ones produced by `toLua` hooks (named after the hook's
+//! metatable). Tags are matched by `__name`, which Lua code can set itself.
+use mlua::prelude::{Lua, LuaTable};
+
+/// Registry key prefix for the metatables we create, so they don't clash with other named
+/// registry values.
+const METATABLE_PREFIX: &str = "lua-js.metatable.";
+
+const ERROR_METATABLE: &str = "Error";
+
+/// Gets (or creates) the metatable used to tag tables produced by a `toLua` hook. Its `__name`
+/// is what `fromLua` hooks match on, so Lua code can also tag tables with
+/// `setmetatable(t, { __name = "Money" })`.
+pub fn named_metatable<'lua>(lua: &'lua Lua, name: &str) -> mlua::Result<LuaTable<'lua>> {
+    let key = format!("{}{}", METATABLE_PREFIX, name);
+    if let mlua::Value::Table(metatable) = lua.named_registry_value::<_, mlua::Value>(&key)? {
+        return Ok(metatable);
+    }
+    let metatable = lua.create_table()?;
+    metatable.raw_set("__name", name)?;
+    lua.set_named_registry_value(&key, metatable.clone())?;
+    Ok(metatable)
+}
+
+/// The metatable of tables converted from JS `Error`s. Its `__tostring` formats them the same way
+/// JS does, e.g. `TypeError: message`.
+pub fn error_metatable(lua: &Lua) -> mlua::Result<LuaTable<'_>> {
+    let metatable = named_metatable(lua, ERROR_METATABLE)?;
+    if let mlua::Value::Nil = metatable.raw_get::<_, mlua::Value>("__tostring")? {
+        let tostring = lua.create_function(|_, error: LuaTable| {
+            let name: Option<String> = error.raw_get("name")?;
+            let message: Option<String> = error.raw_get("message")?;
+            Ok(format!(
+                "{}: {}",
+                name.unwrap_or_else(|| String::from("Error")),
+                message.unwrap_or_default()
+            ))
+        })?;
+        metatable.raw_set("__tostring", tostring)?;
+    }
+    Ok(metatable)
+}
+
+/// Checks if `table` is tagged as an error, either by `error_metatable` or from Lua with
+/// `setmetatable(t, { __name = "Error" })`.
+pub fn is_error_table(table: &LuaTable) -> mlua::Result<bool> {
+    match table.get_metatable() {
+        Some(metatable) => match metatable.raw_get::<_, mlua::Value>("__name")? {
+            mlua::Value::String(name) => Ok(name.as_bytes() == ERROR_METATABLE.as_bytes()),
+            _ => Ok(false),
+        },
+        None => Ok(false),
+    }
+}
+
+/// The `__name` of the metatable of `table`, if it has one.
+#[cfg(feature = "node")]
+pub fn metatable_name(table: &LuaTable) -> mlua::Result<Option<String>> {
+    let metatable = match table.get_metatable() {
+        Some(metatable) => metatable,
+        None => return Ok(None),
+    };
+    match metatable.raw_get::<_, mlua::Value>("__name")? {
+        mlua::Value::String(s) => Ok(Some(s.to_str()?.to_owned())),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,362 @@
+//! MessagePack encoding of Lua values, for `setGlobalPacked`, `getGlobalPacked` and
+//! `callChunkPacked`.
+//!
+//! Payloads go straight between bytes and Lua values, without `Value` or a neon call per property.
+//! Tables are shaped the way they'd reach JS: a table with only positive integer keys is an array
+//! (holes become `nil`), anything else a map (whose keys can be numbers), and an empty table is an
+//! empty map. Like the `json` library, tables that are mostly holes are maps too, see
+//! `value::is_dense_array`. Lua strings are
+//! packed as `str` when they're valid UTF-8 and `bin` otherwise, and both unpack to Lua strings.
+//! Extension types, functions and userdata aren't supported.
+use std::convert::TryInto;
+
+use mlua::prelude::{Lua, LuaTable, LuaValue};
+
+use crate::value;
+
+// Deep enough for real data, and shallow enough that cycles and hostile input can't overflow the
+// stack
+const MAX_DEPTH: usize = 128;
+
+fn error(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+fn too_deep() -> mlua::Error {
+    error(format!(
+        "MessagePack data nested more than {} levels deep (is there a cycle?)",
+        MAX_DEPTH
+    ))
+}
+
+/// Packs a single value.
+pub fn pack(value: &LuaValue) -> mlua::Result<Vec<u8>> {
+    let mut out = vec![];
+    write_value(&mut out, value, 0)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut Vec<u8>, value: &LuaValue, depth: usize) -> mlua::Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(too_deep());
+    }
+    match value {
+        LuaValue::Nil => out.push(0xc0),
+        LuaValue::Boolean(false) => out.push(0xc2),
+        LuaValue::Boolean(true) => out.push(0xc3),
+        LuaValue::Integer(i) => write_integer(out, *i as i64),
+        LuaValue::Number(n) => {
+            out.push(0xcb);
+            out.extend_from_slice(&(*n as f64).to_be_bytes());
+        }
+        LuaValue::String(s) => {
+            let bytes = s.as_bytes();
+            if std::str::from_utf8(bytes).is_ok() {
+                write_length(out, bytes.len(), &STR)?;
+            } else {
+                write_length(out, bytes.len(), &BIN)?;
+            }
+            out.extend_from_slice(bytes);
+        }
+        LuaValue::Table(table) => write_table(out, table, depth)?,
+        other => {
+            return Err(error(format!(
+                "can't pack a {} value as MessagePack",
+                other.type_name()
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn write_integer(out: &mut Vec<u8>, i: i64) {
+    match i {
+        0..=0x7f => out.push(i as u8),
+        -32..=-1 => out.push(i as i8 as u8),
+        0x80..=0xff => out.extend_from_slice(&[0xcc, i as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend_from_slice(&(i as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend_from_slice(&(i as u32).to_be_bytes());
+        }
+        0x1_0000_0000..=i64::MAX => {
+            out.push(0xcf);
+            out.extend_from_slice(&(i as u64).to_be_bytes());
+        }
+        -0x80..=-33 => out.extend_from_slice(&[0xd0, i as i8 as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend_from_slice(&(i as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend_from_slice(&(i as i32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend_from_slice(&i.to_be_bytes());
+        }
+    }
+}
+
+/// A type's length headers: the `fix` marker and how many it can hold, if the type has one, then
+/// the 8 (if any), 16 and 32-bit markers.
+struct Headers {
+    fix: Option<(u8, usize)>,
+    marker8: Option<u8>,
+    marker16: u8,
+    marker32: u8,
+}
+
+const STR: Headers = Headers {
+    fix: Some((0xa0, 32)),
+    marker8: Some(0xd9),
+    marker16: 0xda,
+    marker32: 0xdb,
+};
+const BIN: Headers = Headers {
+    fix: None,
+    marker8: Some(0xc4),
+    marker16: 0xc5,
+    marker32: 0xc6,
+};
+const ARRAY: Headers = Headers {
+    fix: Some((0x90, 16)),
+    marker8: None,
+    marker16: 0xdc,
+    marker32: 0xdd,
+};
+const MAP: Headers = Headers {
+    fix: Some((0x80, 16)),
+    marker8: None,
+    marker16: 0xde,
+    marker32: 0xdf,
+};
+
+/// Writes the smallest header that fits `length`.
+fn write_length(out: &mut Vec<u8>, length: usize, headers: &Headers) -> mlua::Result<()> {
+    match (headers.fix, headers.marker8) {
+        (Some((fix, limit)), _) if length < limit => out.push(fix | length as u8),
+        (_, Some(marker8)) if length <= 0xff => out.extend_from_slice(&[marker8, length as u8]),
+        _ if length <= 0xffff => {
+            out.push(headers.marker16);
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        _ if length <= 0xffff_ffff => {
+            out.push(headers.marker32);
+            out.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+        _ => return Err(error(String::from("too large to pack as MessagePack"))),
+    }
+    Ok(())
+}
+
+fn write_table(out: &mut Vec<u8>, table: &LuaTable, depth: usize) -> mlua::Result<()> {
+    let mut pairs = vec![];
+    for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+        pairs.push(pair?);
+    }
+    // The highest key, if they're all array indexes
+    let length = pairs.iter().try_fold(0, |length, (key, _)| match key {
+        LuaValue::Integer(i) if *i >= 1 && *i <= u32::MAX as i64 => Some(length.max(*i as usize)),
+        _ => None,
+    });
+    let dense = |length: &usize| *length > 0 && value::is_dense_array(*length, pairs.len());
+    if let Some(length) = length.filter(dense) {
+        write_length(out, length, &ARRAY)?;
+        for i in 1..=length {
+            let item: LuaValue = table.raw_get(i)?;
+            write_value(out, &item, depth + 1)?;
+        }
+    } else {
+        write_length(out, pairs.len(), &MAP)?;
+        for (key, value) in &pairs {
+            write_value(out, key, depth + 1)?;
+            write_value(out, value, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks a single value, which must take up all of `data`.
+pub fn unpack<'lua>(lua: &'lua Lua, data: &[u8]) -> mlua::Result<LuaValue<'lua>> {
+    let mut reader = Reader::new(lua, data);
+    let value = reader.read_value(0)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+/// Unpacks an array of call arguments. Unlike a table, this keeps `nil`s at the end.
+pub fn unpack_args<'lua>(lua: &'lua Lua, data: &[u8]) -> mlua::Result<Vec<LuaValue<'lua>>> {
+    let mut reader = Reader::new(lua, data);
+    let length = match reader.take(1)?[0] {
+        marker @ 0x90..=0x9f => (marker & 0x0f) as usize,
+        0xdc => reader.read_u16()? as usize,
+        0xdd => reader.read_u32()? as usize,
+        _ => {
+            return Err(error(String::from(
+                "packed arguments must be a MessagePack array",
+            )))
+        }
+    };
+    let mut args = vec![];
+    for _ in 0..length {
+        args.push(reader.read_value(1)?);
+    }
+    reader.finish()?;
+    Ok(args)
+}
+
+struct Reader<'a, 'lua> {
+    lua: &'lua Lua,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a, 'lua> Reader<'a, 'lua> {
+    fn new(lua: &'lua Lua, data: &'a [u8]) -> Self {
+        Reader {
+            lua,
+            data,
+            position: 0,
+        }
+    }
+
+    fn finish(&self) -> mlua::Result<()> {
+        if self.position != self.data.len() {
+            return Err(error(String::from("trailing data after the MessagePack value")));
+        }
+        Ok(())
+    }
+
+    fn read_value(&mut self, depth: usize) -> mlua::Result<LuaValue<'lua>> {
+        if depth > MAX_DEPTH {
+            return Err(too_deep());
+        }
+        let marker = self.take(1)?[0];
+        let value = match marker {
+            0x00..=0x7f => LuaValue::Integer(marker as _),
+            0x80..=0x8f => self.read_map((marker & 0x0f) as usize, depth)?,
+            0x90..=0x9f => self.read_array((marker & 0x0f) as usize, depth)?,
+            0xa0..=0xbf => self.read_string((marker & 0x1f) as usize)?,
+            0xc0 => LuaValue::Nil,
+            0xc2 => LuaValue::Boolean(false),
+            0xc3 => LuaValue::Boolean(true),
+            0xc4 | 0xd9 => {
+                let length = self.take(1)?[0] as usize;
+                self.read_string(length)?
+            }
+            0xc5 | 0xda => {
+                let length = self.read_u16()? as usize;
+                self.read_string(length)?
+            }
+            0xc6 | 0xdb => {
+                let length = self.read_u32()? as usize;
+                self.read_string(length)?
+            }
+            0xca => {
+                let bytes = self.take(4)?.try_into().unwrap();
+                LuaValue::Number(f32::from_be_bytes(bytes) as _)
+            }
+            0xcb => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                LuaValue::Number(f64::from_be_bytes(bytes) as _)
+            }
+            0xcc => LuaValue::Integer(self.take(1)?[0] as _),
+            0xcd => LuaValue::Integer(self.read_u16()? as _),
+            0xce => LuaValue::Integer(self.read_u32()? as _),
+            0xcf => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                let u = u64::from_be_bytes(bytes);
+                if u > i64::MAX as u64 {
+                    LuaValue::Number(u as f64 as _)
+                } else {
+                    LuaValue::Integer(u as i64 as _)
+                }
+            }
+            0xd0 => LuaValue::Integer(self.take(1)?[0] as i8 as _),
+            0xd1 => LuaValue::Integer(self.read_u16()? as i16 as _),
+            0xd2 => LuaValue::Integer(self.read_u32()? as i32 as _),
+            0xd3 => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                LuaValue::Integer(i64::from_be_bytes(bytes) as _)
+            }
+            0xdc => {
+                let length = self.read_u16()? as usize;
+                self.read_array(length, depth)?
+            }
+            0xdd => {
+                let length = self.read_u32()? as usize;
+                self.read_array(length, depth)?
+            }
+            0xde => {
+                let length = self.read_u16()? as usize;
+                self.read_map(length, depth)?
+            }
+            0xdf => {
+                let length = self.read_u32()? as usize;
+                self.read_map(length, depth)?
+            }
+            0xe0..=0xff => LuaValue::Integer(marker as i8 as _),
+            0xc7..=0xc9 | 0xd4..=0xd8 => {
+                return Err(error(String::from(
+                    "MessagePack extension types aren't supported",
+                )))
+            }
+            0xc1 => return Err(error(String::from("invalid MessagePack data"))),
+        };
+        Ok(value)
+    }
+
+    fn read_string(&mut self, length: usize) -> mlua::Result<LuaValue<'lua>> {
+        let bytes = self.take(length)?;
+        Ok(LuaValue::String(self.lua.create_string(bytes)?))
+    }
+
+    fn read_array(&mut self, length: usize, depth: usize) -> mlua::Result<LuaValue<'lua>> {
+        let table = self.lua.create_table()?;
+        for i in 1..=length {
+            let item = self.read_value(depth + 1)?;
+            table.raw_set(i, item)?;
+        }
+        Ok(LuaValue::Table(table))
+    }
+
+    fn read_map(&mut self, length: usize, depth: usize) -> mlua::Result<LuaValue<'lua>> {
+        let table = self.lua.create_table()?;
+        for _ in 0..length {
+            let key = self.read_value(depth + 1)?;
+            let value = self.read_value(depth + 1)?;
+            if let LuaValue::Nil = key {
+                return Err(error(String::from("MessagePack map has a nil key")));
+            }
+            table.raw_set(key, value)?;
+        }
+        Ok(LuaValue::Table(table))
+    }
+
+    fn read_u16(&mut self) -> mlua::Result<u16> {
+        let bytes = self.take(2)?.try_into().unwrap();
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    fn read_u32(&mut self) -> mlua::Result<u32> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn take(&mut self, length: usize) -> mlua::Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.data.len() {
+            return Err(error(String::from(
+                "corrupt MessagePack data, unexpected end of data",
+            )));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
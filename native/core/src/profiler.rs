@@ -0,0 +1,469 @@
+//! Lua profiler for `startProfiling`/`stopProfiling`, built on the debug hook.
+//!
+//! Call and return events keep a shadow stack of the running functions. The instrumented mode
+//! times every call; the sampling mode only looks at the stack every `interval` and charges the
+//! time since the last sample to the functions on it. Both modes record the sampled stacks, which
+//! is what the `.cpuprofile` and folded-stack exports are made from.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::{Debug, DebugEvent, HookTriggers};
+use neon::prelude::*;
+
+use crate::hooks::{chunk_name, Handler};
+use crate::js_traits::ToJs;
+
+// How often the sampling mode checks the clock
+const SAMPLE_CHECK_INSTRUCTIONS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Sampling,
+    Instrumented,
+}
+
+impl Mode {
+    pub fn from_str(mode: &str) -> Option<Mode> {
+        match mode {
+            "sampling" => Some(Mode::Sampling),
+            "instrumented" => Some(Mode::Instrumented),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Sampling => "sampling",
+            Mode::Instrumented => "instrumented",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FunctionId {
+    name: String,
+    source: String,
+    line: i64,
+}
+
+impl FunctionId {
+    fn from_debug(debug: &Debug) -> FunctionId {
+        let source = debug.source();
+        let name = match debug.names().name {
+            Some(name) => String::from_utf8_lossy(name).into_owned(),
+            None if source.what == Some(&b"main"[..]) => String::from("(main chunk)"),
+            None => String::from("(anonymous)"),
+        };
+        FunctionId {
+            name,
+            source: source.source.map(chunk_name).unwrap_or_default(),
+            line: source.line_defined as i64,
+        }
+    }
+
+    /// `name (source:line)`, or just the name for C functions.
+    fn label(&self) -> String {
+        if self.line > 0 {
+            format!("{} ({}:{})", self.name, self.source, self.line)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FunctionStats {
+    calls: u64,
+    self_time: Duration,
+    total_time: Duration,
+    // Frames of this function on the stack, so recursion isn't counted twice in `total_time`
+    active: u32,
+}
+
+struct Frame {
+    function: usize,
+    start: Instant,
+    children: Duration,
+}
+
+struct Sample {
+    // Function indexes, outermost first
+    stack: Vec<usize>,
+    // Since profiling started
+    time: Duration,
+}
+
+pub struct Profiler {
+    mode: Mode,
+    interval: Duration,
+    started: Instant,
+    functions: Vec<(FunctionId, FunctionStats)>,
+    index: HashMap<FunctionId, usize>,
+    stack: Vec<Frame>,
+    last_sample: Instant,
+    samples: Vec<Sample>,
+}
+
+impl Profiler {
+    /// A profiler timed from now, which records once its `handler` is installed.
+    pub fn new(mode: Mode, interval: Duration) -> Arc<Mutex<Profiler>> {
+        let now = Instant::now();
+        Arc::new(Mutex::new(Profiler {
+            mode,
+            interval,
+            started: now,
+            functions: vec![],
+            index: HashMap::new(),
+            stack: vec![],
+            last_sample: now,
+            samples: vec![],
+        }))
+    }
+
+    /// The call and return hook recording into `profiler`, for `hooks::install`. The sampling mode
+    /// also checks the clock every `SAMPLE_CHECK_INSTRUCTIONS`.
+    pub fn handler(profiler: &Arc<Mutex<Profiler>>) -> Handler {
+        let mode = profiler.lock().map(|profiler| profiler.mode).unwrap_or(Mode::Instrumented);
+        let triggers = HookTriggers {
+            on_calls: true,
+            on_returns: true,
+            every_line: false,
+            every_nth_instruction: match mode {
+                Mode::Sampling => Some(SAMPLE_CHECK_INSTRUCTIONS),
+                Mode::Instrumented => None,
+            },
+        };
+        let profiler = profiler.clone();
+        Handler::new(triggers, move |_lua, debug| {
+            if let Ok(mut profiler) = profiler.lock() {
+                profiler.on_event(debug);
+            }
+            Ok(())
+        })
+    }
+
+    /// Closes any frames still on the stack. The caller removes the handler from the state's hook.
+    pub fn stop(&mut self) -> Profile {
+        let now = Instant::now();
+        while !self.stack.is_empty() {
+            self.pop(now);
+        }
+        let samples = self
+            .samples
+            .drain(..)
+            .map(|sample| (sample.stack, sample.time))
+            .collect();
+        let labels = self.functions.iter().map(|(id, _)| id.label()).collect();
+        self.index.clear();
+        let summaries = self
+            .functions
+            .drain(..)
+            .map(|(id, stats)| FunctionSummary {
+                id,
+                calls: stats.calls,
+                self_time: stats.self_time,
+                total_time: stats.total_time,
+            })
+            .collect();
+        Profile {
+            mode: self.mode,
+            duration: now - self.started,
+            functions: summaries,
+            labels,
+            samples,
+        }
+    }
+
+    fn on_event(&mut self, debug: &Debug) {
+        let now = Instant::now();
+        match debug.event() {
+            DebugEvent::Call => self.push(FunctionId::from_debug(debug), now),
+            // In 5.1 this is a "tail return" after the fact, which the return handling covers
+            DebugEvent::TailCall if cfg!(any(feature = "lua51", feature = "luajit")) => {}
+            DebugEvent::TailCall => {
+                // The caller's frame is replaced, and there won't be a return event for it
+                self.pop(now);
+                self.push(FunctionId::from_debug(debug), now);
+            }
+            DebugEvent::Ret => {
+                // Frames unwound by errors, or Lua 5.1 tail calls, never see a return event,
+                // so close everything above the returning function.
+                let id = FunctionId::from_debug(debug);
+                if let Some(&function) = self.index.get(&id) {
+                    if let Some(position) =
+                        self.stack.iter().rposition(|frame| frame.function == function)
+                    {
+                        self.sample(now);
+                        while self.stack.len() > position {
+                            self.pop(now);
+                        }
+                    }
+                }
+            }
+            DebugEvent::Count => self.sample(now),
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, id: FunctionId, now: Instant) {
+        if self.stack.is_empty() {
+            // Time spent outside of Lua isn't charged to anything
+            self.last_sample = now;
+        } else {
+            self.sample(now);
+        }
+        let function = match self.index.get(&id) {
+            Some(&function) => function,
+            None => {
+                self.functions.push((id.clone(), FunctionStats::default()));
+                self.index.insert(id, self.functions.len() - 1);
+                self.functions.len() - 1
+            }
+        };
+        let stats = &mut self.functions[function].1;
+        stats.calls += 1;
+        stats.active += 1;
+        self.stack.push(Frame {
+            function,
+            start: now,
+            children: Duration::default(),
+        });
+    }
+
+    fn pop(&mut self, now: Instant) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let total = now - frame.start;
+        let stats = &mut self.functions[frame.function].1;
+        stats.active -= 1;
+        if self.mode == Mode::Instrumented {
+            stats.self_time += total.checked_sub(frame.children).unwrap_or_default();
+            if stats.active == 0 {
+                stats.total_time += total;
+            }
+        }
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += total;
+        }
+    }
+
+    /// Records the stack if `interval` has passed since the last sample.
+    fn sample(&mut self, now: Instant) {
+        let elapsed = now - self.last_sample;
+        if elapsed < self.interval || self.stack.is_empty() {
+            return;
+        }
+        self.last_sample = now;
+        let stack: Vec<usize> = self.stack.iter().map(|frame| frame.function).collect();
+        if self.mode == Mode::Sampling {
+            let leaf = stack[stack.len() - 1];
+            self.functions[leaf].1.self_time += elapsed;
+            let mut seen = vec![];
+            for &function in &stack {
+                if !seen.contains(&function) {
+                    seen.push(function);
+                    self.functions[function].1.total_time += elapsed;
+                }
+            }
+        }
+        self.samples.push(Sample {
+            stack,
+            time: now - self.started,
+        });
+    }
+}
+
+struct FunctionSummary {
+    id: FunctionId,
+    calls: u64,
+    self_time: Duration,
+    total_time: Duration,
+}
+
+/// The result of `stopProfiling`.
+pub struct Profile {
+    mode: Mode,
+    duration: Duration,
+    functions: Vec<FunctionSummary>,
+    // Function labels, by index
+    labels: Vec<String>,
+    samples: Vec<(Vec<usize>, Duration)>,
+}
+
+impl Profile {
+    /// One `outer;inner count` line per distinct stack, for flamegraph tools.
+    pub fn folded(&self) -> String {
+        let mut counts: Vec<(String, u64)> = vec![];
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for (stack, _) in &self.samples {
+            let line = stack
+                .iter()
+                .map(|&function| self.labels[function].replace(';', ":"))
+                .collect::<Vec<_>>()
+                .join(";");
+            match positions.get(&line) {
+                Some(&position) => counts[position].1 += 1,
+                None => {
+                    positions.insert(line.clone(), counts.len());
+                    counts.push((line, 1));
+                }
+            }
+        }
+        let mut folded = String::new();
+        for (line, count) in counts {
+            let _ = writeln!(folded, "{} {}", line, count);
+        }
+        folded
+    }
+
+    /// The samples as a Chrome DevTools `.cpuprofile`. Times are in microseconds.
+    fn cpu_profile<'a, CX: Context<'a>>(&self, cx: &mut CX) -> JsResult<'a, JsValue> {
+        // Call tree nodes as (function, children), with the root at 0
+        let mut nodes: Vec<(Option<usize>, Vec<usize>)> = vec![(None, vec![])];
+        let mut sample_nodes = vec![];
+        for (stack, _) in &self.samples {
+            let mut node = 0;
+            for &function in stack {
+                let existing = nodes[node]
+                    .1
+                    .iter()
+                    .copied()
+                    .find(|&child| nodes[child].0 == Some(function));
+                node = match existing {
+                    Some(child) => child,
+                    None => {
+                        nodes.push((Some(function), vec![]));
+                        let child = nodes.len() - 1;
+                        nodes[node].1.push(child);
+                        child
+                    }
+                };
+            }
+            sample_nodes.push(node);
+        }
+        let mut hits = vec![0u32; nodes.len()];
+        for &node in &sample_nodes {
+            hits[node] += 1;
+        }
+
+        let js_nodes = JsArray::new(cx, nodes.len() as u32);
+        for (i, (function, children)) in nodes.iter().enumerate() {
+            let (name, url, line) = match function {
+                Some(function) => {
+                    let id = &self.functions[*function].id;
+                    (id.name.as_str(), id.source.as_str(), id.line)
+                }
+                None => ("(root)", "", 0),
+            };
+            let call_frame = cx.empty_object();
+            let value = cx.string(name);
+            call_frame.set(cx, "functionName", value)?;
+            let value = cx.string("0");
+            call_frame.set(cx, "scriptId", value)?;
+            let value = cx.string(url);
+            call_frame.set(cx, "url", value)?;
+            // Zero based in DevTools
+            let value = cx.number((line - 1).max(0) as f64);
+            call_frame.set(cx, "lineNumber", value)?;
+            let value = cx.number(0);
+            call_frame.set(cx, "columnNumber", value)?;
+
+            let node = cx.empty_object();
+            let value = cx.number(i as f64 + 1.0);
+            node.set(cx, "id", value)?;
+            node.set(cx, "callFrame", call_frame)?;
+            let value = cx.number(hits[i]);
+            node.set(cx, "hitCount", value)?;
+            let js_children = JsArray::new(cx, children.len() as u32);
+            for (j, &child) in children.iter().enumerate() {
+                let value = cx.number(child as f64 + 1.0);
+                js_children.set(cx, j as u32, value)?;
+            }
+            node.set(cx, "children", js_children)?;
+            js_nodes.set(cx, i as u32, node)?;
+        }
+
+        let js_samples = JsArray::new(cx, sample_nodes.len() as u32);
+        let time_deltas = JsArray::new(cx, sample_nodes.len() as u32);
+        let mut previous = Duration::default();
+        for (i, (&node, (_, time))) in sample_nodes.iter().zip(&self.samples).enumerate() {
+            let value = cx.number(node as f64 + 1.0);
+            js_samples.set(cx, i as u32, value)?;
+            let value = cx.number((*time - previous).as_micros() as f64);
+            time_deltas.set(cx, i as u32, value)?;
+            previous = *time;
+        }
+
+        let profile = cx.empty_object();
+        profile.set(cx, "nodes", js_nodes)?;
+        let value = cx.number(0);
+        profile.set(cx, "startTime", value)?;
+        let value = cx.number(self.duration.as_micros() as f64);
+        profile.set(cx, "endTime", value)?;
+        profile.set(cx, "samples", js_samples)?;
+        profile.set(cx, "timeDeltas", time_deltas)?;
+        Ok(profile.upcast())
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl ToJs for Profile {
+    /// `{ mode, duration, functions, samples, cpuProfile, folded }`, with times in milliseconds
+    /// and functions sorted by self time.
+    fn to_js<'a, CX: Context<'a>>(&self, cx: &mut CX) -> JsResult<'a, JsValue> {
+        let mut order: Vec<usize> = (0..self.functions.len()).collect();
+        order.sort_by(|&a, &b| self.functions[b].self_time.cmp(&self.functions[a].self_time));
+        let functions = JsArray::new(cx, order.len() as u32);
+        for (i, &function) in order.iter().enumerate() {
+            let summary = &self.functions[function];
+            let object = cx.empty_object();
+            let value = cx.string(&summary.id.name);
+            object.set(cx, "name", value)?;
+            let value = cx.string(&summary.id.source);
+            object.set(cx, "source", value)?;
+            let value = cx.number(summary.id.line as f64);
+            object.set(cx, "line", value)?;
+            let value = cx.number(summary.calls as f64);
+            object.set(cx, "calls", value)?;
+            let value = cx.number(milliseconds(summary.self_time));
+            object.set(cx, "selfTime", value)?;
+            let value = cx.number(milliseconds(summary.total_time));
+            object.set(cx, "totalTime", value)?;
+            functions.set(cx, i as u32, object)?;
+        }
+
+        let samples = JsArray::new(cx, self.samples.len() as u32);
+        for (i, (stack, time)) in self.samples.iter().enumerate() {
+            let js_stack = JsArray::new(cx, stack.len() as u32);
+            for (j, &function) in stack.iter().enumerate() {
+                let value = cx.string(&self.labels[function]);
+                js_stack.set(cx, j as u32, value)?;
+            }
+            let object = cx.empty_object();
+            object.set(cx, "stack", js_stack)?;
+            let value = cx.number(milliseconds(*time));
+            object.set(cx, "time", value)?;
+            samples.set(cx, i as u32, object)?;
+        }
+
+        let result = cx.empty_object();
+        let value = cx.string(self.mode.as_str());
+        result.set(cx, "mode", value)?;
+        let value = cx.number(milliseconds(self.duration));
+        result.set(cx, "duration", value)?;
+        result.set(cx, "functions", functions)?;
+        result.set(cx, "samples", samples)?;
+        let cpu_profile = self.cpu_profile(cx)?;
+        result.set(cx, "cpuProfile", cpu_profile)?;
+        let value = cx.string(self.folded());
+        result.set(cx, "folded", value)?;
+        Ok(result.upcast())
+    }
+}
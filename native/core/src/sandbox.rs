@@ -0,0 +1,186 @@
+//! Sandboxing below the granularity of `StdLib` flags.
+//!
+//! A `Sandbox` is applied right after the libraries are opened (and again on `reset`). It trims
+//! library tables down to an allow list, removes denied functions, and removes whole globals such
+//! as `load` or `dofile`. Named profiles provide the common configurations, and the caller's
+//! allow/deny lists are merged on top.
+use std::collections::HashMap;
+
+use mlua::prelude::{Lua, LuaTable, LuaValue};
+
+use crate::value::Value;
+
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    // Library name to the only functions kept in it
+    pub allow: HashMap<String, Vec<String>>,
+    // Library name to functions removed from it
+    pub deny: HashMap<String, Vec<String>>,
+    // Globals removed entirely
+    pub remove: Vec<String>,
+}
+
+// Globals that can load or run arbitrary code, or bypass metatables.
+const UNSAFE_GLOBALS: [&str; 9] = [
+    "load",
+    "loadstring",
+    "loadfile",
+    "dofile",
+    "rawset",
+    "rawget",
+    "rawequal",
+    "setfenv",
+    "getfenv",
+];
+
+const OS_ALLOW: [&str; 4] = ["clock", "date", "difftime", "time"];
+
+impl Sandbox {
+    /// The built-in profiles:
+    /// - `none` changes nothing.
+    /// - `standard` keeps only the time functions of `os`, and removes `io`, `dofile` and
+    ///   `loadfile`.
+    /// - `strict` additionally removes `string.rep`, `collectgarbage`, `package`/`require`,
+    ///   `debug` and the globals that load code or bypass metatables.
+    pub fn profile(name: &str) -> Option<Sandbox> {
+        let mut sandbox = Sandbox::default();
+        match name {
+            "none" => {}
+            "standard" => {
+                sandbox.allow.insert(String::from("os"), to_strings(&OS_ALLOW));
+                sandbox.remove = to_strings(&["io", "dofile", "loadfile"]);
+            }
+            "strict" => {
+                sandbox.allow.insert(String::from("os"), to_strings(&OS_ALLOW));
+                sandbox.deny.insert(String::from("string"), to_strings(&["rep"]));
+                sandbox.remove = to_strings(&UNSAFE_GLOBALS);
+                sandbox.remove.extend(to_strings(&[
+                    "io",
+                    "collectgarbage",
+                    "package",
+                    "require",
+                    "module",
+                    "debug",
+                ]));
+            }
+            _ => return None,
+        }
+        Some(sandbox)
+    }
+
+    /// Merges `other` into this sandbox. Allow lists from `other` replace the ones for the same
+    /// library, deny lists and removed globals are added.
+    pub fn merge(&mut self, other: Sandbox) {
+        for (library, names) in other.allow {
+            self.allow.insert(library, names);
+        }
+        for (library, names) in other.deny {
+            self.deny.entry(library).or_insert_with(Vec::new).extend(names);
+        }
+        self.remove.extend(other.remove);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.remove.is_empty()
+    }
+
+    pub fn apply(&self, lua: &Lua) -> mlua::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let globals = lua.globals();
+        for (library, names) in self.allow.iter() {
+            if let Some(table) = library_table(lua, library)? {
+                let mut removed = vec![];
+                for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+                    let (key, _) = pair?;
+                    let keep = match &key {
+                        LuaValue::String(s) => names.iter().any(|name| s.as_bytes() == name.as_bytes()),
+                        _ => false,
+                    };
+                    if !keep {
+                        removed.push(key);
+                    }
+                }
+                for key in removed {
+                    table.raw_set(key, LuaValue::Nil)?;
+                }
+            }
+        }
+        for (library, names) in self.deny.iter() {
+            if let Some(table) = library_table(lua, library)? {
+                for name in names {
+                    table.raw_set(name.as_str(), LuaValue::Nil)?;
+                }
+            }
+        }
+        let loaded = package_loaded(lua)?;
+        for name in self.remove.iter() {
+            globals.raw_set(name.as_str(), LuaValue::Nil)?;
+            // Otherwise `require` would hand the library back
+            if let Some(loaded) = &loaded {
+                loaded.raw_set(name.as_str(), LuaValue::Nil)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn library_table<'lua>(lua: &'lua Lua, library: &str) -> mlua::Result<Option<LuaTable<'lua>>> {
+    match lua.globals().raw_get::<_, LuaValue>(library)? {
+        LuaValue::Table(table) => Ok(Some(table)),
+        _ => Ok(None),
+    }
+}
+
+fn package_loaded(lua: &Lua) -> mlua::Result<Option<LuaTable>> {
+    match library_table(lua, "package")? {
+        Some(package) => match package.raw_get::<_, LuaValue>("loaded")? {
+            LuaValue::Table(loaded) => Ok(Some(loaded)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Describes the global environment: each global maps to its Lua type, except library tables,
+/// which map to the sorted names of their fields.
+pub fn environment(lua: &Lua) -> mlua::Result<Value> {
+    let mut entries: Vec<(String, Value)> = vec![];
+    for pair in lua.globals().pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        let name = match key {
+            LuaValue::String(s) => s.to_str()?.to_owned(),
+            _ => continue,
+        };
+        let description = match value {
+            LuaValue::Table(table) if name != "_G" => {
+                let mut fields = vec![];
+                for pair in table.pairs::<LuaValue, LuaValue>() {
+                    if let (LuaValue::String(s), _) = pair? {
+                        fields.push(s.to_str()?.to_owned());
+                    }
+                }
+                fields.sort();
+                let fields = fields
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, field)| (Value::Integer(i as i64), Value::String(field)))
+                    .collect();
+                Value::ObjectLike(vec![], fields)
+            }
+            value => Value::String(value.type_name().to_string()),
+        };
+        entries.push((name, description));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let entries = entries
+        .into_iter()
+        .map(|(name, description)| (Value::String(name), description))
+        .collect();
+    Ok(Value::ObjectLike(entries, vec![]))
+}
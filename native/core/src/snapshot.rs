@@ -0,0 +1,313 @@
+//! Serialization of the global state for `snapshot`/`restore`.
+//!
+//! Everything reachable from the globals is written out: tables, strings, numbers and booleans.
+//! Tables are numbered in the order they're first seen, and later occurrences refer back to that
+//! number, so shared tables and cycles survive. Functions, userdata and threads can't be
+//! serialized; they're written as a placeholder that `restore` leaves alone, or rejected in strict
+//! mode. Library tables (anything in `package.loaded`) and the base library's globals belong to
+//! the state and are left out. Metatables aren't kept.
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use mlua::prelude::{Lua, LuaTable, LuaValue};
+
+const MAGIC: &[u8; 4] = b"LJSS";
+const FORMAT_VERSION: u8 = 1;
+
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INTEGER: u8 = 3;
+const NUMBER: u8 = 4;
+const STRING: u8 = 5;
+// Followed by the pair count and the pairs, and numbered by the order tables are defined in
+const TABLE: u8 = 6;
+const TABLE_REF: u8 = 7;
+const SKIPPED: u8 = 8;
+
+// The globals table is always table 0
+const GLOBALS_ID: u32 = 0;
+
+// Same limit as msgpack.rs: deep enough for real data, and shallow enough that hostile input can't
+// overflow the stack. Cycles don't count, they're written as references.
+const MAX_DEPTH: usize = 128;
+
+fn error(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+fn too_deep() -> mlua::Error {
+    error(format!("snapshot nested more than {} tables deep", MAX_DEPTH))
+}
+
+struct Writer<'lua> {
+    out: Vec<u8>,
+    // Table -> id, or `false` for tables to skip
+    ids: LuaTable<'lua>,
+    next_id: u32,
+    strict: bool,
+    // Names of the globals that are functions (or `_VERSION`) in a fresh state
+    builtins: HashSet<Vec<u8>>,
+}
+
+/// Serializes the globals. `baseline` is a fresh state built the same way, which tells the base
+/// library's functions apart from the script's. In strict mode, unsupported values are an error
+/// naming where they were found.
+pub fn snapshot(lua: &Lua, baseline: &Lua, strict: bool) -> mlua::Result<Vec<u8>> {
+    let mut builtins = HashSet::new();
+    for pair in baseline.globals().pairs::<LuaValue, LuaValue>() {
+        match pair? {
+            (LuaValue::String(name), LuaValue::Function(_))
+            | (LuaValue::String(name), LuaValue::String(_)) => {
+                builtins.insert(name.as_bytes().to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    let ids = lua.create_table()?;
+    let globals = lua.globals();
+    if let Ok(LuaValue::Table(package)) = globals.raw_get::<_, LuaValue>("package") {
+        if let LuaValue::Table(loaded) = package.raw_get::<_, LuaValue>("loaded")? {
+            for pair in loaded.pairs::<LuaValue, LuaValue>() {
+                if let (_, LuaValue::Table(library)) = pair? {
+                    ids.raw_set(library, false)?;
+                }
+            }
+        }
+        ids.raw_set(package, false)?;
+    }
+    // `_G` is in package.loaded, but it's the root rather than a library
+    ids.raw_set(globals.clone(), GLOBALS_ID)?;
+
+    let mut writer = Writer {
+        out: MAGIC.to_vec(),
+        ids,
+        next_id: GLOBALS_ID + 1,
+        strict,
+        builtins,
+    };
+    writer.out.push(FORMAT_VERSION);
+    writer.write_table_contents(&globals, "_G", true, 0)?;
+    Ok(writer.out)
+}
+
+impl<'lua> Writer<'lua> {
+    fn write_value(&mut self, value: LuaValue<'lua>, path: &str, depth: usize) -> mlua::Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(too_deep());
+        }
+        match value {
+            LuaValue::Nil => self.out.push(NIL),
+            LuaValue::Boolean(false) => self.out.push(FALSE),
+            LuaValue::Boolean(true) => self.out.push(TRUE),
+            LuaValue::Integer(i) => {
+                self.out.push(INTEGER);
+                self.out.extend_from_slice(&(i as i64).to_le_bytes());
+            }
+            LuaValue::Number(n) => {
+                self.out.push(NUMBER);
+                self.out.extend_from_slice(&(n as f64).to_le_bytes());
+            }
+            LuaValue::String(s) => {
+                self.out.push(STRING);
+                self.write_bytes(s.as_bytes());
+            }
+            LuaValue::Table(table) => match self.ids.raw_get::<_, LuaValue>(table.clone())? {
+                LuaValue::Integer(id) => {
+                    self.out.push(TABLE_REF);
+                    self.out.extend_from_slice(&(id as u32).to_le_bytes());
+                }
+                LuaValue::Number(id) => {
+                    self.out.push(TABLE_REF);
+                    self.out.extend_from_slice(&(id as u32).to_le_bytes());
+                }
+                LuaValue::Boolean(false) => self.skip("library table", path)?,
+                _ => {
+                    self.ids.raw_set(table.clone(), self.next_id)?;
+                    self.next_id += 1;
+                    self.out.push(TABLE);
+                    self.write_table_contents(&table, path, false, depth)?;
+                }
+            },
+            other => self.skip(other.type_name(), path)?,
+        }
+        Ok(())
+    }
+
+    fn write_table_contents(
+        &mut self,
+        table: &LuaTable<'lua>,
+        path: &str,
+        is_globals: bool,
+        depth: usize,
+    ) -> mlua::Result<()> {
+        let mut pairs = vec![];
+        for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+            let (key, value) = pair?;
+            if is_globals {
+                if let LuaValue::String(name) = &key {
+                    if self.builtins.contains(name.as_bytes()) {
+                        continue;
+                    }
+                }
+            }
+            // Pairs with keys we can't restore are left out entirely
+            match key {
+                LuaValue::Function(_)
+                | LuaValue::UserData(_)
+                | LuaValue::LightUserData(_)
+                | LuaValue::Thread(_) => {
+                    if self.strict {
+                        return Err(error(format!(
+                            "can't snapshot the {} key in {}",
+                            key.type_name(),
+                            path
+                        )));
+                    }
+                }
+                key => pairs.push((key, value)),
+            }
+        }
+        self.out.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+        for (key, value) in pairs {
+            let child = match &key {
+                LuaValue::String(s) => format!("{}.{}", path, s.to_str().unwrap_or("?")),
+                LuaValue::Integer(i) => format!("{}[{}]", path, i),
+                _ => format!("{}[?]", path),
+            };
+            self.write_value(key, &child, depth + 1)?;
+            self.write_value(value, &child, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, kind: &str, path: &str) -> mlua::Result<()> {
+        if self.strict {
+            return Err(error(format!("can't snapshot the {} at {}", kind, path)));
+        }
+        self.out.push(SKIPPED);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        self.out.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a, 'lua> {
+    lua: &'lua Lua,
+    data: &'a [u8],
+    position: usize,
+    tables: Vec<LuaTable<'lua>>,
+}
+
+/// Assigns the globals from a snapshot. Skipped values keep whatever the state has. The whole
+/// snapshot is decoded before any global is touched, so a corrupt one leaves the state unchanged.
+pub fn restore(lua: &Lua, data: &[u8]) -> mlua::Result<()> {
+    if data.len() < 5 || &data[..4] != MAGIC {
+        return Err(error(String::from("not a Lua state snapshot")));
+    }
+    if data[4] != FORMAT_VERSION {
+        return Err(error(format!("unsupported snapshot format version {}", data[4])));
+    }
+    let mut reader = Reader {
+        lua,
+        data,
+        position: 5,
+        tables: vec![lua.globals()],
+    };
+    // References to table 0 inside the snapshot still point at the real globals, which is fine as
+    // nothing is assigned into them until decoding is done
+    let staging = lua.create_table()?;
+    reader.read_table_contents(&staging, 0)?;
+    if reader.position != data.len() {
+        return Err(error(String::from("trailing data after the snapshot")));
+    }
+    let globals = lua.globals();
+    for pair in staging.pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        globals.raw_set(key, value)?;
+    }
+    Ok(())
+}
+
+/// A decoded value, where `Skipped` stands for something that wasn't serialized.
+enum Decoded<'lua> {
+    Value(LuaValue<'lua>),
+    Skipped,
+}
+
+impl<'a, 'lua> Reader<'a, 'lua> {
+    fn read_value(&mut self, depth: usize) -> mlua::Result<Decoded<'lua>> {
+        if depth > MAX_DEPTH {
+            return Err(too_deep());
+        }
+        let value = match self.byte()? {
+            NIL => LuaValue::Nil,
+            FALSE => LuaValue::Boolean(false),
+            TRUE => LuaValue::Boolean(true),
+            INTEGER => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                LuaValue::Integer(i64::from_le_bytes(bytes) as _)
+            }
+            NUMBER => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                LuaValue::Number(f64::from_le_bytes(bytes) as _)
+            }
+            STRING => {
+                let length = self.length()?;
+                let bytes = self.take(length)?;
+                LuaValue::String(self.lua.create_string(bytes)?)
+            }
+            TABLE => {
+                let table = self.lua.create_table()?;
+                self.tables.push(table.clone());
+                self.read_table_contents(&table, depth)?;
+                LuaValue::Table(table)
+            }
+            TABLE_REF => {
+                let id = self.length()?;
+                match self.tables.get(id) {
+                    Some(table) => LuaValue::Table(table.clone()),
+                    None => return Err(error(format!("snapshot refers to unknown table {}", id))),
+                }
+            }
+            SKIPPED => return Ok(Decoded::Skipped),
+            tag => return Err(error(format!("corrupt snapshot, unknown tag {}", tag))),
+        };
+        Ok(Decoded::Value(value))
+    }
+
+    fn read_table_contents(&mut self, table: &LuaTable<'lua>, depth: usize) -> mlua::Result<()> {
+        let count = self.length()?;
+        for _ in 0..count {
+            let key = self.read_value(depth + 1)?;
+            let value = self.read_value(depth + 1)?;
+            if let (Decoded::Value(key), Decoded::Value(value)) = (key, value) {
+                table.raw_set(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self) -> mlua::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn length(&mut self) -> mlua::Result<usize> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn take(&mut self, length: usize) -> mlua::Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.data.len() {
+            return Err(error(String::from("corrupt snapshot, unexpected end of data")));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
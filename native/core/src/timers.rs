@@ -0,0 +1,114 @@
+//! The opt-in `timer` library: `timer.after(ms, fn)`, `timer.every(ms, fn)` and `timer.cancel(id)`.
+//!
+//! Lua only keeps the callbacks, in a registry table by timer id. The waiting is done by Node's
+//! timers, through a scheduler function lib/index.js gives the state, and expired timers are run
+//! with `run`. Since that happens from the event loop, callbacks never interrupt a running script.
+//! Timers belong to the Lua state they were created in, so `reset()` and `close()` cancel them.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use mlua::prelude::{Lua, LuaFunction, LuaMultiValue, LuaTable, LuaValue};
+
+use crate::error::Result;
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::lua_execution;
+use crate::value::Value;
+
+const TIMERS_KEY: &str = "lua-js.timers";
+
+/// Adds the `timer` table, also available as `require("timer")` when `package` is loaded. The
+/// scheduler is called with `("after" | "every", id, ms)` and `("cancel", id)`.
+pub fn install(lua: &Lua, scheduler: &JsRef) -> mlua::Result<()> {
+    lua.set_named_registry_value(TIMERS_KEY, lua.create_table()?)?;
+    let next_id = Arc::new(AtomicU64::new(1));
+
+    let timer = lua.create_table()?;
+    for &(name, every) in &[("after", false), ("every", true)] {
+        let scheduler = scheduler.clone();
+        let next_id = next_id.clone();
+        let start = js_context::create_function(lua, move |lua, args: LuaMultiValue| {
+            let mut args = args.into_iter();
+            let ms = match args.next() {
+                Some(LuaValue::Integer(ms)) if ms >= 0 => ms as f64,
+                Some(LuaValue::Number(ms)) if ms >= 0.0 => ms as f64,
+                _ => return Err(bad_argument(1, name, "non-negative number expected")),
+            };
+            let callback = match args.next() {
+                Some(LuaValue::Function(callback)) => callback,
+                _ => return Err(bad_argument(2, name, "function expected")),
+            };
+            let id = next_id.fetch_add(1, Ordering::Relaxed) as i64;
+            let entry = lua.create_table()?;
+            entry.raw_set("callback", callback)?;
+            entry.raw_set("every", every)?;
+            timers(lua)?.raw_set(id, entry)?;
+            js_context::call(
+                &scheduler,
+                None,
+                &[
+                    Value::String(String::from(name)),
+                    Value::Integer(id),
+                    Value::Double(ms),
+                ],
+            )?;
+            Ok(Value::Integer(id))
+        })?;
+        timer.raw_set(name, start)?;
+    }
+
+    let scheduler = scheduler.clone();
+    let cancel = js_context::create_function(lua, move |lua, args: LuaMultiValue| {
+        let id = match args.into_iter().next() {
+            Some(LuaValue::Integer(id)) => id as i64,
+            Some(LuaValue::Number(id)) => id as i64,
+            _ => return Err(bad_argument(1, "cancel", "timer id expected")),
+        };
+        let timers = timers(lua)?;
+        if let LuaValue::Nil = timers.raw_get::<_, LuaValue>(id)? {
+            return Ok(Value::Boolean(false));
+        }
+        timers.raw_set(id, LuaValue::Nil)?;
+        js_context::call(
+            &scheduler,
+            None,
+            &[Value::String(String::from("cancel")), Value::Integer(id)],
+        )?;
+        Ok(Value::Boolean(true))
+    })?;
+    timer.raw_set("cancel", cancel)?;
+
+    if let Ok(LuaValue::Table(package)) = lua.globals().raw_get::<_, LuaValue>("package") {
+        if let LuaValue::Table(loaded) = package.raw_get::<_, LuaValue>("loaded")? {
+            loaded.raw_set("timer", timer.clone())?;
+        }
+    }
+    lua.globals().raw_set("timer", timer)
+}
+
+/// Runs the callback of an expired timer. Timers from `after`, and ones cancelled in the meantime,
+/// are forgotten first, so this does nothing for an unknown id.
+pub fn run(lua: &Lua, id: i64) -> Result<()> {
+    let timers = timers(lua)?;
+    let entry = match timers.raw_get::<_, LuaValue>(id)? {
+        LuaValue::Table(entry) => entry,
+        _ => return Ok(()),
+    };
+    let callback: LuaFunction = entry.raw_get("callback")?;
+    if !entry.raw_get::<_, bool>("every")? {
+        timers.raw_set(id, LuaValue::Nil)?;
+    }
+    lua_execution::protected_call(lua, callback, vec![])?;
+    Ok(())
+}
+
+fn timers(lua: &Lua) -> mlua::Result<LuaTable> {
+    lua.named_registry_value(TIMERS_KEY)
+}
+
+fn bad_argument(position: u32, function: &str, message: &str) -> mlua::Error {
+    mlua::Error::RuntimeError(format!(
+        "bad argument #{} to '{}' ({})",
+        position, function, message
+    ))
+}
@@ -0,0 +1,208 @@
+//! JS classes exposed to Lua as userdata types via `registerClass`.
+//!
+//! Every registered class shares the single `JsUserData` type on the Lua side. An instance holds a
+//! reference to its JS object along with its class definition, and the metamethods dispatch to the
+//! JS functions registered for that class.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use mlua::prelude::{FromLua, Lua, LuaMultiValue, LuaValue, ToLua};
+use mlua::{MetaMethod, UserData, UserDataMethods};
+use neon::prelude::*;
+
+use crate::conversion;
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::value::Value;
+
+#[derive(Debug)]
+pub struct UserClass {
+    pub name: String,
+    pub constructor: JsRef,
+    pub methods: HashMap<String, JsRef>,
+    pub getters: HashMap<String, JsRef>,
+    pub setters: HashMap<String, JsRef>,
+    pub metamethods: HashMap<String, JsRef>,
+}
+
+/// Lua-side instance of a registered class.
+pub struct JsUserData {
+    class: Arc<UserClass>,
+    object: JsRef,
+}
+
+impl JsUserData {
+    pub fn new(class: Arc<UserClass>, object: JsRef) -> Self {
+        JsUserData { class, object }
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::UserData(self.class.clone(), self.object.clone())
+    }
+}
+
+const BINARY_METAMETHODS: [(MetaMethod, &str); 10] = [
+    (MetaMethod::Add, "__add"),
+    (MetaMethod::Sub, "__sub"),
+    (MetaMethod::Mul, "__mul"),
+    (MetaMethod::Div, "__div"),
+    (MetaMethod::Mod, "__mod"),
+    (MetaMethod::Pow, "__pow"),
+    (MetaMethod::Concat, "__concat"),
+    (MetaMethod::Eq, "__eq"),
+    (MetaMethod::Lt, "__lt"),
+    (MetaMethod::Le, "__le"),
+];
+
+const UNARY_METAMETHODS: [(MetaMethod, &str); 3] = [
+    (MetaMethod::Unm, "__unm"),
+    (MetaMethod::Len, "__len"),
+    (MetaMethod::ToString, "__tostring"),
+];
+
+impl UserData for JsUserData {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: LuaValue| match key {
+            LuaValue::String(key) => this.index(lua, key.to_str()?.to_owned()),
+            // Like a missing field, e.g. `instance[1]`
+            _ => Ok(LuaValue::Nil),
+        });
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |_, this, (key, value): (String, Value)| this.new_index(key, value),
+        );
+        for (meta, name) in BINARY_METAMETHODS.iter() {
+            let name: &'static str = name;
+            methods.add_meta_function(
+                meta.clone(),
+                move |lua, (lhs, rhs): (LuaValue, LuaValue)| {
+                    call_metamethod(lua, name, vec![lhs, rhs])
+                },
+            );
+        }
+        for (meta, name) in UNARY_METAMETHODS.iter() {
+            let name: &'static str = name;
+            // Lua passes the operand twice for unary metamethods, so only take the first.
+            methods.add_meta_function(meta.clone(), move |lua, args: LuaMultiValue| {
+                let operand = args.into_iter().next().unwrap_or(LuaValue::Nil);
+                call_metamethod(lua, name, vec![operand])
+            });
+        }
+    }
+}
+
+impl JsUserData {
+    fn index<'lua>(&self, lua: &'lua Lua, key: String) -> mlua::Result<LuaValue<'lua>> {
+        if let Some(method) = self.class.methods.get(&key) {
+            // Invoked as `instance:method(...)`, so the instance is the first argument.
+            let method = method.clone();
+            let f = js_context::create_function(lua, move |lua, args: LuaMultiValue| {
+                let mut values = Value::into_vec_for_lua_multi(args, lua)?;
+                if values.is_empty() {
+                    return Err(mlua::Error::RuntimeError(String::from(
+                        "userdata methods must be called with ':'",
+                    )));
+                }
+                let this = values.remove(0);
+                js_context::call(&method, Some(&this), &values)
+            })?;
+            Ok(LuaValue::Function(f))
+        } else if let Some(getter) = self.class.getters.get(&key) {
+            let this = self.to_value();
+            js_context::call(getter, Some(&this), &[])?.to_lua(lua)
+        } else {
+            Ok(LuaValue::Nil)
+        }
+    }
+
+    fn new_index(&self, key: String, value: Value) -> mlua::Result<()> {
+        match self.class.setters.get(&key) {
+            Some(setter) => {
+                let this = self.to_value();
+                js_context::call(setter, Some(&this), &[value])?;
+                Ok(())
+            }
+            None => Err(mlua::Error::RuntimeError(format!(
+                "cannot set field '{}' on a {} value",
+                key, self.class.name
+            ))),
+        }
+    }
+}
+
+fn call_metamethod<'lua>(
+    lua: &'lua Lua,
+    name: &str,
+    operands: Vec<LuaValue<'lua>>,
+) -> mlua::Result<LuaValue<'lua>> {
+    // At least one of the operands is our userdata, otherwise Lua wouldn't have called us.
+    let class = operands.iter().find_map(|operand| match operand {
+        LuaValue::UserData(ud) => ud.borrow::<JsUserData>().ok().map(|ud| ud.class.clone()),
+        _ => None,
+    });
+    let class = match class {
+        Some(class) => class,
+        None => return Err(mlua::Error::UserDataTypeMismatch),
+    };
+    let args = operands
+        .into_iter()
+        .map(|operand| Value::from_lua(operand, lua))
+        .collect::<mlua::Result<Vec<Value>>>()?;
+
+    match class.metamethods.get(name) {
+        Some(f) => js_context::call(f, None, &args)?.to_lua(lua),
+        None => match (name, args.as_slice()) {
+            ("__eq", [Value::UserData(_, lhs), Value::UserData(_, rhs)]) => {
+                let equal = js_context::with_context(|cx| {
+                    let lhs = lhs.to_handle(cx)?;
+                    let rhs = rhs.to_handle(cx)?;
+                    Ok(lhs.strict_equals(cx, rhs))
+                })?;
+                Ok(LuaValue::Boolean(equal))
+            }
+            ("__tostring", [Value::UserData(_, object)]) => {
+                let s = format!("{}: {}", class.name, object.id());
+                Ok(LuaValue::String(lua.create_string(&s)?))
+            }
+            _ => Err(mlua::Error::RuntimeError(format!(
+                "attempt to use '{}' on a {} value",
+                name, class.name
+            ))),
+        },
+    }
+}
+
+/// Creates the global class table for `class`, exposing `Class.new(...)` to Lua.
+pub fn create_class_table<'lua>(
+    lua: &'lua Lua,
+    class: Arc<UserClass>,
+) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    let new = js_context::create_function(lua, move |lua, args: LuaMultiValue| {
+        let values = Value::into_vec_for_lua_multi(args, lua)?;
+        let object = js_context::construct(&class.constructor, &values)?;
+        Ok(Value::UserData(class.clone(), object))
+    })?;
+    table.set("new", new)?;
+    Ok(table)
+}
+
+/// Finds the registered class `object` is an instance of.
+pub fn class_of<'a, CX: Context<'a>>(
+    cx: &mut CX,
+    object: Handle<'a, JsObject>,
+) -> NeonResult<Option<Arc<UserClass>>> {
+    let classes: Vec<Arc<UserClass>> = match js_context::current_conversions() {
+        Some(registry) => match registry.read() {
+            Ok(conversions) => conversions.classes.clone(),
+            Err(_) => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    for class in classes {
+        if conversion::is_instance_of_ref(cx, object, &class.constructor)? {
+            return Ok(Some(class));
+        }
+    }
+    Ok(None)
+}
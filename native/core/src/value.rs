@@ -1,14 +1,54 @@
 //! Rust intermediate state between JS and Lua Value types.
+//!
+//! Without the `node` feature there's no JS side: `Value` is what `Engine` hands in and out of
+//! Lua, and the variants holding JS references don't exist.
+#[cfg(feature = "node")]
+use crate::conversion::{self, SymbolKeys};
+#[cfg(feature = "node")]
+use crate::js_ref::JsRef;
+#[cfg(feature = "node")]
 use crate::js_traits::{FromJs, ToJs};
-use mlua::prelude::{FromLua, Lua, LuaMultiValue, LuaValue, ToLua};
+use crate::metatables;
+#[cfg(feature = "node")]
+use crate::user_data::{self, JsUserData, UserClass};
+use mlua::prelude::{FromLua, Lua, LuaMultiValue, LuaTable, LuaValue, ToLua};
+#[cfg(feature = "node")]
+use mlua::prelude::LuaLightUserData;
+#[cfg(feature = "node")]
+use std::ffi::c_void;
+#[cfg(feature = "node")]
+use std::sync::Arc;
 
+#[cfg(feature = "node")]
 use neon::result::NeonResult;
-use neon::types::{JsBoolean, JsNull, JsNumber, JsObject, JsString, JsUndefined, JsValue, JsFunction};
+#[cfg(feature = "node")]
+use neon::types::{JsBoolean, JsError, JsNull, JsNumber, JsObject, JsString, JsUndefined, JsValue, JsFunction};
+#[cfg(feature = "node")]
 use neon::{context::Context, handle::Handle, object::Object};
 
+#[cfg(any(feature = "node", feature = "serialize"))]
 const JS_MAX_SAFE_INTEGER: i64 = 9007199254740991;
 
+// Numeric contract between JS and Lua:
+//  - Integral JS numbers within the safe integer range become Lua integers, everything else
+//    (including -0, NaN and +/-Infinity) is a Lua float. BigInts become Lua integers.
+//  - Lua integers become JS numbers, or BigInts when outside the safe integer range. Lua floats
+//    become JS numbers as-is, so `math.huge` is `Infinity` and -0 keeps its sign.
+//  - Lua 5.1, 5.2 and LuaJIT have no integer subtype. mlua reports their integral doubles as
+//    integers, so those are mapped back to floats. This also means -0 reads back as 0 there.
+//  - NaN can't be used as a table key.
+
+/// With the `serialize` feature, `Value` implements `Serialize` and `Deserialize` as
+/// `{ "type": ..., "value": ... }`, with `type` the camelCased variant name. `ObjectLike` keeps its
+/// pair lists as lists of `[key, value]` pairs, so nothing is lost but non-finite doubles in
+/// formats that can't hold them. `UserData` and `Symbol` refer to live JS objects and can't be
+/// serialized.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serialize",
+    serde(tag = "type", content = "value", rename_all = "camelCase")
+)]
 pub enum Value {
     // This lets us choose what our JS output is.
     // Both Null and Undefined convert to nil on the Lua side
@@ -20,7 +60,26 @@ pub enum Value {
     Double(f64),
     // (k/v pairs, numerically indexed values)
     ObjectLike(Vec<(Value, Value)>, Vec<(Value, Value)>),
-    Error(String),
+    // JS Error objects, and Lua tables tagged as errors. On the Lua side these are tables with
+    // `name`, `message` and `stack` fields and a `__tostring` metamethod.
+    Error {
+        name: String,
+        message: String,
+        stack: Option<String>,
+    },
+    // An instance of a class registered with `registerClass`. Becomes userdata on the Lua side
+    // and converts back to the original JS object.
+    #[cfg(feature = "node")]
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    UserData(Arc<UserClass>, JsRef),
+    // A value produced by a `toLua` hook (or headed for a `fromLua` hook), tagged with the name
+    // of its Lua metatable.
+    Tagged(String, Box<Value>),
+    // A JS symbol, passed to Lua as a light userdata holding its reference id. Only produced when
+    // the `symbols` conversion option is "lightuserdata".
+    #[cfg(feature = "node")]
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    Symbol(JsRef),
 }
 
 impl Value {
@@ -36,6 +95,130 @@ impl Value {
     }
 }
 
+/// Integral numbers become Lua integers so `//`, `math.type` and `tostring` behave as they would
+/// for literals. -0 stays a float to keep its sign.
+#[cfg(any(feature = "node", feature = "serialize"))]
+fn number_from_js(f: f64) -> Value {
+    let is_negative_zero = f == 0.0 && f.is_sign_negative();
+    if f.is_finite() && f.trunc() == f && f.abs() <= JS_MAX_SAFE_INTEGER as f64 && !is_negative_zero {
+        Value::Integer(f as i64)
+    } else {
+        Value::Double(f)
+    }
+}
+
+#[cfg(any(feature = "lua54", feature = "lua53"))]
+fn integer_from_lua(i: i64) -> Value {
+    Value::Integer(i)
+}
+
+#[cfg(not(any(feature = "lua54", feature = "lua53")))]
+fn integer_from_lua(i: i64) -> Value {
+    Value::Double(i as f64)
+}
+
+/// A table as JSON. Only array items make an array, with holes as `None`, and only pairs make an
+/// object, with number keys as strings. That's how `ToJs` shapes it too, except JS arrays can also
+/// have other properties while JSON can't, so a table with both is an error. Array items too sparse
+/// for `is_dense_array` make an object keyed by their Lua indexes instead.
+#[cfg(any(feature = "json", feature = "serialize"))]
+pub(crate) enum JsonShape<'a> {
+    Array(Vec<Option<&'a Value>>),
+    Object(Vec<(String, &'a Value)>),
+}
+
+/// Shapes `ObjectLike`'s pair lists for the `json` library and the serde_json conversion, so the
+/// two can't disagree. The error describes what can't be converted, e.g. "a non-string key".
+#[cfg(any(feature = "json", feature = "serialize"))]
+pub(crate) fn json_shape<'a>(
+    pairs: &'a [(Value, Value)],
+    array_like: &'a [(Value, Value)],
+) -> Result<JsonShape<'a>, &'static str> {
+    if !pairs.is_empty() && !array_like.is_empty() {
+        return Err("a table with both array items and other keys");
+    }
+    if !array_like.is_empty() {
+        let indexed = array_like
+            .iter()
+            .filter_map(|(index, item)| array_index(index).map(|index| (index, item)));
+        let length = indexed.clone().map(|(index, _)| index + 1).max().unwrap_or(0);
+        if !is_dense_array(length, array_like.len()) {
+            let entries = indexed
+                .map(|(index, item)| ((index + 1).to_string(), item))
+                .collect();
+            return Ok(JsonShape::Object(entries));
+        }
+        let mut items = vec![None; length];
+        for (index, item) in indexed {
+            items[index] = Some(item);
+        }
+        return Ok(JsonShape::Array(items));
+    }
+    let mut entries = Vec::with_capacity(pairs.len());
+    for (key, value) in pairs {
+        let key = match key {
+            Value::String(key) => key.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Double(f) => float_key(*f),
+            _ => return Err("a non-string key"),
+        };
+        entries.push((key, value));
+    }
+    Ok(JsonShape::Object(entries))
+}
+
+/// The 0-based index of an array item, if it's in the range of JS array indexes.
+#[cfg(any(feature = "json", feature = "serialize"))]
+fn array_index(index: &Value) -> Option<usize> {
+    match *index {
+        Value::Integer(i) if i >= 0 && i < u32::MAX as i64 => Some(i as usize),
+        Value::Double(f) if f >= 0.0 && f < u32::MAX as f64 => Some(f as usize),
+        _ => None,
+    }
+}
+
+// Arrays up to this length are always dense enough, whatever their holes
+#[cfg(any(feature = "json", feature = "serialize", feature = "node"))]
+const MAX_ARRAY_HOLES: usize = 10;
+
+/// Whether `count` items spread over `length` slots are worth writing as an array, with the holes
+/// filled in. That's when at least half the slots are used, or the array is short anyway. Sparser
+/// tables like `{[2^31] = 1}` would be mostly holes.
+#[cfg(any(feature = "json", feature = "serialize", feature = "node"))]
+pub(crate) fn is_dense_array(length: usize, count: usize) -> bool {
+    length <= MAX_ARRAY_HOLES || length <= count.saturating_mul(2)
+}
+
+/// Formats a float table key the same way JS stringifies the number.
+pub(crate) fn float_key(f: f64) -> String {
+    if f.is_infinite() {
+        String::from(if f > 0.0 { "Infinity" } else { "-Infinity" })
+    } else if f != 0.0 && (f.abs() >= 1e21 || f.abs() < 1e-6) {
+        // JS includes the sign of positive exponents, e.g. `1e+21`
+        let s = format!("{:e}", f);
+        match s.find('e') {
+            Some(i) if !s[i + 1..].starts_with('-') => format!("{}e+{}", &s[..i], &s[i + 1..]),
+            _ => s,
+        }
+    } else {
+        f.to_string()
+    }
+}
+
+/// JS property keys are never NaN, but `Value`s built in Rust (by `Engine` users, or deserialized
+/// with the `serialize` feature) can hold any double. Lua would raise "table index is NaN" for
+/// these, so they get a clearer error first.
+fn check_table_key(key: &Value) -> mlua::Result<()> {
+    match key {
+        Value::Double(f) if f.is_nan() => Err(mlua::Error::ToLuaConversionError {
+            from: "NaN",
+            to: "table key",
+            message: Some(String::from("NaN can't be used as a table key")),
+        }),
+        _ => Ok(()),
+    }
+}
+
 impl<'lua> ToLua<'lua> for Value {
     fn to_lua(self, lua: &'lua Lua) -> mlua::Result<LuaValue<'lua>> {
         match self {
@@ -47,10 +230,38 @@ impl<'lua> ToLua<'lua> for Value {
             Value::Double(f) => Ok(LuaValue::Number(f)),
             Value::Undefined | Value::Null => Ok(LuaValue::Nil),
             Value::Boolean(b) => Ok(LuaValue::Boolean(b)),
-            Value::Error(_err) => unimplemented!("ToLua for Error"),
+            Value::Error {
+                name,
+                message,
+                stack,
+            } => {
+                let table = lua.create_table()?;
+                table.raw_set("name", name)?;
+                table.raw_set("message", message)?;
+                table.raw_set("stack", stack)?;
+                table.set_metatable(Some(metatables::error_metatable(lua)?));
+                Ok(LuaValue::Table(table))
+            }
+            #[cfg(feature = "node")]
+            Value::UserData(class, object) => {
+                let ud = lua.create_userdata(JsUserData::new(class, object))?;
+                Ok(LuaValue::UserData(ud))
+            }
+            #[cfg(feature = "node")]
+            Value::Symbol(symbol) => Ok(LuaValue::LightUserData(LuaLightUserData(
+                symbol.id() as usize as *mut c_void,
+            ))),
+            Value::Tagged(name, value) => {
+                let lua_value = value.to_lua(lua)?;
+                if let LuaValue::Table(table) = &lua_value {
+                    table.set_metatable(Some(metatables::named_metatable(lua, &name)?));
+                }
+                Ok(lua_value)
+            }
             Value::ObjectLike(kv_pairs, array_like) => {
                 let table = lua.create_table()?;
                 for (i,v) in array_like.into_iter() {
+                    check_table_key(&i)?;
                     // TODO consolidate flow through one of these.
                     if let Value::Double(idx) = i {
                         table.raw_set(idx+1.0, v)?;
@@ -59,6 +270,7 @@ impl<'lua> ToLua<'lua> for Value {
                     }
                 }
                 for (k, v) in kv_pairs.into_iter() {
+                    check_table_key(&k)?;
                     table.raw_set(k, v)?;
                 }
                 Ok(LuaValue::Table(table))
@@ -67,25 +279,86 @@ impl<'lua> ToLua<'lua> for Value {
     }
 }
 
+#[cfg(feature = "node")]
+fn symbol_from_lua(ud: LuaLightUserData) -> Option<JsRef> {
+    conversion::symbol_from_id(ud.0 as usize as u64)
+}
+
+/// The metatable name of a table headed for a `fromLua` hook. Only JS has hooks.
+#[cfg(feature = "node")]
+fn hooked_metatable_name(table: &LuaTable) -> mlua::Result<Option<String>> {
+    conversion::hooked_metatable_name(table)
+}
+
+#[cfg(not(feature = "node"))]
+fn hooked_metatable_name(_table: &LuaTable) -> mlua::Result<Option<String>> {
+    Ok(None)
+}
+
+fn userdata_error() -> mlua::Error {
+    mlua::Error::FromLuaConversionError {
+        from: "userdata",
+        to: "JS value",
+        message: Some(String::from(
+            "only userdata created by a registered class can be converted",
+        )),
+    }
+}
+
+// Deep enough for real data, and shallow enough that a cycle is an error rather than a stack
+// overflow
+const MAX_DEPTH: usize = 128;
+
+fn too_deep_error() -> mlua::Error {
+    mlua::Error::FromLuaConversionError {
+        from: "table",
+        to: "JS value",
+        message: Some(format!(
+            "nested more than {} levels deep (is there a cycle?)",
+            MAX_DEPTH
+        )),
+    }
+}
+
+fn lightuserdata_error() -> mlua::Error {
+    mlua::Error::FromLuaConversionError {
+        from: "lightuserdata",
+        to: "JS value",
+        message: Some(String::from(
+            "only light userdata created from JS symbols can be converted",
+        )),
+    }
+}
+
 impl<'lua> FromLua<'lua> for Value {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> mlua::Result<Self> {
+    fn from_lua(lua_value: LuaValue<'lua>, _lua: &'lua Lua) -> mlua::Result<Self> {
+        Value::from_lua_nested(lua_value, 0)
+    }
+}
+
+impl Value {
+    fn from_lua_nested(lua_value: LuaValue, depth: usize) -> mlua::Result<Self> {
         match lua_value {
             LuaValue::Nil => Ok(Value::Null),
             LuaValue::Boolean(b) => Ok(Value::Boolean(b)),
-            LuaValue::Integer(i) => {
-                let int = i as i64;
-                if int > JS_MAX_SAFE_INTEGER || int < -JS_MAX_SAFE_INTEGER {
-                    Ok(Value::Integer(i as i64))
-                } else {
-                    Ok(Value::Double(i as f64))
-                }
-            },
+            LuaValue::Integer(i) => Ok(integer_from_lua(i)),
             LuaValue::Number(f) => Ok(Value::Double(f)),
             LuaValue::String(s) => {
                 let s = s.to_str()?;
                 Ok(Value::String(s.to_owned()))
             }
+            LuaValue::Table(_) if depth >= MAX_DEPTH => Err(too_deep_error()),
             LuaValue::Table(table) => {
+                let tag = hooked_metatable_name(&table)?;
+                if tag.is_none() && metatables::is_error_table(&table)? {
+                    let name: Option<String> = table.raw_get("name")?;
+                    let message: Option<String> = table.raw_get("message")?;
+                    return Ok(Value::Error {
+                        name: name.unwrap_or_else(|| String::from("Error")),
+                        message: message.unwrap_or_default(),
+                        stack: table.raw_get("stack")?,
+                    });
+                }
                 // We're simulating an object/array similar to how Lua implements it.
                 // we're using raw_len to avoid re-sizng the vec. I don't know if this is actually
                 // an optimization, though.
@@ -95,27 +368,36 @@ impl<'lua> FromLua<'lua> for Value {
 
                 for pair in table.pairs() {
                     let (key, value) = pair?;
-                    let value_v = Value::from_lua(value, lua)?;
+                    let value_v = Value::from_lua_nested(value, depth + 1)?;
                     match key {
-                        LuaValue::Integer(n) => {
+                        LuaValue::Integer(n) if n >= 1 && n <= u32::MAX as i64 => {
                             let idx = n-1;
                             indexed_values.push((Value::Integer(idx), value_v))
                         }
+                        LuaValue::Integer(n) => {
+                            // Outside the range of JS array indexes (including 0 and negatives)
+                            kv_pairs.push((Value::String(n.to_string()), value_v));
+                        }
                         LuaValue::Number(f) => {
                             // floats are converted to strings, as that's the only representation
                             // that makes any sense on the JS side.
-                            let float_key = f.to_string();
-                            kv_pairs.push((Value::String(float_key), value_v));
+                            kv_pairs.push((Value::String(float_key(f)), value_v));
                         }
                         LuaValue::String(s) => {
                             let string = s.to_str()?.to_owned();
                             kv_pairs.push((Value::String(string), value_v));
                         }
+                        #[cfg(feature = "node")]
+                        LuaValue::LightUserData(ud) if symbol_from_lua(ud).is_some() => {
+                            if let Some(symbol) = symbol_from_lua(ud) {
+                                kv_pairs.push((Value::Symbol(symbol), value_v));
+                            }
+                        }
                         // This handles all other cases where the property key is not representable
-                        // in JS. TODO config option to ignore/drop/convert to symbol?
-                        k @ _ => {
+                        // in JS.
+                        k => {
                             let err = mlua::Error::FromLuaConversionError {
-                                from: &k.type_name(),
+                                from: k.type_name(),
                                 to: "JS Object PropertyKey",
                                 message: None
                             };
@@ -123,7 +405,11 @@ impl<'lua> FromLua<'lua> for Value {
                         }
                     };
                 };
-                Ok(Value::ObjectLike(kv_pairs, indexed_values))
+                let value = Value::ObjectLike(kv_pairs, indexed_values);
+                match tag {
+                    Some(name) => Ok(Value::Tagged(name, Box::new(value))),
+                    None => Ok(value),
+                }
             }
             LuaValue::Function(_) => {
                 Ok(Value::String("[LuaFunction]".to_string()))
@@ -131,20 +417,39 @@ impl<'lua> FromLua<'lua> for Value {
             LuaValue::Thread(_) => {
                 Ok(Value::String("[LuaThread]".to_string()))
             },
-            LuaValue::UserData(_) => unimplemented!("UserData"),
-            LuaValue::LightUserData(_) => unimplemented!("LightUserData"),
-            LuaValue::Error(e) => {
-                // TODO what to do with error values instead of calls?
-                Ok(Value::Error(e.to_string()))
-            }
+            #[cfg(feature = "node")]
+            LuaValue::UserData(ud) => match ud.borrow::<JsUserData>() {
+                Ok(data) => Ok(data.to_value()),
+                Err(_) => Err(userdata_error()),
+            },
+            #[cfg(not(feature = "node"))]
+            LuaValue::UserData(_) => Err(userdata_error()),
+            // `json.null`
+            LuaValue::LightUserData(ud) if ud.0.is_null() => Ok(Value::Null),
+            #[cfg(feature = "node")]
+            LuaValue::LightUserData(ud) => match symbol_from_lua(ud) {
+                Some(symbol) => Ok(Value::Symbol(symbol)),
+                None => Err(lightuserdata_error()),
+            },
+            #[cfg(not(feature = "node"))]
+            LuaValue::LightUserData(_) => Err(lightuserdata_error()),
+            LuaValue::Error(e) => Ok(Value::Error {
+                name: String::from("Error"),
+                message: e.to_string(),
+                stack: None,
+            }),
         }
     }
 }
 
+#[cfg(feature = "node")]
 impl ToJs for Value {
     fn to_js<'a, CX: Context<'a>>(&self, cx: &mut CX) -> neon::result::JsResult<'a, JsValue> {
         match self {
             Value::String(s) => Ok(cx.string(s).upcast()),
+            Value::Integer(int) if *int <= JS_MAX_SAFE_INTEGER && *int >= -JS_MAX_SAFE_INTEGER => {
+                Ok(cx.number(*int as f64).upcast())
+            }
             Value::Integer(int) => {
                 let global = cx.global();
                 let bigint_ctor = global.get(cx, "BigInt")?.downcast_or_throw::<JsFunction,_>(cx)?;
@@ -154,10 +459,28 @@ impl ToJs for Value {
                 Ok(val)
             },
             Value::Double(f) => Ok(cx.number(*f).upcast()),
-            Value::Error(e) => cx.throw_error(e.to_string()),
+            Value::Error {
+                name,
+                message,
+                stack,
+            } => {
+                let error = JsError::error(cx, message)?;
+                let js_name = cx.string(name);
+                error.set(cx, "name", js_name)?;
+                if let Some(stack) = stack {
+                    let js_stack = cx.string(stack);
+                    error.set(cx, "stack", js_stack)?;
+                }
+                Ok(error.upcast())
+            }
             Value::Null => Ok(cx.null().upcast()),
             Value::Undefined => Ok(cx.undefined().upcast()),
             Value::Boolean(b) => Ok(cx.boolean(*b).upcast()),
+            Value::UserData(_, object) | Value::Symbol(object) => object.to_handle(cx),
+            Value::Tagged(name, value) => {
+                let js_value = value.to_js(cx)?;
+                conversion::apply_from_lua_hook(cx, name, js_value)
+            }
             Value::ObjectLike(pairs, array_like) => {
                 // let obj = cx.empty_object();
                 let obj: Handle<JsObject> = if array_like.len() > 0 {
@@ -187,13 +510,59 @@ impl ToJs for Value {
     }
 }
 
+#[cfg(feature = "node")]
+fn error_from_js<'a, CX: Context<'a>>(cx: &mut CX, error: Handle<'a, JsObject>) -> NeonResult<Value> {
+    let name = error.get(cx, "name")?;
+    let message = error.get(cx, "message")?;
+    let stack = error.get(cx, "stack")?;
+    let string_or_none = |value: Handle<JsValue>| value.downcast::<JsString>().ok().map(|s| s.value());
+    Ok(Value::Error {
+        name: string_or_none(name).unwrap_or_else(|| String::from("Error")),
+        message: string_or_none(message).unwrap_or_default(),
+        stack: string_or_none(stack),
+    })
+}
+
+/// `String(handle)`, which unlike `toString` works for symbols too.
+#[cfg(feature = "node")]
+fn primitive_string<'a, CX: Context<'a>>(cx: &mut CX, handle: Handle<'a, JsValue>) -> NeonResult<String> {
+    let global = cx.global();
+    let to_string = global
+        .get(cx, "String")?
+        .downcast_or_throw::<JsFunction, CX>(cx)?;
+    let null = cx.null();
+    Ok(to_string
+        .call(cx, null, vec![handle])?
+        .downcast_or_throw::<JsString, CX>(cx)?
+        .value())
+}
+
+/// Neon has no symbol type, so symbols are what's left after the other types, minus BigInts.
+#[cfg(feature = "node")]
+fn is_symbol<'a, CX: Context<'a>>(cx: &mut CX, handle: Handle<'a, JsValue>) -> NeonResult<bool> {
+    if handle.is_a::<JsNull>()
+        || handle.is_a::<JsUndefined>()
+        || handle.is_a::<JsNumber>()
+        || handle.is_a::<JsString>()
+        || handle.is_a::<JsBoolean>()
+        || handle.is_a::<JsObject>()
+    {
+        return Ok(false);
+    }
+    Ok(primitive_string(cx, handle)?.starts_with("Symbol("))
+}
+
+#[cfg(feature = "node")]
 impl FromJs for Value {
-    fn from_js<'a, CX: Context<'a>>(handle: Handle<JsValue>, cx: &mut CX) -> NeonResult<Self> {
+    fn from_js<'a, CX: Context<'a>>(handle: Handle<'a, JsValue>, cx: &mut CX) -> NeonResult<Self> {
+        if let Some(value) = conversion::apply_to_lua_hooks(cx, handle)? {
+            return Ok(value);
+        }
         if handle.is_a::<JsNull>() || handle.is_a::<JsUndefined>() {
             Ok(Value::Null)
         } else if handle.is_a::<JsNumber>() {
             let num = handle.downcast_or_throw::<JsNumber, CX>(cx)?.value();
-            Ok(Value::Double(num))
+            Ok(number_from_js(num))
         } else if handle.is_a::<JsString>() {
             let s = handle.downcast_or_throw::<JsString, CX>(cx)?.value();
             Ok(Value::String(s))
@@ -211,35 +580,258 @@ impl FromJs for Value {
                 // property using an integer, it will be treated as number like. x[1.5] will be coerced
                 // to a string. So we can check for string/number/symbol and decide what do with it.
                 let obj = handle.downcast_or_throw::<JsObject, CX>(cx)?;
-                let props = obj.get_own_property_names(cx)?.to_vec(cx)?;
+                if let Some(class) = user_data::class_of(cx, obj)? {
+                    let object = JsRef::new(cx, handle)?;
+                    return Ok(Value::UserData(class, object));
+                }
+                if conversion::is_error(cx, obj)? {
+                    return error_from_js(cx, obj);
+                }
+                let options = conversion::current_options();
+                let props = conversion::property_keys(cx, obj, &options)?;
 
                 // Don't know what's what, so we're just sizing each piece to the max we'd need
                 let mut hash_like: Vec<(Value, Value)> = Vec::with_capacity(props.len() + 1);
                 let mut array_like: Vec<(Value, Value)> = Vec::with_capacity(props.len() + 1);
 
-                for key_handle in props {
+                for prop in props {
+                    let key_handle = prop.key;
+                    let value_handle = obj.get(cx, key_handle)?;
+                    if prop.skip_functions && value_handle.is_a::<JsFunction>() {
+                        continue;
+                    }
+                    // Symbol values are dropped along with their key, like symbol keys
+                    if options.symbols == SymbolKeys::Skip && is_symbol(cx, value_handle)? {
+                        continue;
+                    }
 
                     if key_handle.is_a::<JsNumber>() {
                         // We can cast to usize because any property key set as a float will be cast
-                        // to a string by the JS runtime.
-                        let key = Value::from_js(key_handle, cx)?;
-                        let value_handle = obj.get(cx, key_handle)?;
+                        // to a string by the JS runtime. Keys skip the conversion hooks.
+                        let key = key_handle.downcast_or_throw::<JsNumber, CX>(cx)?.value();
+                        let key = Value::Double(key);
                         let value = Value::from_js(value_handle, cx)?;
                         array_like.push((key, value))
                     } else if key_handle.is_a::<JsString>() {
-                        let key = Value::from_js(key_handle, cx)?;
-                        let value_handle = obj.get(cx, key_handle)?;
+                        let key = key_handle.downcast_or_throw::<JsString, CX>(cx)?.value();
+                        let key = Value::String(key);
                         let value = Value::from_js(value_handle, cx)?;
                         hash_like.push((key, value))
                     } else {
-                        unimplemented!("JsSymbol Property Key")
+                        // Symbol keys are only collected with the "lightuserdata" option
+                        let key = conversion::symbol_value(cx, key_handle)?;
+                        let value = Value::from_js(value_handle, cx)?;
+                        hash_like.push((key, value))
                     }
                 }
                 Ok(Value::ObjectLike(hash_like, array_like))
             }
         } else {
-            // TODO what should be happening here? returrning Err(Throw) is converted to nil instead of actually erroring out.
-            panic!("Cannot convert JsValue from JS")
+            // The only remaining primitives are BigInts and symbols. BigInts that fit become Lua
+            // integers, symbols become light userdata if enabled and nil otherwise, everything
+            // else errors.
+            let s = primitive_string(cx, handle)?;
+            match s.parse::<i64>() {
+                Ok(i) => Ok(Value::Integer(i)),
+                // BigInts stringify to digits only, so this can only be a symbol
+                Err(_) if s.starts_with("Symbol(") => match conversion::current_options().symbols {
+                    SymbolKeys::LightUserData => conversion::symbol_value(cx, handle),
+                    SymbolKeys::Skip => Ok(Value::Null),
+                },
+                Err(_) => cx.throw_type_error(format!("Cannot convert {} to a Lua value", s)),
+            }
+        }
+    }
+}
+
+/// Converts to JSON shaped by `json_shape`, like the `json` library does: `ObjectLike` is an array
+/// when it only has array items, an object when it only has pairs, and an error when it has both.
+/// Null and undefined are `null`, as are non-finite doubles.
+#[cfg(feature = "serialize")]
+impl std::convert::TryFrom<&Value> for serde_json::Value {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        use serde::ser::Error;
+        use serde_json::{Map, Number, Value as Json};
+
+        Ok(match value {
+            Value::Null | Value::Undefined => Json::Null,
+            Value::Boolean(b) => Json::Bool(*b),
+            Value::String(s) => Json::String(s.clone()),
+            Value::Integer(i) => Json::Number(Number::from(*i)),
+            Value::Double(f) => Number::from_f64(*f).map(Json::Number).unwrap_or(Json::Null),
+            Value::Tagged(_, value) => Json::try_from(value.as_ref())?,
+            Value::Error {
+                name,
+                message,
+                stack,
+            } => {
+                let mut object = Map::new();
+                object.insert(String::from("name"), Json::String(name.clone()));
+                object.insert(String::from("message"), Json::String(message.clone()));
+                if let Some(stack) = stack {
+                    object.insert(String::from("stack"), Json::String(stack.clone()));
+                }
+                Json::Object(object)
+            }
+            Value::ObjectLike(pairs, array_like) => match json_shape(pairs, array_like) {
+                Ok(JsonShape::Array(items)) => Json::Array(
+                    items
+                        .into_iter()
+                        .map(|item| item.map_or(Ok(Json::Null), Json::try_from))
+                        .collect::<Result<_, _>>()?,
+                ),
+                Ok(JsonShape::Object(entries)) => {
+                    let mut object = Map::new();
+                    for (key, value) in entries {
+                        object.insert(key, Json::try_from(value)?);
+                    }
+                    Json::Object(object)
+                }
+                Err(what) => {
+                    return Err(serde_json::Error::custom(format!("can't convert {} to JSON", what)))
+                }
+            },
+            #[cfg(feature = "node")]
+            Value::UserData(..) => return Err(serde_json::Error::custom("can't convert userdata to JSON")),
+            #[cfg(feature = "node")]
+            Value::Symbol(_) => return Err(serde_json::Error::custom("can't convert a symbol to JSON")),
+        })
+    }
+}
+
+/// Converts from JSON the way `FromJs` converts the equivalent JS value: arrays become array items
+/// and objects become pairs, and integral numbers in the safe integer range become integers.
+#[cfg(feature = "serialize")]
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        use serde_json::Value as Json;
+
+        match json {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Boolean(b),
+            Json::Number(n) => match n.as_i64() {
+                Some(i) if i.unsigned_abs() <= JS_MAX_SAFE_INTEGER as u64 => Value::Integer(i),
+                _ => number_from_js(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Json::String(s) => Value::String(s),
+            Json::Array(items) => Value::ObjectLike(
+                vec![],
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| (Value::Integer(i as i64), Value::from(item)))
+                    .collect(),
+            ),
+            Json::Object(entries) => Value::ObjectLike(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (Value::String(key), Value::from(value)))
+                    .collect(),
+                vec![],
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nan_table_keys_are_rejected() {
+        let lua = Lua::new();
+        let tables = vec![
+            Value::ObjectLike(vec![(Value::Double(f64::NAN), Value::Integer(1))], vec![]),
+            Value::ObjectLike(vec![], vec![(Value::Double(f64::NAN), Value::Integer(1))]),
+        ];
+        for table in tables {
+            let error = table.to_lua(&lua).unwrap_err();
+            assert!(error.to_string().contains("NaN can't be used as a table key"));
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn object_like_serializes_its_pair_lists() {
+        let value = Value::ObjectLike(
+            vec![(Value::String(String::from("a")), Value::Double(0.5))],
+            vec![(Value::Integer(0), Value::Null)],
+        );
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"objectLike","value":[[[{"type":"string","value":"a"},{"type":"double","value":0.5}]],[[{"type":"integer","value":0},{"type":"null"}]]]}"#
+        );
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), json);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn json_conversion_shapes_tables() {
+        use serde_json::json;
+        use std::convert::TryFrom;
+
+        let array = Value::ObjectLike(
+            vec![],
+            vec![
+                (Value::Integer(2), Value::Integer(3)),
+                (Value::Integer(0), Value::Integer(1)),
+            ],
+        );
+        assert_eq!(serde_json::Value::try_from(&array).unwrap(), json!([1, null, 3]));
+
+        let object = Value::ObjectLike(
+            vec![
+                (Value::String(String::from("list")), array.clone()),
+                (Value::Integer(-1), Value::Boolean(true)),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            serde_json::Value::try_from(&object).unwrap(),
+            json!({ "list": [1, null, 3], "-1": true })
+        );
+
+        let mixed = Value::ObjectLike(
+            vec![(Value::String(String::from("key")), Value::Integer(2))],
+            vec![(Value::Integer(0), Value::Integer(1))],
+        );
+        let error = serde_json::Value::try_from(&mixed).unwrap_err();
+        assert!(error.to_string().contains("both array items and other keys"));
+
+        let empty = Value::ObjectLike(vec![], vec![]);
+        assert_eq!(serde_json::Value::try_from(&empty).unwrap(), json!({}));
+
+        // Too sparse to fill in the holes, so keyed by Lua index
+        let sparse = Value::ObjectLike(
+            vec![],
+            vec![
+                (Value::Integer(0), Value::Integer(1)),
+                (Value::Integer(u32::MAX as i64 - 1), Value::Integer(2)),
+            ],
+        );
+        assert_eq!(
+            serde_json::Value::try_from(&sparse).unwrap(),
+            json!({ "1": 1, "4294967295": 2 })
+        );
+
+        let json = json!([1, { "a": "b" }]);
+        assert_eq!(serde_json::Value::try_from(&Value::from(json.clone())).unwrap(), json);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn json_numbers_outside_the_safe_range_are_doubles() {
+        let json: serde_json::Value = serde_json::from_str("[-9223372036854775808, 9007199254740991]").unwrap();
+        match Value::from(json) {
+            Value::ObjectLike(_, items) => {
+                assert!(matches!(items[0].1, Value::Double(f) if f == i64::MIN as f64));
+                assert!(matches!(items[1].1, Value::Integer(9007199254740991)));
+            }
+            value => panic!("expected an array, got {:?}", value),
         }
     }
 }
@@ -0,0 +1,695 @@
+//! Virtual filesystem for the file access of a `LuaState`.
+//!
+//! When configured, `doFileSync` and the Lua functions that read or write files (`dofile`,
+//! `loadfile`, `io.open`, `io.lines`, `require`, `os.remove` and `os.rename`) go through a
+//! `FileSystem` instead of the real disk. A filesystem is backed by an in-memory map, by JS `read`/`write` callbacks, or by a
+//! directory on disk that paths can't escape. Any backend can be made read-only.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+use mlua::prelude::{Lua, LuaMultiValue, LuaValue};
+use mlua::{Function, MetaMethod, UserData, UserDataMethods};
+
+use crate::js_context;
+use crate::js_ref::JsRef;
+use crate::value::Value;
+
+pub enum Backend {
+    // Keyed by normalized path
+    Memory(Mutex<HashMap<String, Vec<u8>>>),
+    // Canonicalized root directory
+    Disk(PathBuf),
+    Js { read: JsRef, write: Option<JsRef> },
+}
+
+pub struct FileSystem {
+    backend: Backend,
+    read_only: bool,
+}
+
+/// Resolves `.` and `..` segments. Paths are relative to the root of the filesystem, so a leading
+/// `/` is ignored, and `..` can't go above the root.
+pub fn normalize(path: &str) -> Result<String, String> {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split(|c| c == '/' || c == '\\') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(format!("{}: path is outside of the filesystem root", path));
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+fn runtime_error(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+// Open files are held in memory, so a write (e.g. after seeking far past the end) can't grow one
+// beyond this
+const MAX_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+impl FileSystem {
+    pub fn memory(files: HashMap<String, Vec<u8>>, read_only: bool) -> Result<Self, String> {
+        let mut normalized = HashMap::with_capacity(files.len());
+        for (path, contents) in files {
+            normalized.insert(normalize(&path)?, contents);
+        }
+        Ok(FileSystem {
+            backend: Backend::Memory(Mutex::new(normalized)),
+            read_only,
+        })
+    }
+
+    pub fn disk(root: &str, read_only: bool) -> Result<Self, String> {
+        let root = fs::canonicalize(root).map_err(|e| format!("{}: {}", root, e))?;
+        Ok(FileSystem {
+            backend: Backend::Disk(root),
+            read_only,
+        })
+    }
+
+    pub fn js(read: JsRef, write: Option<JsRef>, read_only: bool) -> Self {
+        // Without a `write` callback there's nowhere to write to
+        let read_only = read_only || write.is_none();
+        FileSystem {
+            backend: Backend::Js { read, write },
+            read_only,
+        }
+    }
+
+    /// Reads a file, or returns `None` if it doesn't exist.
+    pub fn read(&self, path: &str) -> mlua::Result<Option<Vec<u8>>> {
+        let normalized = normalize(path).map_err(runtime_error)?;
+        match &self.backend {
+            Backend::Memory(files) => match files.lock() {
+                Ok(files) => Ok(files.get(&normalized).cloned()),
+                Err(_) => Err(runtime_error(String::from("filesystem lock poisoned"))),
+            },
+            Backend::Disk(root) => {
+                let full_path = jailed_path(root, &normalized)?;
+                match fs::read(&full_path) {
+                    Ok(contents) => Ok(Some(contents)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(runtime_error(format!("{}: {}", path, e))),
+                }
+            }
+            Backend::Js { read, .. } => {
+                match js_context::call(read, None, &[Value::String(normalized)])? {
+                    Value::String(s) => Ok(Some(s.into_bytes())),
+                    Value::Null | Value::Undefined => Ok(None),
+                    _ => Err(runtime_error(format!(
+                        "{}: filesystem read callback must return a string or undefined",
+                        path
+                    ))),
+                }
+            }
+        }
+    }
+
+    pub fn write(&self, path: &str, contents: &[u8]) -> mlua::Result<()> {
+        if self.read_only {
+            return Err(runtime_error(format!("{}: read-only filesystem", path)));
+        }
+        let normalized = normalize(path).map_err(runtime_error)?;
+        match &self.backend {
+            Backend::Memory(files) => match files.lock() {
+                Ok(mut files) => {
+                    files.insert(normalized, contents.to_vec());
+                    Ok(())
+                }
+                Err(_) => Err(runtime_error(String::from("filesystem lock poisoned"))),
+            },
+            Backend::Disk(root) => {
+                let full_path = jailed_path(root, &normalized)?;
+                fs::write(&full_path, contents)
+                    .map_err(|e| runtime_error(format!("{}: {}", path, e)))
+            }
+            Backend::Js { write, .. } => match write {
+                Some(write) => {
+                    let contents = String::from_utf8_lossy(contents).into_owned();
+                    let args = [Value::String(normalized), Value::String(contents)];
+                    js_context::call(write, None, &args)?;
+                    Ok(())
+                }
+                None => Err(runtime_error(format!("{}: read-only filesystem", path))),
+            },
+        }
+    }
+
+    /// Removes a file, for `os.remove`.
+    pub fn remove(&self, path: &str) -> mlua::Result<()> {
+        if self.read_only {
+            return Err(runtime_error(format!("{}: read-only filesystem", path)));
+        }
+        let normalized = normalize(path).map_err(runtime_error)?;
+        match &self.backend {
+            Backend::Memory(files) => match files.lock() {
+                Ok(mut files) => match files.remove(&normalized) {
+                    Some(_) => Ok(()),
+                    None => Err(not_found(path)),
+                },
+                Err(_) => Err(runtime_error(String::from("filesystem lock poisoned"))),
+            },
+            Backend::Disk(root) => {
+                let full_path = jailed_path(root, &normalized)?;
+                let result = if full_path.is_dir() {
+                    fs::remove_dir(&full_path)
+                } else {
+                    fs::remove_file(&full_path)
+                };
+                result.map_err(|e| runtime_error(format!("{}: {}", path, e)))
+            }
+            Backend::Js { .. } => Err(unsupported(path)),
+        }
+    }
+
+    /// Renames a file, for `os.rename`.
+    pub fn rename(&self, from: &str, to: &str) -> mlua::Result<()> {
+        if self.read_only {
+            return Err(runtime_error(format!("{}: read-only filesystem", from)));
+        }
+        let normalized_from = normalize(from).map_err(runtime_error)?;
+        let normalized_to = normalize(to).map_err(runtime_error)?;
+        match &self.backend {
+            Backend::Memory(files) => match files.lock() {
+                Ok(mut files) => match files.remove(&normalized_from) {
+                    Some(contents) => {
+                        files.insert(normalized_to, contents);
+                        Ok(())
+                    }
+                    None => Err(not_found(from)),
+                },
+                Err(_) => Err(runtime_error(String::from("filesystem lock poisoned"))),
+            },
+            Backend::Disk(root) => {
+                let full_from = jailed_path(root, &normalized_from)?;
+                let full_to = jailed_path(root, &normalized_to)?;
+                fs::rename(&full_from, &full_to)
+                    .map_err(|e| runtime_error(format!("{}: {}", from, e)))
+            }
+            Backend::Js { .. } => Err(unsupported(from)),
+        }
+    }
+
+    /// Reads a file as a string for `doFileSync`.
+    pub fn read_to_string(&self, path: &str) -> mlua::Result<String> {
+        match self.read(path)? {
+            Some(contents) => String::from_utf8(contents)
+                .map_err(|e| runtime_error(format!("{}: {}", path, e))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    /// Replaces the file access functions of `lua` with ones going through this filesystem.
+    pub fn install(self: &Arc<Self>, lua: &Lua) -> mlua::Result<()> {
+        let filesystem = self.clone();
+        let open = lua.create_function(move |lua, (path, mode): (String, Option<String>)| {
+            let mode = mode.unwrap_or_else(|| String::from("r"));
+            match VfsFile::open(filesystem.clone(), &path, &mode)? {
+                Ok(file) => Ok((LuaValue::UserData(lua.create_userdata(file)?), None)),
+                Err(message) => Ok((LuaValue::Nil, Some(message))),
+            }
+        })?;
+        let filesystem = self.clone();
+        let read = lua.create_function(move |lua, path: String| match filesystem.read(&path)? {
+            Some(contents) => Ok((Some(lua.create_string(&contents)?), None)),
+            None => Ok((None, Some(not_found_message(&path)))),
+        })?;
+        let filesystem = self.clone();
+        let search_path = lua.create_function(move |_, (name, path): (String, String)| {
+            let name = name.replace('.', "/");
+            let mut tried = String::new();
+            for template in path.split(';').filter(|template| !template.is_empty()) {
+                let candidate = template.replace('?', &name);
+                if filesystem.read(&candidate)?.is_some() {
+                    return Ok((Some(candidate), None));
+                }
+                tried.push_str(&format!("\n\tno file '{}'", candidate));
+            }
+            Ok((None, Some(tried)))
+        })?;
+        let filesystem = self.clone();
+        let remove = lua.create_function(move |_, path: String| match filesystem.remove(&path) {
+            Ok(_) => Ok((Some(true), None)),
+            Err(e) => Ok((None, Some(error_message(e)))),
+        })?;
+        let filesystem = self.clone();
+        let rename = lua.create_function(move |_, (from, to): (String, String)| {
+            match filesystem.rename(&from, &to) {
+                Ok(_) => Ok((Some(true), None)),
+                Err(e) => Ok((None, Some(error_message(e)))),
+            }
+        })?;
+        let installer: Function = lua.load(INSTALLER).set_name("=lua-js.vfs")?.eval()?;
+        installer.call((open, read, search_path, lines_factory(lua)?, remove, rename))
+    }
+}
+
+fn not_found(path: &str) -> mlua::Error {
+    runtime_error(not_found_message(path))
+}
+
+fn not_found_message(path: &str) -> String {
+    format!("{}: No such file or directory", path)
+}
+
+fn unsupported(path: &str) -> mlua::Error {
+    runtime_error(format!("{}: not supported by this filesystem", path))
+}
+
+/// The message Lua's file functions return alongside `nil`.
+fn error_message(error: mlua::Error) -> String {
+    match error {
+        mlua::Error::RuntimeError(message) => message,
+        error => error.to_string(),
+    }
+}
+
+/// Joins a normalized path onto `root`, making sure symlinks don't lead outside of it.
+fn jailed_path(root: &Path, normalized: &str) -> mlua::Result<PathBuf> {
+    let full_path = root.join(normalized);
+    let outside = || {
+        runtime_error(format!(
+            "{}: path is outside of the filesystem root",
+            normalized
+        ))
+    };
+    // The file may not exist yet when writing, in which case the nearest existing directory must
+    // be in the jail. A dangling symlink exists here even though `exists()` says otherwise, as
+    // writing through it would create its target.
+    let mut existing = full_path.as_path();
+    while fs::symlink_metadata(existing).is_err() {
+        existing = existing.parent().ok_or_else(outside)?;
+    }
+    // Paths that can't be resolved, e.g. dangling symlinks, are rejected too
+    match fs::canonicalize(existing) {
+        Ok(canonical) if canonical.starts_with(root) => Ok(full_path),
+        _ => Err(outside()),
+    }
+}
+
+// Replaces the globals using the Rust primitives. `load`, `loadstring` and `setfenv` are captured
+// here, as the sandbox is applied afterwards and may remove them.
+const INSTALLER: &str = r#"
+local error, type, load, loadstring, setfenv = error, type, load, loadstring, setfenv
+
+return function(open, read, search_path, lines, remove, rename)
+    local function vfs_loadfile(path, mode, env)
+        local code, err = read(path)
+        if code == nil then return nil, err end
+        if setfenv and loadstring then
+            local f, err = loadstring(code, "@" .. path)
+            if f and env ~= nil then setfenv(f, env) end
+            return f, err
+        end
+        -- An explicit nil env would replace _ENV
+        if env ~= nil then return load(code, "@" .. path, mode or "bt", env) end
+        return load(code, "@" .. path, mode or "bt")
+    end
+
+    loadfile = vfs_loadfile
+    dofile = function(path)
+        local f, err = vfs_loadfile(path)
+        if not f then error(err, 2) end
+        return f()
+    end
+
+    if type(io) == "table" then
+        io.open = open
+        io.lines = function(path, ...)
+            if path == nil then
+                error("io.lines without a file name is not supported by the virtual filesystem", 2)
+            end
+            local file, err = open(path, "r")
+            if not file then error(err, 2) end
+            return lines(file, true, ...)
+        end
+        local function standard_only(f, name)
+            return function(file, ...)
+                if type(file) == "string" then
+                    error(name .. " with a file name is not supported by the virtual filesystem", 2)
+                end
+                return f(file, ...)
+            end
+        end
+        io.input = standard_only(io.input, "io.input")
+        io.output = standard_only(io.output, "io.output")
+        io.popen = nil
+        io.tmpfile = nil
+    end
+
+    if type(os) == "table" then
+        os.remove = remove
+        os.rename = rename
+    end
+
+    if type(package) == "table" then
+        package.path = "?.lua;?/init.lua"
+        package.cpath = ""
+        local searchers = package.searchers or package.loaders
+        searchers[2] = function(name)
+            local path, err = search_path(name, package.path)
+            if not path then return err end
+            local f, err = vfs_loadfile(path)
+            if not f then error(err, 2) end
+            return f, path
+        end
+        -- C modules can only come from the real disk
+        for i = #searchers, 3, -1 do searchers[i] = nil end
+        if package.searchpath then package.searchpath = search_path end
+        package.loadlib = nil
+    end
+end
+"#;
+
+const LINES_KEY: &str = "lua-js.vfs.lines";
+
+/// Lua factory for the iterators returned by `file:lines(...)` and `io.lines(...)`.
+fn lines_factory(lua: &Lua) -> mlua::Result<Function> {
+    if let LuaValue::Function(f) = lua.named_registry_value::<_, LuaValue>(LINES_KEY)? {
+        return Ok(f);
+    }
+    let factory: Function = lua
+        .load(
+            r#"
+            local select = select
+            local function unpack(t, i, n)
+                if i > n then return end
+                return t[i], unpack(t, i + 1, n)
+            end
+            return function(file, close, ...)
+                local n, formats = select("#", ...), { ... }
+                return function()
+                    local results = { file:read(unpack(formats, 1, n)) }
+                    if results[1] == nil and close then file:close() end
+                    return unpack(results, 1, n > 0 and n or 1)
+                end
+            end
+            "#,
+        )
+        .set_name("=lua-js.vfs")?
+        .eval()?;
+    lua.set_named_registry_value(LINES_KEY, factory.clone())?;
+    Ok(factory)
+}
+
+/// File handle returned by `io.open`. The whole file is read on open, and writes are buffered
+/// until `flush`, `close` or the handle being garbage collected.
+pub struct VfsFile {
+    filesystem: Arc<FileSystem>,
+    path: String,
+    contents: Vec<u8>,
+    position: usize,
+    writable: bool,
+    append: bool,
+    dirty: bool,
+    closed: bool,
+}
+
+impl VfsFile {
+    /// Opens `path` with a Lua `io.open` mode. Failures that `io.open` reports by returning `nil`
+    /// and a message are returned as the inner `Err`.
+    fn open(
+        filesystem: Arc<FileSystem>,
+        path: &str,
+        mode: &str,
+    ) -> mlua::Result<Result<VfsFile, String>> {
+        let mode_chars = mode.trim_end_matches('b');
+        let (kind, update) = match mode_chars {
+            "r" | "w" | "a" => (mode_chars, false),
+            "r+" | "w+" | "a+" => (&mode_chars[..1], true),
+            _ => return Err(runtime_error(format!("invalid mode '{}'", mode))),
+        };
+        let writable = kind != "r" || update;
+        let contents = if kind == "w" {
+            vec![]
+        } else {
+            match filesystem.read(path)? {
+                Some(contents) => contents,
+                None if kind == "a" => vec![],
+                None => return Ok(Err(not_found_message(path))),
+            }
+        };
+        if writable {
+            // Creates (or truncates) the file right away, and surfaces read-only errors on open
+            if let Err(e) = filesystem.write(path, &contents) {
+                return Ok(Err(error_message(e)));
+            }
+        }
+        let position = if kind == "a" { contents.len() } else { 0 };
+        Ok(Ok(VfsFile {
+            filesystem,
+            path: path.to_string(),
+            contents,
+            position,
+            writable,
+            append: kind == "a",
+            dirty: false,
+            closed: false,
+        }))
+    }
+
+    fn check_open(&self) -> mlua::Result<()> {
+        if self.closed {
+            Err(runtime_error(String::from("attempt to use a closed file")))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> mlua::Result<()> {
+        if self.dirty {
+            self.filesystem.write(&self.path, &self.contents)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn read_format<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        format: &LuaValue<'lua>,
+    ) -> mlua::Result<LuaValue<'lua>> {
+        let remaining = &self.contents[self.position.min(self.contents.len())..];
+        match format {
+            LuaValue::Integer(_) | LuaValue::Number(_) => {
+                let count = match format {
+                    LuaValue::Integer(i) => *i as usize,
+                    LuaValue::Number(f) => *f as usize,
+                    _ => 0,
+                };
+                if remaining.is_empty() {
+                    return Ok(LuaValue::Nil);
+                }
+                let bytes = &remaining[..count.min(remaining.len())];
+                self.position += bytes.len();
+                Ok(LuaValue::String(lua.create_string(bytes)?))
+            }
+            LuaValue::String(format) => {
+                let format = format.to_str()?.trim_start_matches('*');
+                match format.chars().next() {
+                    Some('a') => {
+                        self.position = self.contents.len();
+                        Ok(LuaValue::String(lua.create_string(remaining)?))
+                    }
+                    Some(c @ 'l') | Some(c @ 'L') => {
+                        if remaining.is_empty() {
+                            return Ok(LuaValue::Nil);
+                        }
+                        let line = match remaining.iter().position(|b| *b == b'\n') {
+                            Some(newline) => {
+                                self.position += newline + 1;
+                                if c == 'L' {
+                                    &remaining[..newline + 1]
+                                } else {
+                                    &remaining[..newline]
+                                }
+                            }
+                            None => {
+                                self.position = self.contents.len();
+                                remaining
+                            }
+                        };
+                        Ok(LuaValue::String(lua.create_string(line)?))
+                    }
+                    Some('n') => {
+                        let skipped = remaining
+                            .iter()
+                            .take_while(|b| b.is_ascii_whitespace())
+                            .count();
+                        let numeral: String = remaining[skipped..]
+                            .iter()
+                            .take_while(|b| b.is_ascii_alphanumeric() || b"+-.".contains(b))
+                            .map(|b| *b as char)
+                            .collect();
+                        let value = parse_number(&numeral);
+                        if !matches!(value, LuaValue::Nil) {
+                            self.position += skipped + numeral.len();
+                        }
+                        Ok(value)
+                    }
+                    _ => Err(runtime_error(String::from(
+                        "bad argument to 'read' (invalid format)",
+                    ))),
+                }
+            }
+            _ => Err(runtime_error(String::from(
+                "bad argument to 'read' (invalid format)",
+            ))),
+        }
+    }
+}
+
+fn parse_number<'lua>(numeral: &str) -> LuaValue<'lua> {
+    let (negative, digits) = match numeral.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, numeral.trim_start_matches('+')),
+    };
+    let hex = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"));
+    if let Some(hex) = hex {
+        return match i64::from_str_radix(hex, 16) {
+            Ok(i) => LuaValue::Integer(if negative { -i } else { i }),
+            Err(_) => LuaValue::Nil,
+        };
+    }
+    if let Ok(i) = numeral.parse::<i64>() {
+        LuaValue::Integer(i)
+    } else if let Ok(f) = numeral.parse::<f64>() {
+        LuaValue::Number(f)
+    } else {
+        LuaValue::Nil
+    }
+}
+
+// Like Lua's own files, which are flushed when collected. Errors have nowhere to go, and a JS
+// backend can only be written to while the state is running code for JS.
+impl Drop for VfsFile {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.flush();
+        }
+    }
+}
+
+impl UserData for VfsFile {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("read", |lua, this, formats: LuaMultiValue| {
+            this.check_open()?;
+            let mut formats = formats.into_vec();
+            if formats.is_empty() {
+                formats.push(LuaValue::String(lua.create_string("l")?));
+            }
+            let mut results = Vec::with_capacity(formats.len());
+            for format in formats.iter() {
+                let value = this.read_format(lua, format)?;
+                let done = matches!(value, LuaValue::Nil);
+                results.push(value);
+                if done {
+                    break;
+                }
+            }
+            Ok(LuaMultiValue::from_vec(results))
+        });
+        methods.add_function("write", |_, args: LuaMultiValue| {
+            let mut args = args.into_vec().into_iter();
+            let file = match args.next() {
+                Some(LuaValue::UserData(file)) => file,
+                _ => return Err(mlua::Error::UserDataTypeMismatch),
+            };
+            {
+                let mut this = file.borrow_mut::<VfsFile>()?;
+                this.check_open()?;
+                if !this.writable {
+                    return Err(runtime_error(format!("{}: file not open for writing", this.path)));
+                }
+                for value in args {
+                    let bytes: Vec<u8> = match value {
+                        LuaValue::String(s) => s.as_bytes().to_vec(),
+                        LuaValue::Integer(i) => i.to_string().into_bytes(),
+                        LuaValue::Number(f) => f.to_string().into_bytes(),
+                        other => {
+                            return Err(runtime_error(format!(
+                                "bad argument to 'write' (string expected, got {})",
+                                other.type_name()
+                            )))
+                        }
+                    };
+                    if this.append {
+                        this.position = this.contents.len();
+                    }
+                    let start = this.position;
+                    let end = start + bytes.len();
+                    if end > MAX_FILE_SIZE {
+                        return Err(runtime_error(format!(
+                            "{}: file would be larger than {} bytes",
+                            this.path, MAX_FILE_SIZE
+                        )));
+                    }
+                    if this.contents.len() < end {
+                        this.contents.resize(end, 0);
+                    }
+                    this.contents[start..end].copy_from_slice(&bytes);
+                    this.position = end;
+                    this.dirty = true;
+                }
+            }
+            Ok(file)
+        });
+        methods.add_function("lines", |lua, args: LuaMultiValue| {
+            let mut args = args.into_vec();
+            if args.is_empty() {
+                return Err(mlua::Error::UserDataTypeMismatch);
+            }
+            let file = args.remove(0);
+            let mut factory_args = vec![file, LuaValue::Boolean(false)];
+            factory_args.extend(args);
+            lines_factory(lua)?.call::<_, Function>(LuaMultiValue::from_vec(factory_args))
+        });
+        methods.add_method_mut("seek", |_, this, (whence, offset): (Option<String>, Option<i64>)| {
+            this.check_open()?;
+            let base = match whence.as_deref().unwrap_or("cur") {
+                "set" => 0,
+                "cur" => this.position as i64,
+                "end" => this.contents.len() as i64,
+                whence => return Err(runtime_error(format!("invalid option '{}'", whence))),
+            };
+            let position = base + offset.unwrap_or(0);
+            if position < 0 {
+                return Ok((None, Some(String::from("Invalid argument"))));
+            }
+            this.position = position as usize;
+            Ok((Some(position), None))
+        });
+        methods.add_method_mut("flush", |_, this, ()| {
+            this.check_open()?;
+            this.flush()?;
+            Ok(true)
+        });
+        methods.add_method_mut("close", |_, this, ()| {
+            this.check_open()?;
+            this.flush()?;
+            this.closed = true;
+            Ok(true)
+        });
+        methods.add_method("setvbuf", |_, this, _: LuaMultiValue| {
+            this.check_open()?;
+            Ok(true)
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            if this.closed {
+                Ok(String::from("file (closed)"))
+            } else {
+                Ok(format!("file ({})", this.path))
+            }
+        });
+    }
+}
@@ -2,7 +2,9 @@ import { createLuaState, Lua54, Lua53, Lua52, Lua51, LuaJIT, LuaState } from "..
 import test from "ava";
 
 export type Context = {
-    lua: LuaState
+    lua: LuaState,
+    // Lets suites create additional states with their own options
+    version: "lua51" | "lua52" | "lua53" | "lua54" | "luajit"
 }
 
 /**
@@ -0,0 +1,71 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+// Long enough for the scheduled delivery to have run
+const delivery = () => new Promise((resolve) => setTimeout(resolve, 20));
+
+export const callbacksInOrder: Macro<[], Context> = async (t) => {
+    const received: number[] = [];
+    t.context.lua.registerFunction("record", ([i]: [number]) => {
+        received.push(i);
+    });
+    t.context.lua.doStringSync(`for i = 1, 1000 do record(i) end`);
+    t.deepEqual(received, []);
+    await delivery();
+    t.is(received.length, 1000);
+    t.deepEqual(received, received.slice().sort((a, b) => a - b));
+}
+callbacksInOrder.title = (version) => `${version}: It delivers registered function calls in order, on a later tick`;
+
+export const callbacksBatched: Macro<[], Context> = async (t) => {
+    const batches: any[][][] = [];
+    t.context.lua.registerFunction("record", (calls: any[][]) => {
+        batches.push(calls);
+    }, { batch: true });
+    t.context.lua.doStringSync(`record(1, "a") record(2) record(3)`);
+    await delivery();
+    t.deepEqual(batches, [[[1, "a"], [2], [3]]]);
+}
+callbacksBatched.title = (version) => `${version}: It delivers the calls of a tick as one batch`;
+
+export const callbacksBackpressure: Macro<[], Context> = async (t) => {
+    const received: number[] = [];
+    t.context.lua.registerFunction("record", ([i]: [number]) => {
+        received.push(i);
+    }, { maxQueued: 10 });
+    t.context.lua.doStringSync(`for i = 1, 25 do record(i) end`);
+    // The 11th and 21st calls found the queue full and delivered it
+    t.is(received.length, 20);
+    await delivery();
+    t.deepEqual(received, Array.from({ length: 25 }, (_, i) => i + 1));
+}
+callbacksBackpressure.title = (version) => `${version}: It delivers a full queue synchronously`;
+
+export const callbacksErrors: Macro<[], Context> = async (t) => {
+    const errors: any[] = [];
+    t.context.lua.on("error", (e: any) => errors.push(e));
+    t.context.lua.registerFunction("fail", () => {
+        throw new Error("callback failed");
+    });
+    t.context.lua.doStringSync(`fail() fail()`);
+    await delivery();
+    t.is(errors.length, 2);
+    t.is(errors[0].message, "callback failed");
+}
+callbacksErrors.title = (version) => `${version}: It emits exceptions from callbacks as error events`;
+
+export const callbacksBackpressureErrors: Macro<[], Context> = (t) => {
+    t.context.lua.registerFunction("fail", () => {
+        throw new Error("callback failed");
+    }, { maxQueued: 1 });
+    t.throws(() => t.context.lua.doStringSync(`fail() fail()`), { message: /callback failed/ });
+}
+callbacksBackpressureErrors.title = (version) => `${version}: It raises exceptions from synchronous deliveries in Lua`;
+
+export const callbacksSuite: OneOrMoreMacros<[], Context> = [
+    callbacksInOrder,
+    callbacksBatched,
+    callbacksBackpressure,
+    callbacksErrors,
+    callbacksBackpressureErrors
+];
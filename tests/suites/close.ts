@@ -0,0 +1,33 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+export const closeThrowsAfterwards: Macro<[], Context> = (t) => {
+    const state = createLuaState({ version: t.context.version } as any);
+    state.setGlobal("value", 1);
+    t.false(state.isClosed);
+    state.close();
+    t.true(state.isClosed);
+
+    t.throws(() => state.getGlobal("value"), { message: "state is closed" });
+    t.throws(() => state.doStringSync("x = 1"), { message: "state is closed" });
+    t.throws(() => state.registerFunction("f", () => {}), { message: "state is closed" });
+    t.throws(() => state.reset(), { message: "state is closed" });
+    t.throws(() => state.gc.collect(), { message: "state is closed" });
+    // Closing again is harmless
+    t.notThrows(() => state.close());
+}
+closeThrowsAfterwards.title = (version) => `${version}: It throws when a closed state is used`;
+
+export const closeDispose: Macro<[], Context> = (t) => {
+    const state = createLuaState({ version: t.context.version } as any);
+    const dispose = (Symbol as any).dispose || Symbol.for("nodejs.dispose");
+    (state as any)[dispose]();
+    t.true(state.isClosed);
+}
+closeDispose.title = (version) => `${version}: It closes the state on Symbol.dispose`;
+
+export const closeSuite: OneOrMoreMacros<[], Context> = [
+    closeThrowsAfterwards,
+    closeDispose
+];
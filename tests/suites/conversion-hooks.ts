@@ -0,0 +1,76 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+class Money {
+    cents: number;
+    currency: string;
+
+    constructor(cents: number, currency: string) {
+        this.cents = cents;
+        this.currency = currency;
+    }
+}
+
+const registerMoney = (t: { context: Context }) => {
+    const state = t.context.lua;
+    state.addToLuaHook<Money>({
+        class: Money,
+        metatable: "Money",
+        serialize: (m) => ({ cents: m.cents, currency: m.currency })
+    });
+    state.addFromLuaHook<Money>({
+        metatable: "Money",
+        deserialize: (t) => new Money(t.cents, t.currency)
+    });
+}
+
+export const hooksRoundTrip: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerMoney(t);
+    state.setGlobal("price", new Money(150, "USD"));
+    const luaAssertTagged = () => {
+        state.doStringSync(`
+        assert(price.cents == 150)
+        assert(price.currency == "USD")
+        assert(getmetatable(price).__name == "Money")
+        `);
+    };
+    t.notThrows(luaAssertTagged);
+
+    const actual = state.getGlobal("price");
+    t.true(actual instanceof Money);
+    t.deepEqual(actual, new Money(150, "USD"));
+}
+hooksRoundTrip.title = (version) => `${version}: It round-trips values through conversion hooks`;
+
+export const hooksFromLuaTagged: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerMoney(t);
+    state.doStringSync(`
+        wallet = { setmetatable({ cents = 5, currency = "EUR" }, { __name = "Money" }) }
+    `);
+    const actual = state.getGlobal<Money[]>("wallet");
+    t.true(actual[0] instanceof Money);
+    t.is(actual[0].cents, 5);
+}
+hooksFromLuaTagged.title = (version) => `${version}: It deserializes tables tagged from Lua`;
+
+export const hooksPredicate: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.addToLuaHook<Date>({
+        test: (value) => value instanceof Date,
+        serialize: (date) => date.toISOString()
+    });
+    state.setGlobal("when", new Date(0));
+    const luaAssertSerialized = () => {
+        state.doStringSync(`assert(when == "1970-01-01T00:00:00.000Z")`);
+    };
+    t.notThrows(luaAssertSerialized);
+}
+hooksPredicate.title = (version) => `${version}: It serializes values matched by a predicate`;
+
+export const conversionHooksSuite: OneOrMoreMacros<[], Context> = [
+    hooksRoundTrip,
+    hooksFromLuaTagged,
+    hooksPredicate
+];
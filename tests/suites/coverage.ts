@@ -0,0 +1,107 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+const ALL_SAFE = 0xFFFFFFFE;
+const DEBUG = 0x80000000;
+
+const SCRIPT = [
+    "local function classify(n)",
+    "    if n > 0 then",
+    "        return 'positive'",
+    "    end",
+    "    return 'not positive'",
+    "end",
+    "classify(1)",
+    "classify(2)",
+].join("\n");
+
+export const coverageLines: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startCoverage();
+    state.doStringSync(SCRIPT, "classify.lua");
+    const coverage = state.takeCoverage();
+    const file = coverage.files["classify.lua"];
+    t.truthy(file);
+    t.is(file.lines[3], 2);
+    t.is(file.lines[7], 1);
+    t.is(file.lines[8], 1);
+    t.deepEqual(file.functions, [{ name: "classify", line: 1, calls: 2 }]);
+}
+coverageLines.title = (version) => `${version}: It counts executed lines per chunk`;
+
+export const coverageUnexecuted: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        libraries: [ALL_SAFE, DEBUG],
+        unsafe: true
+    } as any);
+    state.startCoverage();
+    state.doStringSync(SCRIPT, "classify.lua");
+    const file = state.takeCoverage().files["classify.lua"];
+    // Known from the debug library, but never run
+    t.is(file.lines[5], 0);
+    state.close();
+}
+coverageUnexecuted.title = (version) => `${version}: It reports lines that never ran with the debug library`;
+
+export const coverageReports: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startCoverage();
+    state.doStringSync(SCRIPT, "classify.lua");
+    const { lcov, istanbul } = state.takeCoverage();
+
+    t.true(lcov.startsWith("TN:\nSF:classify.lua\n"));
+    t.true(lcov.includes("FN:1,classify\n"));
+    t.true(lcov.includes("FNDA:2,classify\n"));
+    t.true(lcov.includes("DA:3,2\n"));
+    t.true(lcov.trim().endsWith("end_of_record"));
+
+    const file = istanbul["classify.lua"];
+    t.is(file.path, "classify.lua");
+    const statements = Object.keys(file.statementMap);
+    t.true(statements.length > 0);
+    t.deepEqual(Object.keys(file.s), statements);
+    t.is(file.fnMap["0"].name, "classify");
+    t.is(file.f["0"], 2);
+}
+coverageReports.title = (version) => `${version}: It emits LCOV and Istanbul coverage`;
+
+export const coverageAcrossReset: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startCoverage();
+    state.doStringSync("local a = 1", "first.lua");
+    state.reset();
+    state.doStringSync("local b = 2", "second.lua");
+    const files = Object.keys(state.takeCoverage().files);
+    t.deepEqual(files.sort(), ["first.lua", "second.lua"]);
+
+    state.stopCoverage();
+    t.throws(() => state.takeCoverage(), { message: /not started/ });
+}
+coverageAcrossReset.title = (version) => `${version}: It keeps collecting coverage across resets`;
+
+export const coverageWithHook: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const lines: number[] = [];
+    state.setHook({ line: true }, (event) => { lines.push(event.line as number); });
+    state.startCoverage();
+    state.doStringSync(SCRIPT, "classify.lua");
+    t.is(state.takeCoverage().files["classify.lua"].lines[3], 2);
+    t.true(lines.includes(3));
+
+    // Stopping coverage leaves the hook in place
+    state.stopCoverage();
+    lines.length = 0;
+    state.doStringSync("local a = 1\nlocal b = 2", "after");
+    t.deepEqual(lines, [1, 2]);
+}
+coverageWithHook.title = (version) => `${version}: It collects coverage alongside setHook`;
+
+export const coverageSuite: OneOrMoreMacros<[], Context> = [
+    coverageLines,
+    coverageUnexecuted,
+    coverageReports,
+    coverageAcrossReset,
+    coverageWithHook
+];
@@ -0,0 +1,239 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { spawn } from "child_process";
+import * as net from "net";
+import * as path from "path";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+const DEBUG = 0x80000000;
+const ALL_SAFE = 0xFFFFFFFE;
+
+const encode = (message: object) => {
+    const body = JSON.stringify(message);
+    return `Content-Length: ${Buffer.byteLength(body)}\r\n\r\n${body}`;
+};
+
+// Collects messages until `count` have arrived
+const receive = (socket: net.Socket, count: number) => new Promise<any[]>((resolve) => {
+    let buffer = "";
+    const messages: any[] = [];
+    socket.on("data", (data) => {
+        buffer += data.toString();
+        let match;
+        while ((match = /Content-Length: (\d+)\r\n\r\n/.exec(buffer))) {
+            const start = match.index + match[0].length;
+            const length = parseInt(match[1], 10);
+            if (buffer.length < start + length) {
+                break;
+            }
+            messages.push(JSON.parse(buffer.slice(start, start + length)));
+            buffer = buffer.slice(start + length);
+        }
+        if (messages.length >= count) {
+            resolve(messages);
+        }
+    });
+});
+
+export const debuggerRequiresDebug: Macro<[], Context> = (t) => {
+    t.throws(() => t.context.lua.startDebugger(), { message: /debug library/ });
+}
+debuggerRequiresDebug.title = (version) => `${version}: It requires the debug library for the debugger`;
+
+export const debuggerProtocol: Macro<[], Context> = async (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        libraries: [ALL_SAFE, DEBUG],
+        unsafe: true
+    } as any);
+    const port = state.startDebugger() as number;
+    t.true(port > 0);
+
+    const socket = net.connect(port, "127.0.0.1");
+    const messages = receive(socket, 4);
+    socket.write(encode({ seq: 1, type: "request", command: "initialize", arguments: {} }));
+    socket.write(encode({
+        seq: 2,
+        type: "request",
+        command: "setBreakpoints",
+        arguments: { source: { path: "/scripts/main.lua" }, breakpoints: [{ line: 3 }] }
+    }));
+    socket.write(encode({ seq: 3, type: "request", command: "threads" }));
+
+    const [initialize, initialized, breakpoints, threads] = await messages;
+    t.true(initialize.success);
+    t.is(initialized.event, "initialized");
+    t.deepEqual(breakpoints.body.breakpoints, [{ verified: true, line: 3 }]);
+    t.is(threads.body.threads.length, 1);
+
+    socket.destroy();
+    state.stopDebugger();
+    state.close();
+}
+debuggerProtocol.title = (version) => `${version}: It serves the Debug Adapter Protocol over TCP`;
+
+export const debuggerLocalhost: Macro<[], Context> = async (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        libraries: [ALL_SAFE, DEBUG],
+        unsafe: true
+    } as any);
+    const port = state.startDebugger({ host: "localhost" }) as number;
+    t.true(port > 0);
+    const socket = net.connect(port, "localhost");
+    const messages = receive(socket, 1);
+    socket.write(encode({ seq: 1, type: "request", command: "threads" }));
+    const [threads] = await messages;
+    t.true(threads.success);
+    socket.destroy();
+    state.stopDebugger();
+    state.close();
+}
+debuggerLocalhost.title = (version) => `${version}: It resolves host names for the debugger address`;
+
+const SESSION_SCRIPT = `local function add(a, b)
+    local sum = a + b
+    return sum
+end
+local offset = 10
+local function run()
+    local total = add(1, 2) + offset
+    return total
+end
+result = run()
+`;
+
+// A Lua state paused by the debugger blocks its JS thread, so the debuggee runs in a child process
+// talking the protocol over stdio.
+const spawnDebuggee = (version: string) => {
+    const lib = path.resolve(__dirname, "../../lib");
+    const code = `
+        const { createLuaState } = require(${JSON.stringify(lib)});
+        const state = createLuaState({ version: ${JSON.stringify(version)}, libraries: [${ALL_SAFE}, ${DEBUG}], unsafe: true });
+        state.startDebugger({ stdio: true, waitForClient: true });
+        state.doStringSync(${JSON.stringify(SESSION_SCRIPT)}, "@main.lua");
+        process.stderr.write(String(state.getGlobal("result")));
+        state.stopDebugger();
+        state.close();
+    `;
+    return spawn(process.execPath, ["-e", code], { stdio: ["pipe", "pipe", "pipe"] });
+};
+
+class Client {
+    private buffer = "";
+    private messages: any[] = [];
+    private waiting: Array<() => void> = [];
+    private seq = 1;
+
+    constructor(private input: NodeJS.WritableStream, output: NodeJS.ReadableStream) {
+        output.on("data", (data) => {
+            this.buffer += data.toString();
+            let match;
+            while ((match = /Content-Length: (\d+)\r\n\r\n/.exec(this.buffer))) {
+                const start = match.index + match[0].length;
+                const length = parseInt(match[1], 10);
+                if (this.buffer.length < start + length) {
+                    break;
+                }
+                this.messages.push(JSON.parse(this.buffer.slice(start, start + length)));
+                this.buffer = this.buffer.slice(start + length);
+            }
+            this.waiting.splice(0).forEach((check) => check());
+        });
+    }
+
+    request(command: string, args: object = {}): Promise<any> {
+        const seq = this.seq++;
+        this.input.write(encode({ seq, type: "request", command, arguments: args }));
+        return this.next((message) => message.type === "response" && message.request_seq === seq);
+    }
+
+    event(name: string): Promise<any> {
+        return this.next((message) => message.type === "event" && message.event === name);
+    }
+
+    private next(match: (message: any) => boolean): Promise<any> {
+        return new Promise((resolve) => {
+            const check = () => {
+                const i = this.messages.findIndex(match);
+                if (i !== -1) {
+                    resolve(this.messages.splice(i, 1)[0]);
+                } else {
+                    this.waiting.push(check);
+                }
+            };
+            check();
+        });
+    }
+}
+
+const variable = (variables: any[], name: string) => variables.find((v) => v.name === name);
+
+export const debuggerSession: Macro<[], Context> = async (t) => {
+    const child = spawnDebuggee(t.context.version);
+    let stderr = "";
+    child.stderr!.on("data", (data) => stderr += data.toString());
+    const exited = new Promise<number | null>((resolve) => child.on("exit", resolve));
+    const client = new Client(child.stdin!, child.stdout!);
+
+    t.true((await client.request("initialize")).success);
+    await client.event("initialized");
+    await client.request("setBreakpoints", {
+        source: { path: "/scripts/main.lua" },
+        breakpoints: [{ line: 7 }]
+    });
+    await client.request("configurationDone");
+
+    // Stops at the breakpoint, with the upvalues of `run` visible
+    t.is((await client.event("stopped")).body.reason, "breakpoint");
+    let stack = (await client.request("stackTrace", { threadId: 1 })).body.stackFrames;
+    t.is(stack[0].line, 7);
+    t.is(stack[0].source.name, "main.lua");
+    const depth = stack.length;
+    const scopes = (await client.request("scopes", { frameId: 0 })).body.scopes;
+    t.deepEqual(scopes.map((scope: any) => scope.name), ["Locals", "Upvalues"]);
+    let variables = (await client.request("variables", { variablesReference: scopes[1].variablesReference })).body.variables;
+    t.is(variable(variables, "offset").value, "10");
+    t.is(variable(variables, "add").type, "function");
+
+    // Steps into `add`, where the arguments are locals
+    await client.request("stepIn", { threadId: 1 });
+    t.is((await client.event("stopped")).body.reason, "step");
+    stack = (await client.request("stackTrace", { threadId: 1 })).body.stackFrames;
+    t.is(stack[0].line, 2);
+    t.is(stack.length, depth + 1);
+    variables = (await client.request("variables", { variablesReference: 1 })).body.variables;
+    t.is(variable(variables, "a").value, "1");
+    t.is(variable(variables, "b").value, "2");
+    t.is(variable(variables, "sum"), undefined);
+    const evaluated = await client.request("evaluate", { expression: "a * 10 + b", frameId: 0 });
+    t.true(evaluated.success);
+    t.is(evaluated.body.result, "12");
+
+    // Steps over the next line, then out back into `run`
+    await client.request("next", { threadId: 1 });
+    await client.event("stopped");
+    stack = (await client.request("stackTrace", { threadId: 1 })).body.stackFrames;
+    t.is(stack[0].line, 3);
+    variables = (await client.request("variables", { variablesReference: 1 })).body.variables;
+    t.is(variable(variables, "sum").value, "3");
+    await client.request("stepOut", { threadId: 1 });
+    await client.event("stopped");
+    stack = (await client.request("stackTrace", { threadId: 1 })).body.stackFrames;
+    t.is(stack.length, depth);
+    t.true([7, 8].includes(stack[0].line));
+
+    await client.request("continue", { threadId: 1 });
+    child.stdin!.end();
+    t.is(await exited, 0);
+    // Node may print warnings before the result
+    t.regex(stderr, /13$/);
+}
+debuggerSession.title = (version) => `${version}: It stops at breakpoints, steps and inspects a paused script`;
+
+export const debuggerSuite: OneOrMoreMacros<[], Context> = [
+    debuggerRequiresDebug,
+    debuggerProtocol,
+    debuggerLocalhost,
+    debuggerSession
+];
@@ -0,0 +1,75 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+import { LuaValueError } from "../../lib";
+
+export const errorsLuaPayload: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const raiseTable = () => {
+        state.doStringSync(`error({ code = 404, msg = "nf" })`);
+    };
+    const error = t.throws<LuaValueError>(raiseTable);
+    t.deepEqual(error.value, { code: 404, msg: "nf" });
+
+    const raiseString = () => {
+        state.doStringSync(`error("plain")`);
+    };
+    const plain = t.throws<LuaValueError>(raiseString);
+    t.is(plain.value, undefined);
+}
+errorsLuaPayload.title = (version) => `${version}: It attaches non-string Lua error values`;
+
+export const errorsToLua: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("err", new TypeError("bad input"));
+    const luaAssertError = () => {
+        state.doStringSync(`
+        assert(err.name == "TypeError")
+        assert(err.message == "bad input")
+        assert(type(err.stack) == "string")
+        assert(tostring(err) == "TypeError: bad input")
+        `);
+    };
+    t.notThrows(luaAssertError);
+
+    const actual = state.callChunk("function(e) return e end", [new RangeError("out of range")]);
+    t.true(actual instanceof Error);
+    t.is((actual as Error).name, "RangeError");
+    t.is((actual as Error).message, "out of range");
+}
+errorsToLua.title = (version) => `${version}: It converts JS errors to Lua tables`;
+
+class Resource {
+    load() {
+        throw new Error("not found");
+    }
+}
+
+export const errorsFromCallbacks: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.registerClass("Resource", {
+        constructor: Resource,
+        methods: { load: Resource.prototype.load }
+    });
+    const luaAssertCaught = () => {
+        state.doStringSync(`
+        local ok, err = pcall(function() Resource.new():load() end)
+        assert(not ok)
+        assert(err.message == "not found")
+        `);
+    };
+    t.notThrows(luaAssertCaught);
+
+    const uncaught = () => {
+        state.doStringSync(`Resource.new():load()`);
+    };
+    const error = t.throws<LuaValueError>(uncaught);
+    t.is(error.message, "Error: not found");
+    t.true(error.value instanceof Error);
+}
+errorsFromCallbacks.title = (version) => `${version}: It raises JS callback exceptions as Lua error values`;
+
+export const errorsSuite: OneOrMoreMacros<[], Context> = [
+    errorsLuaPayload,
+    errorsToLua,
+    errorsFromCallbacks
+];
@@ -0,0 +1,81 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+export const eventsFromLua: Macro<[], Context> = (t) => {
+    const received: any[][] = [];
+    t.context.lua.on("progress", (...args: any[]) => received.push(args));
+    t.context.lua.doStringSync(`
+        emit("progress", 1, "one")
+        emit("progress", 2)
+        emit("done")
+    `);
+    t.deepEqual(received, [[1, "one"], [2]]);
+}
+eventsFromLua.title = (version) => `${version}: It calls listeners with the values passed to emit`;
+
+export const eventsReturnWhetherHeard: Macro<[], Context> = (t) => {
+    t.context.lua.on("heard", () => {});
+    t.context.lua.doStringSync(`heard, unheard = emit("heard"), emit("unheard")`);
+    t.true(t.context.lua.getGlobal("heard"));
+    t.false(t.context.lua.getGlobal("unheard"));
+}
+eventsReturnWhetherHeard.title = (version) => `${version}: emit returns whether there were listeners`;
+
+export const eventsOnceAndOff: Macro<[], Context> = (t) => {
+    let once = 0;
+    let on = 0;
+    const listener = () => on++;
+    t.context.lua.once("tick", () => once++);
+    t.context.lua.on("tick", listener);
+    t.context.lua.doStringSync(`emit("tick") emit("tick")`);
+    t.context.lua.off("tick", listener);
+    t.context.lua.doStringSync(`emit("tick")`);
+    t.is(once, 1);
+    t.is(on, 2);
+}
+eventsOnceAndOff.title = (version) => `${version}: It removes once listeners after a call, and listeners passed to off`;
+
+export const eventsListenerErrors: Macro<[], Context> = (t) => {
+    const errors: [any, string][] = [];
+    t.context.lua.on("error", (e: any, name: string) => errors.push([e, name]));
+    t.context.lua.on("broken", () => {
+        throw new Error("listener failed");
+    });
+    t.context.lua.doStringSync(`emit("broken")`);
+    t.is(errors.length, 1);
+    t.is(errors[0][0].message, "listener failed");
+    t.is(errors[0][1], "broken");
+}
+eventsListenerErrors.title = (version) => `${version}: It reports listener exceptions to the error listeners`;
+
+export const eventsRethrowWithoutErrorListener: Macro<[], Context> = (t) => {
+    t.context.lua.on("broken", () => {
+        throw new Error("listener failed");
+    });
+    t.throws(() => t.context.lua.doStringSync(`emit("broken")`), { message: /listener failed/ });
+}
+eventsRethrowWithoutErrorListener.title = (version) => `${version}: It rethrows listener exceptions into Lua without error listeners`;
+
+export const eventsNameRequired: Macro<[], Context> = (t) => {
+    t.throws(() => t.context.lua.doStringSync(`emit(1)`), { message: /event name expected/ });
+}
+eventsNameRequired.title = (version) => `${version}: emit requires an event name`;
+
+export const eventsSurviveReset: Macro<[], Context> = (t) => {
+    let calls = 0;
+    t.context.lua.on("ping", () => calls++);
+    t.context.lua.reset();
+    t.context.lua.doStringSync(`emit("ping")`);
+    t.is(calls, 1);
+}
+eventsSurviveReset.title = (version) => `${version}: Listeners are kept across reset`;
+
+export const eventsSuite: OneOrMoreMacros<[], Context> = [
+    eventsFromLua,
+    eventsReturnWhetherHeard,
+    eventsOnceAndOff,
+    eventsListenerErrors,
+    eventsRethrowWithoutErrorListener,
+    eventsNameRequired,
+    eventsSurviveReset
+];
@@ -0,0 +1,176 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import * as fs from "fs";
+import * as os from "os";
+import * as path from "path";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+export const filesystemMemory: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        filesystem: {
+            files: {
+                "main.lua": "result = dofile('lib/helper.lua') + require('lib.module').value",
+                "lib/helper.lua": "return 1",
+                "lib/module.lua": "return { value = 2 }",
+            }
+        }
+    });
+    state.doFileSync("main.lua");
+    t.is(state.getGlobal("result"), 3);
+    state.close();
+}
+filesystemMemory.title = (version) => `${version}: It loads files and modules from memory`;
+
+export const filesystemReadWrite: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        filesystem: { files: { "data.txt": "first\nsecond\n" } }
+    });
+    state.doStringSync(`
+        local file = assert(io.open("out.txt", "w"))
+        file:write("a", 1, "\\n")
+        file:close()
+        local lines = {}
+        for line in io.lines("data.txt") do lines[#lines + 1] = line end
+        assert(#lines == 2 and lines[2] == "second")
+        local out = assert(io.open("out.txt"))
+        result = out:read("a")
+        out:close()
+        local missing, err = io.open("missing.txt")
+        assert(missing == nil and err:find("No such file"))
+    `);
+    t.is(state.getGlobal("result"), "a1\n");
+    state.close();
+}
+filesystemReadWrite.title = (version) => `${version}: It opens, reads and writes virtual files`;
+
+export const filesystemReadOnly: Macro<[], Context> = (t) => {
+    const written: string[] = [];
+    const state = createLuaState({
+        version: t.context.version,
+        filesystem: {
+            read: (p) => p === "config.lua" ? "value = 42" : undefined,
+            write: (p) => { written.push(p) },
+            readOnly: true
+        }
+    });
+    state.doStringSync(`
+        dofile("config.lua")
+        local file, err = io.open("config.lua", "w")
+        assert(file == nil and err:find("read-only"))
+    `);
+    t.is(state.getGlobal("value"), 42);
+    t.deepEqual(written, []);
+    state.close();
+}
+filesystemReadOnly.title = (version) => `${version}: It reads through JS callbacks and refuses writes when read-only`;
+
+export const filesystemDiskJail: Macro<[], Context> = (t) => {
+    const root = fs.mkdtempSync(path.join(os.tmpdir(), "lua-js-"));
+    fs.writeFileSync(path.join(root, "inside.lua"), "inside = true");
+    const state = createLuaState({ version: t.context.version, filesystem: { root } });
+    state.doFileSync("/inside.lua");
+    t.true(state.getGlobal("inside"));
+    const escape = () => state.doStringSync(`dofile("../outside.lua")`);
+    t.throws(escape, { message: /outside of the filesystem root/ });
+    state.close();
+    fs.unlinkSync(path.join(root, "inside.lua"));
+    fs.rmdirSync(root);
+}
+filesystemDiskJail.title = (version) => `${version}: It jails disk-backed filesystems to their root`;
+
+export const filesystemDiskSymlinks: Macro<[], Context> = (t) => {
+    const root = fs.mkdtempSync(path.join(os.tmpdir(), "lua-js-"));
+    const outside = fs.mkdtempSync(path.join(os.tmpdir(), "lua-js-outside-"));
+    const target = path.join(outside, "created.txt");
+    // Dangling, so the target would be created by opening the link for writing
+    fs.symlinkSync(target, path.join(root, "dangling.txt"));
+    fs.symlinkSync(outside, path.join(root, "linked"));
+    const state = createLuaState({ version: t.context.version, filesystem: { root } });
+    const write = (file: string) => () => state.doStringSync(`
+        local file, err = io.open("${file}", "w")
+        if not file then error(err) end
+        file:write("escaped")
+        file:close()
+    `);
+    t.throws(write("dangling.txt"), { message: /outside of the filesystem root/ });
+    t.throws(write("linked/new.txt"), { message: /outside of the filesystem root/ });
+    t.false(fs.existsSync(target));
+    t.false(fs.existsSync(path.join(outside, "new.txt")));
+    state.close();
+    fs.unlinkSync(path.join(root, "dangling.txt"));
+    fs.unlinkSync(path.join(root, "linked"));
+    fs.rmdirSync(root);
+    fs.rmdirSync(outside);
+}
+filesystemDiskSymlinks.title = (version) => `${version}: It refuses symlinks leading out of the root, dangling or not`;
+
+export const filesystemFileLimits: Macro<[], Context> = (t) => {
+    const state = createLuaState({ version: t.context.version, filesystem: { files: {} } });
+    const grow = () => state.doStringSync(`
+        local file = assert(io.open("big.bin", "w"))
+        file:seek("set", 2^40)
+        file:write("x")
+    `);
+    t.throws(grow, { message: /file would be larger than/ });
+    // Writes to a file that's never closed are flushed when it's collected
+    state.doStringSync(`
+        do
+            local file = assert(io.open("log.txt", "w"))
+            file:write("kept")
+        end
+        collectgarbage()
+        collectgarbage()
+        local file = assert(io.open("log.txt"))
+        result = file:read("a")
+        file:close()
+    `);
+    t.is(state.getGlobal("result"), "kept");
+    state.close();
+}
+filesystemFileLimits.title = (version) => `${version}: It limits file sizes and flushes files that aren't closed`;
+
+export const filesystemRemoveRename: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        filesystem: { files: { "a.txt": "a" } }
+    });
+    state.doStringSync(`
+        assert(os.rename("a.txt", "b.txt"))
+        assert(io.open("a.txt") == nil)
+        assert(os.remove("b.txt"))
+        local ok, err = os.remove("b.txt")
+        assert(ok == nil and err:find("No such file"))
+    `);
+    state.close();
+
+    const root = fs.mkdtempSync(path.join(os.tmpdir(), "lua-js-"));
+    const outside = fs.mkdtempSync(path.join(os.tmpdir(), "lua-js-outside-"));
+    fs.writeFileSync(path.join(outside, "real.txt"), "real");
+    fs.symlinkSync(outside, path.join(root, "linked"));
+    const disk = createLuaState({ version: t.context.version, filesystem: { root } });
+    disk.doStringSync(`
+        local ok, err = os.remove("linked/real.txt")
+        assert(ok == nil and err:find("outside of the filesystem root"))
+        ok, err = os.rename("linked/real.txt", "moved.txt")
+        assert(ok == nil and err:find("outside of the filesystem root"))
+    `);
+    t.true(fs.existsSync(path.join(outside, "real.txt")));
+    disk.close();
+    fs.unlinkSync(path.join(root, "linked"));
+    fs.unlinkSync(path.join(outside, "real.txt"));
+    fs.rmdirSync(root);
+    fs.rmdirSync(outside);
+}
+filesystemRemoveRename.title = (version) => `${version}: It removes and renames files within the filesystem`;
+
+export const filesystemSuite: OneOrMoreMacros<[], Context> = [
+    filesystemMemory,
+    filesystemReadWrite,
+    filesystemReadOnly,
+    filesystemDiskJail,
+    filesystemDiskSymlinks,
+    filesystemFileLimits,
+    filesystemRemoveRename
+];
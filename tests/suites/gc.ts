@@ -0,0 +1,60 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+export const gcCollect: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`garbage = {} for i = 1, 10000 do garbage[i] = { i } end`);
+    const before = state.gc.stats().memory;
+    state.doStringSync(`garbage = nil`);
+    state.gc.collect();
+    t.true(state.gc.stats().memory < before);
+}
+gcCollect.title = (version) => `${version}: It collects garbage on demand`;
+
+export const gcStopRestart: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const gc = state.gc;
+    gc.stop();
+    const stopped = gc.stats();
+    gc.restart();
+    const restarted = gc.stats();
+    if (t.context.version === "lua51" || t.context.version === "luajit") {
+        t.is(stopped.running, null);
+    } else {
+        t.false(stopped.running);
+        t.true(restarted.running);
+    }
+    t.is(typeof gc.step(), "boolean");
+    t.is(typeof gc.step(64), "boolean");
+}
+gcStopRestart.title = (version) => `${version}: It stops, restarts and steps the collector`;
+
+export const gcSettings: Macro<[], Context> = (t) => {
+    const gc = t.context.lua.gc;
+    // Lua 5.4 stores these divided by 4
+    gc.setPause(160);
+    t.is(gc.setPause(200), 160);
+    gc.setStepMultiplier(320);
+    t.is(gc.setStepMultiplier(200), 320);
+}
+gcSettings.title = (version) => `${version}: It changes the pause and step multiplier`;
+
+export const gcModes: Macro<[], Context> = (t) => {
+    const gc = t.context.lua.gc;
+    if (t.context.version === "lua54") {
+        t.is(gc.setMode("generational"), "incremental");
+        t.is(gc.setMode("incremental"), "generational");
+    } else {
+        t.is(gc.setMode("incremental", { pause: 180 }), "incremental");
+        t.throws(() => gc.setMode("generational"), { message: /not supported/ });
+    }
+    t.throws(() => gc.setMode("concurrent" as any), { message: /unknown garbage collector mode/ });
+}
+gcModes.title = (version) => `${version}: It selects the collector mode where supported`;
+
+export const gcSuite: OneOrMoreMacros<[], Context> = [
+    gcCollect,
+    gcStopRestart,
+    gcSettings,
+    gcModes
+];
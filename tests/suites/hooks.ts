@@ -0,0 +1,53 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { HookEvent } from "../../lib";
+import { Context } from "../index";
+
+export const hooksLines: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const lines: number[] = [];
+    state.setHook({ line: true }, (event: HookEvent) => {
+        t.is(event.event, "line");
+        t.is(event.source, "traced");
+        lines.push(event.line as number);
+    });
+    state.doStringSync("local a = 1\nlocal b = 2\nlocal c = a + b", "traced");
+    t.deepEqual(lines, [1, 2, 3]);
+}
+hooksLines.title = (version) => `${version}: It reports executed lines to the hook`;
+
+export const hooksCalls: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const names: (string | null)[] = [];
+    state.setHook({ call: true }, (event: HookEvent) => {
+        if (event.event === "call") {
+            names.push(event.name);
+        }
+    });
+    state.doStringSync(`
+        local function greet() return "hi" end
+        greet()
+    `);
+    t.true(names.includes("greet"));
+}
+hooksCalls.title = (version) => `${version}: It reports function calls to the hook`;
+
+export const hooksAbort: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setHook({ count: 1000 }, () => {
+        throw new Error("instruction limit");
+    });
+    const runaway = () => state.doStringSync(`
+        while true do pcall(function() while true do end end) end
+    `);
+    t.throws(runaway, { message: /instruction limit/ });
+
+    state.removeHook();
+    t.notThrows(() => state.doStringSync(`for i = 1, 10000 do end`));
+}
+hooksAbort.title = (version) => `${version}: It aborts execution when the hook throws`;
+
+export const hooksSuite: OneOrMoreMacros<[], Context> = [
+    hooksLines,
+    hooksCalls,
+    hooksAbort
+];
@@ -0,0 +1,79 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+const jsonState = (t: { context: Context }) => createLuaState({ version: t.context.version, json: true } as any);
+
+export const jsonEncode: Macro<[], Context> = (t) => {
+    const state = jsonState(t);
+    const encode = (code: string) => state.callChunk(`function() return json.encode(${code}) end`, []);
+    t.is(encode(`{ 1, "two", true, json.null }`), `[1,"two",true,null]`);
+    t.is(encode(`{ [1] = 1, [3] = 3 }`), `[1,null,3]`);
+    // Mostly holes, so keyed by index instead
+    t.is(encode(`{ [1] = 1, [2^31] = 2 }`), `{"1":1,"2147483648":2}`);
+    t.is(encode(`{}`), `{}`);
+    t.is(encode(`{ name = "lua", quote = "\\"\\n" }, { sort_keys = true }`), `{"name":"lua","quote":"\\"\\n"}`);
+    t.is(encode(`1.5`), `1.5`);
+    t.is(encode(`math.huge`), `null`);
+    t.deepEqual(JSON.parse(encode(`{ nested = { list = { 1, 2 } } }`)), { nested: { list: [1, 2] } });
+    state.close();
+}
+jsonEncode.title = (version) => `${version}: json.encode shapes tables the way they reach JS`;
+
+export const jsonPretty: Macro<[], Context> = (t) => {
+    const state = jsonState(t);
+    const encoded = state.callChunk(`function() return json.encode({ b = { 1 }, a = {} }, { pretty = true, sort_keys = true }) end`, []);
+    t.is(encoded, JSON.stringify({ a: {}, b: [1] }, null, 2));
+    state.close();
+}
+jsonPretty.title = (version) => `${version}: json.encode pretty prints like JSON.stringify`;
+
+export const jsonEncodeErrors: Macro<[], Context> = (t) => {
+    const state = jsonState(t);
+    t.throws(() => state.doStringSync(`json.encode({ 1, key = 2 })`), { message: /both array items and other keys/ });
+    // A cycle is a Lua error that scripts can catch, not a stack overflow
+    const [ok, message] = state.callChunk(`function()
+        local t = {}
+        t.self = t
+        local ok, err = pcall(json.encode, t)
+        return { ok, tostring(err) }
+    end`, []);
+    t.false(ok);
+    t.regex(message, /nested more than 128 levels deep \(is there a cycle\?\)/);
+    t.throws(() => state.doStringSync(`local t = {}; t[1] = t; return t`), { message: /is there a cycle/ });
+    state.close();
+}
+jsonEncodeErrors.title = (version) => `${version}: json.encode rejects cycles and tables mixing array items and keys`;
+
+export const jsonDecode: Macro<[], Context> = (t) => {
+    const state = jsonState(t);
+    state.doStringSync(`
+        decoded = json.decode('{"list": [1, null, "three"], "flag": false, "n": 2.5}')
+        null_kept = decoded.list[2] == json.null
+        length = #decoded.list
+    `);
+    t.true(state.getGlobal("null_kept"));
+    t.is(state.getGlobal("length"), 3);
+    t.deepEqual(state.getGlobal("decoded"), { list: [1, null, "three"], flag: false, n: 2.5 });
+    t.throws(() => state.doStringSync(`json.decode("{")`), { message: /invalid JSON/ });
+    state.close();
+}
+jsonDecode.title = (version) => `${version}: json.decode keeps nulls as json.null`;
+
+export const jsonOptIn: Macro<[], Context> = (t) => {
+    t.is(t.context.lua.getGlobal("json"), undefined);
+    const state = jsonState(t);
+    t.true(state.callChunk(`function() return require("json") == json end`, []));
+    state.reset();
+    t.is(state.callChunk(`function() return json.encode({ 1 }) end`, []), "[1]");
+    state.close();
+}
+jsonOptIn.title = (version) => `${version}: The json library is opt-in, and kept across reset`;
+
+export const jsonSuite: OneOrMoreMacros<[], Context> = [
+    jsonEncode,
+    jsonPretty,
+    jsonEncodeErrors,
+    jsonDecode,
+    jsonOptIn
+];
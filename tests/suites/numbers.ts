@@ -0,0 +1,104 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+import { LuaState } from "../../lib";
+
+// Lua 5.3 and 5.4 have an integer subtype, the others only have doubles.
+const hasIntegers = (state: LuaState): boolean => {
+    return state.callChunk("function() return math.type ~= nil end", []);
+}
+
+export const numbersInfinity: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("inf", Infinity);
+    state.setGlobal("negInf", -Infinity);
+    const luaAssertHuge = () => {
+        state.doStringSync(`
+        assert(inf == math.huge)
+        assert(negInf == -math.huge)
+        `);
+    };
+    t.notThrows(luaAssertHuge);
+
+    state.doStringSync("huge = math.huge");
+    t.is(state.getGlobal("huge"), Infinity);
+}
+numbersInfinity.title = (version) => `${version}: It converts Infinity and math.huge`;
+
+export const numbersNaN: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("nan", NaN);
+    const luaAssertNaN = () => {
+        state.doStringSync(`assert(nan ~= nan)`);
+    };
+    t.notThrows(luaAssertNaN);
+    t.true(Number.isNaN(state.getGlobal("nan")));
+
+    const nanKey = () => {
+        state.doStringSync(`local t = {}; t[0/0] = 1`);
+    };
+    t.throws(nanKey);
+}
+numbersNaN.title = (version) => `${version}: It converts NaN, and Lua rejects NaN keys`;
+
+export const numbersNegativeZero: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("negZero", -0);
+    const luaAssertNegativeZero = () => {
+        state.doStringSync(`assert(1/negZero == -math.huge)`);
+    };
+    t.notThrows(luaAssertNegativeZero);
+
+    const actual = state.callChunk("function() return -0.0 end", []);
+    if (hasIntegers(state)) {
+        t.true(Object.is(actual, -0));
+    } else {
+        t.is(actual, 0);
+    }
+}
+numbersNegativeZero.title = (version) => `${version}: It preserves -0`;
+
+export const numbersIntegerSubtype: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("one", 1);
+    state.setGlobal("half", 1.5);
+    if (hasIntegers(state)) {
+        const luaAssertSubtypes = () => {
+            state.doStringSync(`
+            assert(math.type(one) == "integer")
+            assert(math.type(half) == "float")
+            assert(one // 1 == 1 and math.type(one // 1) == "integer")
+            `);
+        };
+        t.notThrows(luaAssertSubtypes);
+
+        const max = state.callChunk("function() return math.maxinteger end", []);
+        t.is(typeof max, "bigint");
+        t.is(String(max), "9223372036854775807");
+    } else {
+        const luaAssertNumbers = () => {
+            state.doStringSync(`assert(one == 1 and half == 1.5)`);
+        };
+        t.notThrows(luaAssertNumbers);
+
+        // Integral doubles outside the safe range stay numbers
+        const big = state.callChunk("function() return 2^60 end", []);
+        t.is(big, Math.pow(2, 60));
+    }
+}
+numbersIntegerSubtype.title = (version) => `${version}: It maps integers and floats to the Lua subtypes`;
+
+export const numbersTableKeys: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`keys = { [0] = "zero", [-1] = "neg", [math.huge] = "inf", [1e21] = "big" }`);
+    const actual = state.getGlobal("keys");
+    t.deepEqual(actual, { "0": "zero", "-1": "neg", "Infinity": "inf", "1e+21": "big" });
+}
+numbersTableKeys.title = (version) => `${version}: It converts numeric table keys like JS would`;
+
+export const numbersSuite: OneOrMoreMacros<[], Context> = [
+    numbersInfinity,
+    numbersNaN,
+    numbersNegativeZero,
+    numbersIntegerSubtype,
+    numbersTableKeys
+];
@@ -0,0 +1,90 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+// { a: 1, b: [true, "hi"] }
+const packedObject = Buffer.from([0x82, 0xa1, 0x61, 0x01, 0xa1, 0x62, 0x92, 0xc3, 0xa2, 0x68, 0x69]);
+
+export const packedSetGlobal: Macro<[], Context> = (t) => {
+    t.context.lua.setGlobalPacked("data", packedObject);
+    t.deepEqual(t.context.lua.getGlobal("data"), { a: 1, b: [true, "hi"] });
+}
+packedSetGlobal.title = (version) => `${version}: setGlobalPacked decodes MessagePack into Lua`;
+
+export const packedGetGlobal: Macro<[], Context> = (t) => {
+    t.context.lua.doStringSync(`
+        list = { 1, -1, 300 }
+        empty = {}
+        float = 1.5
+        sparse = { [1] = true, [3] = true }
+    `);
+    t.deepEqual(t.context.lua.getGlobalPacked("list"), Buffer.from([0x93, 0x01, 0xff, 0xcd, 0x01, 0x2c]));
+    t.deepEqual(t.context.lua.getGlobalPacked("empty"), Buffer.from([0x80]));
+    const float = Buffer.alloc(9);
+    float[0] = 0xcb;
+    float.writeDoubleBE(1.5, 1);
+    t.deepEqual(t.context.lua.getGlobalPacked("float"), float);
+    // Holes are nil, like they're `undefined` in getGlobal's array
+    t.deepEqual(t.context.lua.getGlobalPacked("sparse"), Buffer.from([0x93, 0xc3, 0xc0, 0xc3]));
+}
+packedGetGlobal.title = (version) => `${version}: getGlobalPacked encodes Lua values as MessagePack`;
+
+export const packedRoundTrip: Macro<[], Context> = (t) => {
+    t.context.lua.doStringSync(`
+        data = { items = {}, name = "inventory" }
+        for i = 1, 10000 do
+            data.items[i] = { id = i, label = "item " .. i, tags = { "a", "b" } }
+        end
+    `);
+    const packed = t.context.lua.getGlobalPacked("data");
+    const other = createLuaState({ version: t.context.version } as any);
+    other.setGlobalPacked("data", packed);
+    t.deepEqual(other.getGlobal("data"), t.context.lua.getGlobal("data"));
+    other.close();
+}
+packedRoundTrip.title = (version) => `${version}: Packed globals round trip between states`;
+
+export const packedSparse: Macro<[], Context> = (t) => {
+    t.context.lua.doStringSync(`sparse = { [4e9] = "far" }`);
+    const packed = t.context.lua.getGlobalPacked("sparse");
+    // A map of 4000000000 to "far", not an array of four billion nils
+    t.deepEqual(packed, Buffer.from([0x81, 0xce, 0xee, 0x6b, 0x28, 0x00, 0xa3, 0x66, 0x61, 0x72]));
+    const other = createLuaState({ version: t.context.version } as any);
+    other.setGlobalPacked("sparse", packed);
+    t.is(other.callChunk(`function() return sparse[4e9] end`, []), "far");
+    other.close();
+}
+packedSparse.title = (version) => `${version}: Tables that are mostly holes are packed as maps`;
+
+export const packedCallChunk: Macro<[], Context> = (t) => {
+    const args = Buffer.from([0x92, 0x01, 0x02]);
+    t.deepEqual(t.context.lua.callChunkPacked("function(a, b) return a + b end", args), Buffer.from([0x03]));
+    const result = t.context.lua.callChunkPacked("function(data) return data.b end", "packed", Buffer.from([0x91, ...packedObject]));
+    t.deepEqual(result, Buffer.from([0x92, 0xc3, 0xa2, 0x68, 0x69]));
+}
+packedCallChunk.title = (version) => `${version}: callChunkPacked takes and returns MessagePack`;
+
+export const packedErrors: Macro<[], Context> = (t) => {
+    t.context.lua.doStringSync(`f = print`);
+    t.throws(() => t.context.lua.getGlobalPacked("f"), { message: /can't pack a function/ });
+    t.throws(() => t.context.lua.setGlobalPacked("x", Buffer.from([0x92, 0x01])), { message: /unexpected end of data/ });
+    t.throws(() => t.context.lua.callChunkPacked("function() end", Buffer.from([0x01])), { message: /must be a MessagePack array/ });
+}
+packedErrors.title = (version) => `${version}: It rejects values and data it can't convert`;
+
+export const packedKeptOnReset: Macro<[], Context> = (t) => {
+    t.context.lua.setGlobalPacked("data", packedObject);
+    t.context.lua.reset({ keepRegistrations: true });
+    t.deepEqual(t.context.lua.getGlobal("data"), { a: 1, b: [true, "hi"] });
+}
+packedKeptOnReset.title = (version) => `${version}: Packed globals are kept by reset with keepRegistrations`;
+
+export const packedSuite: OneOrMoreMacros<[], Context> = [
+    packedSetGlobal,
+    packedGetGlobal,
+    packedRoundTrip,
+    packedSparse,
+    packedCallChunk,
+    packedErrors,
+    packedKeptOnReset
+];
@@ -0,0 +1,90 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { LuaStatePool } from "../../lib";
+import { Context } from "../index";
+
+export const poolInit: Macro<[], Context> = async (t) => {
+    const pool = new LuaStatePool({
+        version: t.context.version,
+        max: 2,
+        init: "greeting = 'hello'"
+    } as any);
+    const greeting = await pool.withState((state) => state.getGlobal("greeting"));
+    t.is(greeting, "hello");
+
+    // Released states are reset and initialized again
+    await pool.withState((state) => state.doStringSync("greeting = 'changed'"));
+    t.is(await pool.withState((state) => state.getGlobal("greeting")), "hello");
+    t.is(pool.metrics.created, 1);
+    pool.drain();
+}
+poolInit.title = (version) => `${version}: It initializes and reuses pooled states`;
+
+export const poolWaiting: Macro<[], Context> = async (t) => {
+    const pool = new LuaStatePool({ version: t.context.version, max: 1 } as any);
+    const first = await pool.acquire();
+    const second = pool.acquire();
+    t.is(pool.metrics.waiting, 1);
+    t.is(pool.metrics.inUse, 1);
+    setTimeout(() => pool.release(first), 20);
+    t.is(await second, first);
+    t.is(pool.metrics.acquisitions, 2);
+    t.true(pool.metrics.maxWaitTime >= 10);
+    pool.release(first);
+    pool.drain();
+}
+poolWaiting.title = (version) => `${version}: It makes acquisitions wait for a free state`;
+
+export const poolLimits: Macro<[], Context> = async (t) => {
+    const pool = new LuaStatePool({ version: t.context.version, max: 1, maxUses: 2 } as any);
+    const first = await pool.acquire();
+    pool.release(first);
+    t.is(await pool.acquire(), first);
+    pool.release(first);
+    // Used twice, so it was discarded
+    const second = await pool.acquire();
+    t.not(second, first);
+    t.is(pool.metrics.discarded, 1);
+    t.is(pool.metrics.created, 2);
+    pool.release(second);
+
+    await t.throwsAsync(pool.withState(() => {
+        throw new Error("broken");
+    }), { message: "broken" });
+    t.is(pool.metrics.discarded, 2);
+    pool.drain();
+}
+poolLimits.title = (version) => `${version}: It discards states over their limits`;
+
+export const poolTimeout: Macro<[], Context> = async (t) => {
+    const pool = new LuaStatePool({ version: t.context.version, max: 1, acquireTimeout: 10 } as any);
+    const state = await pool.acquire();
+    await t.throwsAsync(pool.acquire(), { message: /timed out/ });
+    t.is(pool.metrics.waiting, 0);
+    pool.release(state);
+    pool.drain();
+}
+poolTimeout.title = (version) => `${version}: It times out waiting acquisitions`;
+
+export const poolClosedState: Macro<[], Context> = async (t) => {
+    const pool = new LuaStatePool({ version: t.context.version, max: 1 } as any);
+    const first = await pool.acquire();
+    first.close();
+    pool.release(first);
+    t.is(pool.metrics.size, 0);
+    t.is(pool.metrics.discarded, 1);
+    // Its slot is free again
+    const second = await pool.acquire();
+    t.not(second, first);
+    t.false(second.isClosed);
+    pool.release(second);
+    pool.drain();
+}
+poolClosedState.title = (version) => `${version}: It drops states closed before their release`;
+
+export const poolSuite: OneOrMoreMacros<[], Context> = [
+    poolInit,
+    poolWaiting,
+    poolLimits,
+    poolTimeout,
+    poolClosedState
+];
@@ -0,0 +1,96 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+const WORKLOAD = `
+    local function fib(n)
+        if n < 2 then return n end
+        return fib(n - 1) + fib(n - 2)
+    end
+    local function busy()
+        local sum = 0
+        for i = 1, 200000 do sum = sum + i end
+        return sum
+    end
+    fib(15)
+    busy()
+`;
+
+export const profilerInstrumented: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startProfiling({ mode: "instrumented" });
+    state.doStringSync(WORKLOAD, "workload");
+    const profile = state.stopProfiling();
+
+    t.is(profile.mode, "instrumented");
+    const fib = profile.functions.find((f) => f.name === "fib");
+    t.truthy(fib);
+    t.is(fib!.calls, 1973);
+    t.is(fib!.source, "workload");
+    t.true(fib!.totalTime >= fib!.selfTime);
+    const busy = profile.functions.find((f) => f.name === "busy");
+    t.is(busy!.calls, 1);
+}
+profilerInstrumented.title = (version) => `${version}: It counts and times calls when instrumented`;
+
+export const profilerSampling: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startProfiling({ mode: "sampling", interval: 100 });
+    state.doStringSync(`for _ = 1, 10 do ${WORKLOAD} end`, "workload");
+    const profile = state.stopProfiling();
+
+    t.is(profile.mode, "sampling");
+    t.true(profile.samples.length > 0);
+    t.true(profile.samples.some((s) => s.stack.some((frame) => frame.startsWith("busy (workload:"))));
+}
+profilerSampling.title = (version) => `${version}: It samples stacks`;
+
+export const profilerExports: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.startProfiling({ interval: 50 });
+    state.doStringSync(`for _ = 1, 10 do ${WORKLOAD} end`, "workload");
+    const profile = state.stopProfiling();
+
+    const cpuProfile = profile.cpuProfile;
+    t.is(cpuProfile.nodes[0].callFrame.functionName, "(root)");
+    t.is(cpuProfile.samples.length, profile.samples.length);
+    t.is(cpuProfile.timeDeltas.length, profile.samples.length);
+    const ids = new Set(cpuProfile.nodes.map((node) => node.id));
+    t.true(cpuProfile.samples.every((id) => ids.has(id)));
+
+    const lines = profile.folded.trim().split("\n");
+    const total = lines.reduce((sum, line) => sum + Number(line.slice(line.lastIndexOf(" ") + 1)), 0);
+    t.is(total, profile.samples.length);
+}
+profilerExports.title = (version) => `${version}: It exports .cpuprofile and folded stacks`;
+
+export const profilerNotStarted: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    t.throws(() => state.stopProfiling(), { message: /not started/ });
+    t.throws(() => state.startProfiling({ mode: "tracing" as any }), { instanceOf: TypeError });
+}
+profilerNotStarted.title = (version) => `${version}: It rejects bad profiling calls`;
+
+export const profilerWithHook: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    let lines = 0;
+    state.setHook({ line: true }, () => { lines++; });
+    state.startProfiling({ mode: "instrumented" });
+    state.doStringSync(WORKLOAD, "workload");
+    const profile = state.stopProfiling();
+    t.is(profile.functions.find((f) => f.name === "fib")!.calls, 1973);
+    t.true(lines > 0);
+
+    // Stopping the profiler leaves the hook in place
+    lines = 0;
+    state.doStringSync("local a = 1\nlocal b = 2", "after");
+    t.is(lines, 2);
+}
+profilerWithHook.title = (version) => `${version}: It profiles alongside setHook`;
+
+export const profilerSuite: OneOrMoreMacros<[], Context> = [
+    profilerInstrumented,
+    profilerSampling,
+    profilerExports,
+    profilerNotStarted,
+    profilerWithHook
+];
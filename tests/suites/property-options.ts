@@ -0,0 +1,114 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+class Point {
+    x: number;
+    private secret: number;
+
+    constructor(x: number) {
+        this.x = x;
+        this.secret = 42;
+        Object.defineProperty(this, "secret", { enumerable: false });
+    }
+
+    get double() {
+        return this.x * 2;
+    }
+
+    describe() {
+        return `Point(${this.x})`;
+    }
+}
+
+export const propertyDefaults: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const key = Symbol("key");
+    state.setGlobal("p", Object.assign(new Point(1), { [key]: "symbol" }));
+    state.doStringSync(`
+        assert(p.x == 1)
+        assert(p.double == nil)
+        assert(p.secret == nil)
+        local count = 0
+        for _ in pairs(p) do count = count + 1 end
+        assert(count == 1)
+    `);
+    t.pass();
+}
+propertyDefaults.title = (version) => `${version}: It only converts own enumerable string keys by default`;
+
+export const propertyGettersAndInherited: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        conversion: { getters: true, inherited: true, nonEnumerable: true }
+    });
+    const base = { inheritedField: "base" };
+    const child = Object.create(base);
+    child.own = "child";
+    state.setGlobal("p", new Point(3));
+    state.setGlobal("child", child);
+    state.doStringSync(`
+        assert(p.x == 3)
+        assert(p.double == 6)
+        assert(p.secret == 42)
+        assert(p.describe == nil)
+        assert(child.own == "child")
+        assert(child.inheritedField == "base")
+    `);
+    state.close();
+    t.pass();
+}
+propertyGettersAndInherited.title = (version) => `${version}: It includes getters, inherited and non-enumerable properties when enabled`;
+
+export const propertySymbols: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        conversion: { symbols: "lightuserdata" }
+    });
+    const key = Symbol("key");
+    state.setGlobal("key", key);
+    state.setGlobal("obj", { [key]: "symbol", plain: 1 });
+    state.doStringSync(`
+        assert(type(key) == "userdata")
+        assert(obj[key] == "symbol")
+        assert(obj.plain == 1)
+    `);
+    t.is(state.getGlobal("key"), key);
+    const actual = state.getGlobal<{ [key: string]: any }>("obj");
+    t.is(actual[key as any], "symbol");
+    state.close();
+}
+propertySymbols.title = (version) => `${version}: It passes symbols to Lua as light userdata when enabled`;
+
+export const propertySymbolValuesSkipped: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("x", { tag: Symbol("t"), plain: 1 });
+    state.setGlobal("bare", Symbol("bare"));
+    state.doStringSync(`
+        assert(x.tag == nil)
+        assert(x.plain == 1)
+        assert(bare == nil)
+        local count = 0
+        for _ in pairs(x) do count = count + 1 end
+        assert(count == 1)
+    `);
+    t.pass();
+}
+propertySymbolValuesSkipped.title = (version) => `${version}: It drops symbol values by default`;
+
+export const propertyOptionsValidated: Macro<[], Context> = (t) => {
+    const create = () => createLuaState({
+        version: t.context.version,
+        conversion: { symbols: "convert" as any }
+    });
+    t.throws(create);
+}
+propertyOptionsValidated.title = (version) => `${version}: It rejects unknown symbol options`;
+
+export const propertyOptionsSuite: OneOrMoreMacros<[], Context> = [
+    propertyDefaults,
+    propertyGettersAndInherited,
+    propertySymbols,
+    propertySymbolValuesSkipped,
+    propertyOptionsValidated
+];
@@ -0,0 +1,77 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+class Counter {
+    count = 0;
+}
+
+export const resetClearsEverything: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("config", { name: "game" });
+    state.registerFunction("notify", () => {});
+    state.doStringSync("scripted = true");
+    state.reset();
+    t.deepEqual(state.callChunk(`function() return { config == nil, notify == nil, scripted == nil } end`, []), [true, true, true]);
+
+    // Nothing is left to restore either
+    state.reset({ keepRegistrations: true });
+    t.is(state.getGlobal("config"), undefined);
+}
+resetClearsEverything.title = (version) => `${version}: It clears registrations on a plain reset`;
+
+export const resetKeepsRegistrations: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("config", { name: "game" });
+    state.setGlobal("limit", 1);
+    state.setGlobal("limit", 5);
+    state.registerFunction("notify", () => {});
+    state.registerClass("Counter", { constructor: Counter });
+    state.doStringSync("scripted = true");
+
+    state.reset({ keepRegistrations: true });
+    t.deepEqual(state.getGlobal("config"), { name: "game" });
+    t.is(state.getGlobal("limit"), 5);
+    t.is(state.callChunk(`function() return type(notify) end`, []), "function");
+    t.true(state.callChunk(`function() return Counter.new() end`, []) instanceof Counter);
+    t.is(state.getGlobal("scripted"), undefined);
+}
+resetKeepsRegistrations.title = (version) => `${version}: It re-applies registrations with keepRegistrations`;
+
+export const resetKeepsHook: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    const lines: number[] = [];
+    state.setHook({ line: true }, (event) => {
+        lines.push(event.line as number);
+    });
+    state.reset({ keepRegistrations: true });
+    state.doStringSync("local a = 1");
+    t.deepEqual(lines, [1]);
+
+    state.removeHook();
+    state.reset({ keepRegistrations: true });
+    state.doStringSync("local b = 2");
+    t.deepEqual(lines, [1]);
+}
+resetKeepsHook.title = (version) => `${version}: It re-installs the hook with keepRegistrations`;
+
+export const resetSkipsFailedRegistrations: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.setGlobal("config", { name: "game" });
+    state.doStringSync(`setmetatable(_G, { __newindex = function() error("read only") end })`);
+    t.throws(() => state.setGlobal("blocked", 1), { message: /read only/ });
+    // Truncated, so it would fail again on every reset
+    t.throws(() => state.setGlobalPacked("broken", Buffer.from([0x92, 0x01])), { message: /unexpected end of data/ });
+
+    state.reset({ keepRegistrations: true });
+    t.deepEqual(state.getGlobal("config"), { name: "game" });
+    t.is(state.getGlobal("blocked"), undefined);
+    t.is(state.getGlobal("broken"), undefined);
+}
+resetSkipsFailedRegistrations.title = (version) => `${version}: It only keeps registrations that succeeded`;
+
+export const resetSuite: OneOrMoreMacros<[], Context> = [
+    resetClearsEverything,
+    resetKeepsRegistrations,
+    resetKeepsHook,
+    resetSkipsFailedRegistrations
+];
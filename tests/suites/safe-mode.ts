@@ -0,0 +1,47 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+const DEBUG = 0x80000000;
+const MATH = 0x80;
+const TABLE = 0x2;
+const ALL = 0xFFFFFFFF;
+
+export const safeModeRejectsDebug: Macro<[], Context> = (t) => {
+    const withDebug = () => createLuaState({ version: t.context.version, libraries: [MATH, DEBUG] } as any);
+    t.throws(withDebug, { message: /unsafe/ });
+    const withAll = () => createLuaState({ version: t.context.version, libraries: [ALL] } as any);
+    t.throws(withAll, { message: /unsafe/ });
+}
+safeModeRejectsDebug.title = (version) => `${version}: It rejects unsafe libraries by default`;
+
+export const safeModeUnsafeOptIn: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        libraries: [MATH, DEBUG],
+        unsafe: true
+    } as any);
+    state.doStringSync(`assert(type(debug.traceback) == "function")`);
+    t.deepEqual(state.loadedLibraries, ["math", "debug"]);
+    state.close();
+}
+safeModeUnsafeOptIn.title = (version) => `${version}: It loads unsafe libraries with the unsafe option`;
+
+export const safeModeLoadedLibraries: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        libraries: [TABLE, MATH],
+    } as any);
+    t.deepEqual(state.loadedLibraries, ["table", "math"]);
+    // `package` is only loaded when it's in the list
+    t.true(state.callChunk(`function() return package == nil and require == nil end`, []));
+    t.false(t.context.lua.loadedLibraries.includes("debug"));
+    state.close();
+}
+safeModeLoadedLibraries.title = (version) => `${version}: It reports the loaded libraries`;
+
+export const safeModeSuite: OneOrMoreMacros<[], Context> = [
+    safeModeRejectsDebug,
+    safeModeUnsafeOptIn,
+    safeModeLoadedLibraries
+];
@@ -0,0 +1,59 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+export const sandboxAllowList: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        sandbox: { allow: { os: ["time", "clock"] } }
+    });
+    state.doStringSync(`
+        assert(type(os.time()) == "number")
+        assert(os.execute == nil)
+        assert(os.remove == nil)
+    `);
+    t.deepEqual(state.getEnvironment().os, ["clock", "time"]);
+    state.close();
+}
+sandboxAllowList.title = (version) => `${version}: It keeps only allowed library functions`;
+
+export const sandboxDenyAndRemove: Macro<[], Context> = (t) => {
+    const state = createLuaState({
+        version: t.context.version,
+        sandbox: { deny: { string: ["rep"] }, remove: ["load", "dofile", "rawset"] }
+    });
+    state.doStringSync(`
+        assert(string.rep == nil)
+        assert(("x").rep == nil)
+        assert(string.upper("a") == "A")
+        assert(load == nil and dofile == nil and rawset == nil)
+    `);
+    const environment = state.getEnvironment();
+    t.is(environment.print, "function");
+    t.is(environment.load, undefined);
+    state.close();
+}
+sandboxDenyAndRemove.title = (version) => `${version}: It removes denied functions and globals`;
+
+export const sandboxProfiles: Macro<[], Context> = (t) => {
+    const state = createLuaState({ version: t.context.version, sandbox: "strict" });
+    const assertStrict = () => state.doStringSync(`
+        assert(io == nil and debug == nil and require == nil)
+        assert(loadstring == nil and load == nil)
+        assert(os.execute == nil and os.time ~= nil)
+    `);
+    t.notThrows(assertStrict);
+    state.reset();
+    t.notThrows(assertStrict);
+    state.close();
+
+    const create = () => createLuaState({ version: t.context.version, sandbox: "lenient" as any });
+    t.throws(create);
+}
+sandboxProfiles.title = (version) => `${version}: It applies named sandbox profiles, including after reset`;
+
+export const sandboxSuite: OneOrMoreMacros<[], Context> = [
+    sandboxAllowList,
+    sandboxDenyAndRemove,
+    sandboxProfiles
+];
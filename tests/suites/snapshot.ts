@@ -0,0 +1,101 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+export const snapshotRoundTrip: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`
+        player = { name = "ada", level = 3, ratio = 0.5, alive = true, items = { "sword", "shield" } }
+        session = { id = 42, owner = player }
+        player.session = session
+    `);
+    const snapshot = state.snapshot();
+    t.true(Buffer.isBuffer(snapshot));
+
+    const restored = createLuaState({ version: t.context.version });
+    restored.restore(snapshot);
+    t.deepEqual(restored.callChunk(`function()
+        return {
+            player.name, player.level, player.ratio, player.alive, player.items[2],
+            session.id, session.owner == player, player.session == session,
+        }
+    end`, []), ["ada", 3, 0.5, true, "shield", 42, true, true]);
+    t.is(restored.callChunk(`function() return type(print) end`, []), "function");
+    restored.close();
+}
+snapshotRoundTrip.title = (version) => `${version}: It restores globals with shared references and cycles`;
+
+export const snapshotSkipsFunctions: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`
+        config = { name = "game", handler = function() end }
+        function greet() return "hi" end
+    `);
+    const restored = createLuaState({ version: t.context.version });
+    restored.doStringSync(`function greet() return "kept" end`);
+    restored.restore(state.snapshot());
+    t.is(restored.callChunk(`function() return config.name end`, []), "game");
+    t.true(restored.callChunk(`function() return config.handler == nil end`, []));
+    t.is(restored.callChunk(`function() return greet() end`, []), "kept");
+    restored.close();
+
+    t.throws(() => state.snapshot({ strict: true }), { message: /function at _G\.(config\.handler|greet)/ });
+}
+snapshotSkipsFunctions.title = (version) => `${version}: It skips functions, or rejects them when strict`;
+
+export const snapshotInvalid: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    t.throws(() => state.restore(Buffer.from("not a snapshot")), { message: /not a Lua state snapshot/ });
+    const truncated = (() => {
+        state.doStringSync(`data = { 1, 2, 3 }`);
+        const snapshot = state.snapshot();
+        return snapshot.subarray(0, snapshot.length - 3);
+    })();
+    t.throws(() => state.restore(truncated), { message: /unexpected end/ });
+}
+snapshotInvalid.title = (version) => `${version}: It rejects invalid snapshots`;
+
+export const snapshotTruncatedUnchanged: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`first = 1; second = { "a", "b" }`);
+    const snapshot = state.snapshot();
+    state.doStringSync(`first = 10; second = nil`);
+    t.throws(() => state.restore(snapshot.subarray(0, snapshot.length - 1)), { message: /unexpected end/ });
+    t.deepEqual(state.callChunk(`function() return { first, second == nil } end`, []), [10, true]);
+}
+snapshotTruncatedUnchanged.title = (version) => `${version}: It leaves the globals alone when a snapshot is truncated`;
+
+export const snapshotDepthLimit: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    state.doStringSync(`
+        deep = {}
+        local t = deep
+        for i = 1, 200 do t.next = {}; t = t.next end
+    `);
+    t.throws(() => state.snapshot(), { message: /nested more than 128 tables deep/ });
+
+    // `x = { next = { next = ... } }`, written by hand as nothing produces it
+    const u32 = (n: number) => {
+        const bytes = Buffer.alloc(4);
+        bytes.writeUInt32LE(n);
+        return bytes;
+    };
+    const key = (name: string) => Buffer.concat([Buffer.from([5]), u32(name.length), Buffer.from(name)]);
+    const parts = [Buffer.from("LJSS"), Buffer.from([1]), u32(1), key("x")];
+    for (let i = 0; i < 10000; i++) {
+        parts.push(Buffer.from([6]), u32(1), key("next"));
+    }
+    parts.push(Buffer.from([6]), u32(0));
+    state.doStringSync(`x = "kept"`);
+    t.throws(() => state.restore(Buffer.concat(parts)), { message: /nested more than 128 tables deep/ });
+    t.is(state.getGlobal("x"), "kept");
+}
+snapshotDepthLimit.title = (version) => `${version}: It limits how deeply snapshots nest`;
+
+export const snapshotSuite: OneOrMoreMacros<[], Context> = [
+    snapshotRoundTrip,
+    snapshotSkipsFunctions,
+    snapshotInvalid,
+    snapshotTruncatedUnchanged,
+    snapshotDepthLimit
+];
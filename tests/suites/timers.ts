@@ -0,0 +1,81 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { createLuaState } from "../../lib";
+import { Context } from "../index";
+
+const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+export const timersAfter: Macro<[], Context> = async (t) => {
+    const state = createLuaState({ version: t.context.version, timers: true } as any);
+    state.doStringSync(`
+        fired = ""
+        timer.after(20, function() fired = fired .. "second" end)
+        timer.after(0, function() fired = fired .. "first," end)
+    `);
+    t.is(state.getGlobal("fired"), "");
+    await sleep(50);
+    t.is(state.getGlobal("fired"), "first,second");
+    state.close();
+}
+timersAfter.title = (version) => `${version}: timer.after calls back once the time has passed`;
+
+export const timersEveryAndCancel: Macro<[], Context> = async (t) => {
+    const state = createLuaState({ version: t.context.version, timers: true } as any);
+    state.doStringSync(`
+        ticks = 0
+        local id
+        id = timer.every(5, function()
+            ticks = ticks + 1
+            if ticks == 3 then cancelled = timer.cancel(id) end
+        end)
+    `);
+    await sleep(60);
+    t.is(state.getGlobal("ticks"), 3);
+    t.true(state.getGlobal("cancelled"));
+    t.false(state.callChunk("function() return timer.cancel(12345) end", []));
+    state.close();
+}
+timersEveryAndCancel.title = (version) => `${version}: timer.every repeats until cancelled`;
+
+export const timersCancelledOnReset: Macro<[], Context> = async (t) => {
+    const state = createLuaState({ version: t.context.version, timers: true } as any);
+    let fired = false;
+    state.registerFunction("fire", () => {
+        fired = true;
+    });
+    state.doStringSync(`timer.after(10, function() fire() end)`);
+    state.reset({ keepRegistrations: true });
+    // The library is back, without the old timers
+    t.is(state.callChunk("function() return type(timer.after) end", []), "function");
+    await sleep(40);
+    t.false(fired);
+    state.close();
+}
+timersCancelledOnReset.title = (version) => `${version}: reset cancels pending timers`;
+
+export const timersErrors: Macro<[], Context> = async (t) => {
+    const state = createLuaState({ version: t.context.version, timers: true } as any);
+    const errors: any[] = [];
+    state.on("error", (e: any) => errors.push(e));
+    state.doStringSync(`timer.after(0, function() error("timer failed") end)`);
+    await sleep(20);
+    t.is(errors.length, 1);
+    t.regex(errors[0].message, /timer failed/);
+    state.close();
+}
+timersErrors.title = (version) => `${version}: Errors in timer callbacks are emitted as error events`;
+
+export const timersOptIn: Macro<[], Context> = (t) => {
+    t.is(t.context.lua.getGlobal("timer"), undefined);
+    const state = createLuaState({ version: t.context.version, timers: true } as any);
+    t.throws(() => state.doStringSync(`timer.after(-1, function() end)`), { message: /non-negative number expected/ });
+    state.close();
+}
+timersOptIn.title = (version) => `${version}: The timer library is opt-in and checks its arguments`;
+
+export const timersSuite: OneOrMoreMacros<[], Context> = [
+    timersAfter,
+    timersEveryAndCancel,
+    timersCancelledOnReset,
+    timersErrors,
+    timersOptIn
+];
@@ -0,0 +1,135 @@
+import { Macro, OneOrMoreMacros } from "ava";
+import { Context } from "../index";
+
+class Vector {
+    x: number;
+    y: number;
+
+    constructor(x: number, y: number) {
+        this.x = x;
+        this.y = y;
+    }
+}
+
+const registerVector = (t: { context: Context }) => {
+    t.context.lua.registerClass("Vector", {
+        constructor: Vector,
+        methods: {
+            length() {
+                return Math.sqrt(this.x * this.x + this.y * this.y);
+            },
+            scale(factor: number) {
+                this.x *= factor;
+                this.y *= factor;
+            }
+        },
+        getters: {
+            x() {
+                return this.x;
+            },
+            y() {
+                return this.y;
+            }
+        },
+        setters: {
+            x(value: number) {
+                this.x = value;
+            }
+        },
+        metamethods: {
+            __add: (a: Vector, b: Vector) => new Vector(a.x + b.x, a.y + b.y),
+            __eq: (a: Vector, b: Vector) => a.x === b.x && a.y === b.y,
+            __tostring: (v: Vector) => `Vector(${v.x}, ${v.y})`
+        }
+    });
+}
+
+export const userDataConstruct: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    state.doStringSync(`
+        v = Vector.new(3, 4)
+        assert(v:length() == 5)
+        assert(v.x == 3)
+        v.x = 6
+        assert(v.x == 6)
+    `);
+    const actual = state.getGlobal("v");
+    t.true(actual instanceof Vector);
+    t.is((actual as Vector).x, 6);
+}
+userDataConstruct.title = (version) => `${version}: It constructs registered classes from Lua`;
+
+export const userDataMetamethods: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    const runMetamethods = () => {
+        state.doStringSync(`
+        local sum = Vector.new(1, 2) + Vector.new(3, 4)
+        assert(sum.x == 4 and sum.y == 6)
+        assert(Vector.new(1, 1) == Vector.new(1, 1))
+        assert(tostring(sum) == "Vector(4, 6)")
+        `);
+    };
+    t.notThrows(runMetamethods);
+}
+userDataMetamethods.title = (version) => `${version}: It maps metamethods to JS functions`;
+
+export const userDataToLua: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    const vector = new Vector(1, 2);
+    state.setGlobal("v", vector);
+    state.doStringSync(`v:scale(2)`);
+    t.is(vector.x, 2);
+    t.is(vector.y, 4);
+    t.is(state.getGlobal("v"), vector);
+}
+userDataToLua.title = (version) => `${version}: It passes class instances to Lua by reference`;
+
+export const userDataErrors: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    const setMissing = () => {
+        state.doStringSync(`Vector.new(1, 2).z = 1`);
+    };
+    t.throws(setMissing);
+
+    const missingMetamethod = () => {
+        state.doStringSync(`local _ = Vector.new(1, 2) * 2`);
+    };
+    t.throws(missingMetamethod);
+}
+userDataErrors.title = (version) => `${version}: It errors on undefined fields and metamethods`;
+
+export const userDataNonStringKeys: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    state.setGlobal("v", new Vector(1, 2));
+    t.deepEqual(state.callChunk(`function() return { v[1] == nil, v[true] == nil, v[v] == nil } end`, []), [true, true, true]);
+}
+userDataNonStringKeys.title = (version) => `${version}: It indexes class instances with non-string keys as nil`;
+
+export const userDataHiddenReferences: Macro<[], Context> = (t) => {
+    const state = t.context.lua;
+    registerVector(t);
+    state.setGlobal("v", new Vector(1, 2));
+    t.false(Object.keys(globalThis).some((key) => key.includes("luaJs")));
+    const table = (globalThis as any)[Symbol.for("lua-js.references")];
+    // Modules are strict, so writing the read-only property throws
+    t.throws(() => {
+        (globalThis as any)[Symbol.for("lua-js.references")] = new Map();
+    });
+    t.is((globalThis as any)[Symbol.for("lua-js.references")], table);
+    t.true(state.getGlobal("v") instanceof Vector);
+}
+userDataHiddenReferences.title = (version) => `${version}: It keeps JS references out of reach of user code`;
+
+export const userDataSuite: OneOrMoreMacros<[], Context> = [
+    userDataConstruct,
+    userDataMetamethods,
+    userDataToLua,
+    userDataErrors,
+    userDataNonStringKeys,
+    userDataHiddenReferences
+];
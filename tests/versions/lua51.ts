@@ -4,12 +4,35 @@ import { toLuaSuite } from "../suites/to-lua";
 import { createLuaState, Lua51 } from "../../lib";
 import { Context } from "../index";
 import { fromLuaSuite } from "../suites/from-lua";
+import { userDataSuite } from "../suites/user-data";
+import { conversionHooksSuite } from "../suites/conversion-hooks";
+import { numbersSuite } from "../suites/numbers";
+import { errorsSuite } from "../suites/errors";
+import { propertyOptionsSuite } from "../suites/property-options";
+import { sandboxSuite } from "../suites/sandbox";
+import { safeModeSuite } from "../suites/safe-mode";
+import { filesystemSuite } from "../suites/filesystem";
+import { hooksSuite } from "../suites/hooks";
+import { debuggerSuite } from "../suites/debugger";
+import { profilerSuite } from "../suites/profiler";
+import { coverageSuite } from "../suites/coverage";
+import { gcSuite } from "../suites/gc";
+import { poolSuite } from "../suites/pool";
+import { snapshotSuite } from "../suites/snapshot";
+import { resetSuite } from "../suites/reset";
+import { closeSuite } from "../suites/close";
+import { eventsSuite } from "../suites/events";
+import { callbacksSuite } from "../suites/callbacks";
+import { timersSuite } from "../suites/timers";
+import { jsonSuite } from "../suites/json";
+import { packedSuite } from "../suites/packed";
 
 const test = anyTest as TestInterface<Context>;
 
 beforeEach(t => {
     t.context = {
-        lua: createLuaState({ version: Lua51.Version })
+        lua: createLuaState({ version: Lua51.Version }),
+        version: Lua51.Version
     }
 });
 
@@ -21,4 +44,26 @@ afterEach(t => {
  * Test suites
  */
 test("lua51", toLuaSuite);
-test("lua51", fromLuaSuite);
\ No newline at end of file
+test("lua51", fromLuaSuite);
+test("lua51", userDataSuite);
+test("lua51", conversionHooksSuite);
+test("lua51", numbersSuite);
+test("lua51", errorsSuite);
+test("lua51", propertyOptionsSuite);
+test("lua51", sandboxSuite);
+test("lua51", safeModeSuite);
+test("lua51", filesystemSuite);
+test("lua51", hooksSuite);
+test("lua51", debuggerSuite);
+test("lua51", profilerSuite);
+test("lua51", coverageSuite);
+test("lua51", gcSuite);
+test("lua51", poolSuite);
+test("lua51", snapshotSuite);
+test("lua51", resetSuite);
+test("lua51", closeSuite);
+test("lua51", eventsSuite);
+test("lua51", callbacksSuite);
+test("lua51", timersSuite);
+test("lua51", jsonSuite);
+test("lua51", packedSuite);
\ No newline at end of file
@@ -4,12 +4,35 @@ import { toLuaSuite } from "../suites/to-lua";
 import { createLuaState, Lua52 } from "../../lib";
 import { Context } from "../index";
 import { fromLuaSuite } from "../suites/from-lua";
+import { userDataSuite } from "../suites/user-data";
+import { conversionHooksSuite } from "../suites/conversion-hooks";
+import { numbersSuite } from "../suites/numbers";
+import { errorsSuite } from "../suites/errors";
+import { propertyOptionsSuite } from "../suites/property-options";
+import { sandboxSuite } from "../suites/sandbox";
+import { safeModeSuite } from "../suites/safe-mode";
+import { filesystemSuite } from "../suites/filesystem";
+import { hooksSuite } from "../suites/hooks";
+import { debuggerSuite } from "../suites/debugger";
+import { profilerSuite } from "../suites/profiler";
+import { coverageSuite } from "../suites/coverage";
+import { gcSuite } from "../suites/gc";
+import { poolSuite } from "../suites/pool";
+import { snapshotSuite } from "../suites/snapshot";
+import { resetSuite } from "../suites/reset";
+import { closeSuite } from "../suites/close";
+import { eventsSuite } from "../suites/events";
+import { callbacksSuite } from "../suites/callbacks";
+import { timersSuite } from "../suites/timers";
+import { jsonSuite } from "../suites/json";
+import { packedSuite } from "../suites/packed";
 
 const test = anyTest as TestInterface<Context>;
 
 beforeEach(t => {
     t.context = {
-        lua: createLuaState({ version: Lua52.Version })
+        lua: createLuaState({ version: Lua52.Version }),
+        version: Lua52.Version
     }
 });
 
@@ -21,4 +44,26 @@ afterEach(t => {
  * Test suites
  */
 test("lua52", toLuaSuite);
-test("lua52", fromLuaSuite);
\ No newline at end of file
+test("lua52", fromLuaSuite);
+test("lua52", userDataSuite);
+test("lua52", conversionHooksSuite);
+test("lua52", numbersSuite);
+test("lua52", errorsSuite);
+test("lua52", propertyOptionsSuite);
+test("lua52", sandboxSuite);
+test("lua52", safeModeSuite);
+test("lua52", filesystemSuite);
+test("lua52", hooksSuite);
+test("lua52", debuggerSuite);
+test("lua52", profilerSuite);
+test("lua52", coverageSuite);
+test("lua52", gcSuite);
+test("lua52", poolSuite);
+test("lua52", snapshotSuite);
+test("lua52", resetSuite);
+test("lua52", closeSuite);
+test("lua52", eventsSuite);
+test("lua52", callbacksSuite);
+test("lua52", timersSuite);
+test("lua52", jsonSuite);
+test("lua52", packedSuite);
\ No newline at end of file
@@ -4,12 +4,35 @@ import { toLuaSuite } from "../suites/to-lua";
 import { createLuaState, Lua53 } from "../../lib";
 import { Context } from "../index";
 import { fromLuaSuite } from "../suites/from-lua";
+import { userDataSuite } from "../suites/user-data";
+import { conversionHooksSuite } from "../suites/conversion-hooks";
+import { numbersSuite } from "../suites/numbers";
+import { errorsSuite } from "../suites/errors";
+import { propertyOptionsSuite } from "../suites/property-options";
+import { sandboxSuite } from "../suites/sandbox";
+import { safeModeSuite } from "../suites/safe-mode";
+import { filesystemSuite } from "../suites/filesystem";
+import { hooksSuite } from "../suites/hooks";
+import { debuggerSuite } from "../suites/debugger";
+import { profilerSuite } from "../suites/profiler";
+import { coverageSuite } from "../suites/coverage";
+import { gcSuite } from "../suites/gc";
+import { poolSuite } from "../suites/pool";
+import { snapshotSuite } from "../suites/snapshot";
+import { resetSuite } from "../suites/reset";
+import { closeSuite } from "../suites/close";
+import { eventsSuite } from "../suites/events";
+import { callbacksSuite } from "../suites/callbacks";
+import { timersSuite } from "../suites/timers";
+import { jsonSuite } from "../suites/json";
+import { packedSuite } from "../suites/packed";
 
 const test = anyTest as TestInterface<Context>;
 
 beforeEach(t => {
     t.context = {
-        lua: createLuaState({ version: Lua53.Version })
+        lua: createLuaState({ version: Lua53.Version }),
+        version: Lua53.Version
     }
 });
 
@@ -21,4 +44,26 @@ afterEach(t => {
  * Test suites
  */
 test("lua53", toLuaSuite);
-test("lua53", fromLuaSuite);
\ No newline at end of file
+test("lua53", fromLuaSuite);
+test("lua53", userDataSuite);
+test("lua53", conversionHooksSuite);
+test("lua53", numbersSuite);
+test("lua53", errorsSuite);
+test("lua53", propertyOptionsSuite);
+test("lua53", sandboxSuite);
+test("lua53", safeModeSuite);
+test("lua53", filesystemSuite);
+test("lua53", hooksSuite);
+test("lua53", debuggerSuite);
+test("lua53", profilerSuite);
+test("lua53", coverageSuite);
+test("lua53", gcSuite);
+test("lua53", poolSuite);
+test("lua53", snapshotSuite);
+test("lua53", resetSuite);
+test("lua53", closeSuite);
+test("lua53", eventsSuite);
+test("lua53", callbacksSuite);
+test("lua53", timersSuite);
+test("lua53", jsonSuite);
+test("lua53", packedSuite);
\ No newline at end of file
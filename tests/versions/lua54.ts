@@ -4,12 +4,35 @@ import { toLuaSuite } from "../suites/to-lua";
 import { createLuaState, Lua54 } from "../../lib";
 import { Context } from "../index";
 import { fromLuaSuite } from "../suites/from-lua";
+import { userDataSuite } from "../suites/user-data";
+import { conversionHooksSuite } from "../suites/conversion-hooks";
+import { numbersSuite } from "../suites/numbers";
+import { errorsSuite } from "../suites/errors";
+import { propertyOptionsSuite } from "../suites/property-options";
+import { sandboxSuite } from "../suites/sandbox";
+import { safeModeSuite } from "../suites/safe-mode";
+import { filesystemSuite } from "../suites/filesystem";
+import { hooksSuite } from "../suites/hooks";
+import { debuggerSuite } from "../suites/debugger";
+import { profilerSuite } from "../suites/profiler";
+import { coverageSuite } from "../suites/coverage";
+import { gcSuite } from "../suites/gc";
+import { poolSuite } from "../suites/pool";
+import { snapshotSuite } from "../suites/snapshot";
+import { resetSuite } from "../suites/reset";
+import { closeSuite } from "../suites/close";
+import { eventsSuite } from "../suites/events";
+import { callbacksSuite } from "../suites/callbacks";
+import { timersSuite } from "../suites/timers";
+import { jsonSuite } from "../suites/json";
+import { packedSuite } from "../suites/packed";
 
 const test = anyTest as TestInterface<Context>;
 
 beforeEach(t => {
     t.context = {
-        lua: createLuaState({ version: Lua54.Version })
+        lua: createLuaState({ version: Lua54.Version }),
+        version: Lua54.Version
     }
 });
 
@@ -21,4 +44,26 @@ afterEach(t => {
  * Test suites
  */
 test("lua54", toLuaSuite);
-test("lua54", fromLuaSuite);
\ No newline at end of file
+test("lua54", fromLuaSuite);
+test("lua54", userDataSuite);
+test("lua54", conversionHooksSuite);
+test("lua54", numbersSuite);
+test("lua54", errorsSuite);
+test("lua54", propertyOptionsSuite);
+test("lua54", sandboxSuite);
+test("lua54", safeModeSuite);
+test("lua54", filesystemSuite);
+test("lua54", hooksSuite);
+test("lua54", debuggerSuite);
+test("lua54", profilerSuite);
+test("lua54", coverageSuite);
+test("lua54", gcSuite);
+test("lua54", poolSuite);
+test("lua54", snapshotSuite);
+test("lua54", resetSuite);
+test("lua54", closeSuite);
+test("lua54", eventsSuite);
+test("lua54", callbacksSuite);
+test("lua54", timersSuite);
+test("lua54", jsonSuite);
+test("lua54", packedSuite);
\ No newline at end of file
@@ -4,12 +4,35 @@ import { toLuaSuite } from "../suites/to-lua";
 import { createLuaState, LuaJIT } from "../../lib";
 import { Context } from "../index";
 import { fromLuaSuite } from "../suites/from-lua";
+import { userDataSuite } from "../suites/user-data";
+import { conversionHooksSuite } from "../suites/conversion-hooks";
+import { numbersSuite } from "../suites/numbers";
+import { errorsSuite } from "../suites/errors";
+import { propertyOptionsSuite } from "../suites/property-options";
+import { sandboxSuite } from "../suites/sandbox";
+import { safeModeSuite } from "../suites/safe-mode";
+import { filesystemSuite } from "../suites/filesystem";
+import { hooksSuite } from "../suites/hooks";
+import { debuggerSuite } from "../suites/debugger";
+import { profilerSuite } from "../suites/profiler";
+import { coverageSuite } from "../suites/coverage";
+import { gcSuite } from "../suites/gc";
+import { poolSuite } from "../suites/pool";
+import { snapshotSuite } from "../suites/snapshot";
+import { resetSuite } from "../suites/reset";
+import { closeSuite } from "../suites/close";
+import { eventsSuite } from "../suites/events";
+import { callbacksSuite } from "../suites/callbacks";
+import { timersSuite } from "../suites/timers";
+import { jsonSuite } from "../suites/json";
+import { packedSuite } from "../suites/packed";
 
 const test = anyTest as TestInterface<Context>;
 
 beforeEach(t => {
     t.context = {
-        lua: createLuaState({ version: LuaJIT.Version })
+        lua: createLuaState({ version: LuaJIT.Version }),
+        version: LuaJIT.Version
     }
 });
 
@@ -21,4 +44,26 @@ afterEach(t => {
  * Test suites
  */
 test("luajit", toLuaSuite);
-test("luajit", fromLuaSuite);
\ No newline at end of file
+test("luajit", fromLuaSuite);
+test("luajit", userDataSuite);
+test("luajit", conversionHooksSuite);
+test("luajit", numbersSuite);
+test("luajit", errorsSuite);
+test("luajit", propertyOptionsSuite);
+test("luajit", sandboxSuite);
+test("luajit", safeModeSuite);
+test("luajit", filesystemSuite);
+test("luajit", hooksSuite);
+test("luajit", debuggerSuite);
+test("luajit", profilerSuite);
+test("luajit", coverageSuite);
+test("luajit", gcSuite);
+test("luajit", poolSuite);
+test("luajit", snapshotSuite);
+test("luajit", resetSuite);
+test("luajit", closeSuite);
+test("luajit", eventsSuite);
+test("luajit", callbacksSuite);
+test("luajit", timersSuite);
+test("luajit", jsonSuite);
+test("luajit", packedSuite);
\ No newline at end of file